@@ -0,0 +1,19 @@
+-- Add migration script here
+-- Every status a subscription can be in.
+-- The allowed transitions are enforced by `domain::SubscriptionStatus`.
+CREATE TYPE subscription_status AS ENUM (
+    'pending',
+    'confirmed',
+    'unsubscribed',
+    'bounced',
+    'complained',
+    'deleted'
+);
+ALTER TABLE subscriptions
+    ALTER COLUMN status TYPE subscription_status
+    USING (
+        CASE status
+            WHEN 'pending_confirmation' THEN 'pending'
+            ELSE status
+        END
+    )::subscription_status;
@@ -0,0 +1,14 @@
+-- Add migration script here
+-- Confirmation tokens expire and can only be used once.
+ALTER TABLE subscription_tokens
+    ADD COLUMN created_at timestamptz NOT NULL DEFAULT now(),
+    ADD COLUMN expires_at timestamptz NOT NULL DEFAULT now() + interval '24 hours',
+    ADD COLUMN consumed_at timestamptz NULL;
+-- Tokens of confirmed subscribers have been used already
+UPDATE subscription_tokens t
+    SET consumed_at = now()
+    FROM subscriptions s
+    WHERE t.subscriber_id = s.id AND s.status <> 'pending';
+ALTER TABLE subscription_tokens
+    ALTER COLUMN created_at DROP DEFAULT,
+    ALTER COLUMN expires_at DROP DEFAULT;
@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Addresses we must not send emails to anymore, as reported by the
+-- email provider.
+CREATE TYPE suppression_reason AS ENUM ('hard_bounce', 'spam_complaint');
+CREATE TABLE email_suppressions(
+    email TEXT NOT NULL,
+    reason suppression_reason NOT NULL,
+    suppressed_at timestamptz NOT NULL,
+    PRIMARY KEY(email)
+);
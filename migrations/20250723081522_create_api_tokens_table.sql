@@ -0,0 +1,16 @@
+-- Add migration script here
+-- Named, revocable credentials for scripts calling our API on behalf
+-- of a user. Only the SHA-256 of the token is stored.
+CREATE TABLE api_tokens(
+    id uuid NOT NULL,
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    scopes TEXT[] NOT NULL,
+    created_at timestamptz NOT NULL,
+    last_used_at timestamptz NULL,
+    expires_at timestamptz NULL,
+    revoked_at timestamptz NULL,
+    PRIMARY KEY(id)
+);
+CREATE INDEX api_tokens_user_id_idx ON api_tokens (user_id);
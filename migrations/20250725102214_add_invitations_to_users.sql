@@ -0,0 +1,12 @@
+-- Add migration script here
+-- Users are invited by email and set their password themselves.
+-- Until they do, they have no password and cannot log in.
+CREATE TYPE user_status AS ENUM ('invited', 'active', 'disabled');
+ALTER TABLE users
+    ADD COLUMN email TEXT NULL UNIQUE,
+    ADD COLUMN status user_status NOT NULL DEFAULT 'active',
+    ADD COLUMN created_at timestamptz NOT NULL DEFAULT now(),
+    ALTER COLUMN password_hash DROP NOT NULL;
+ALTER TABLE users
+    ALTER COLUMN status DROP DEFAULT,
+    ALTER COLUMN created_at DROP DEFAULT;
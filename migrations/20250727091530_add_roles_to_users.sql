@@ -0,0 +1,5 @@
+-- Add migration script here
+-- Every existing user used to be able to do everything: they become owners.
+CREATE TYPE user_role AS ENUM ('owner', 'editor', 'analyst', 'read_only');
+ALTER TABLE users ADD COLUMN role user_role NOT NULL DEFAULT 'owner';
+ALTER TABLE users ALTER COLUMN role DROP DEFAULT;
@@ -0,0 +1,14 @@
+-- Add migration script here
+-- Optional TOTP second factor (RFC 6238).
+-- `totp_secret` is encrypted with AES-256-GCM: nonce followed by ciphertext.
+-- `totp_last_step` is the time step of the last accepted code, so that
+-- a code cannot be replayed.
+ALTER TABLE users
+    ADD COLUMN totp_secret BYTEA NULL,
+    ADD COLUMN totp_last_step BIGINT NULL;
+CREATE TABLE totp_recovery_codes (
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    code_hash TEXT NOT NULL,
+    used_at timestamptz NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
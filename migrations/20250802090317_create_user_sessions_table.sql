@@ -0,0 +1,14 @@
+-- Add migration script here
+-- The sessions opened by logging in, so that users can see where they are
+-- logged in and end any of them. The session state itself stays in Redis:
+-- a session without its row here is logged out on its next request.
+CREATE TABLE user_sessions(
+    id uuid NOT NULL,
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    created_at timestamptz NOT NULL,
+    last_seen_at timestamptz NOT NULL,
+    ip_address TEXT NULL,
+    user_agent TEXT NULL,
+    PRIMARY KEY(id)
+);
+CREATE INDEX user_sessions_user_id_idx ON user_sessions (user_id);
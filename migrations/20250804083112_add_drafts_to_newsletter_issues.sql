@@ -0,0 +1,12 @@
+-- Add migration script here
+-- Issues can be drafted in the admin panel: they are only published, and
+-- delivered, once `published_at` is set.
+ALTER TABLE newsletter_issues ALTER COLUMN published_at DROP NOT NULL;
+-- Issues outlive the users who wrote them.
+ALTER TABLE newsletter_issues ADD COLUMN author_id uuid NULL REFERENCES users (user_id) ON DELETE SET NULL;
+-- Issues published so far were created and last updated when they were published.
+ALTER TABLE newsletter_issues ADD COLUMN created_at timestamptz NULL;
+ALTER TABLE newsletter_issues ADD COLUMN updated_at timestamptz NULL;
+UPDATE newsletter_issues SET created_at = published_at, updated_at = published_at;
+ALTER TABLE newsletter_issues ALTER COLUMN created_at SET NOT NULL;
+ALTER TABLE newsletter_issues ALTER COLUMN updated_at SET NOT NULL;
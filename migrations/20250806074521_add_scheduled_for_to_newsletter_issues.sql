@@ -0,0 +1,5 @@
+-- Add migration script here
+-- Drafts can be scheduled: the scheduler publishes them once they are due.
+ALTER TABLE newsletter_issues ADD COLUMN scheduled_for timestamptz NULL;
+CREATE INDEX newsletter_issues_due_idx ON newsletter_issues (scheduled_for)
+    WHERE published_at IS NULL AND scheduled_for IS NOT NULL;
@@ -0,0 +1,37 @@
+-- Add migration script here
+-- The outcome of every delivery of every published issue. Unlike
+-- `issue_delivery_queue`, rows are kept once the delivery is settled:
+-- they back the delivery reports.
+CREATE TYPE delivery_status AS ENUM ('queued', 'sent', 'failed', 'bounced');
+CREATE TABLE issue_deliveries(
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    status delivery_status NOT NULL,
+    last_error TEXT NULL,
+    queued_at timestamptz NOT NULL,
+    -- When the email was sent, or given up on.
+    settled_at timestamptz NULL,
+    PRIMARY KEY(newsletter_issue_id, subscriber_email)
+);
+-- Bounces are reported by address.
+CREATE INDEX issue_deliveries_subscriber_email_idx ON issue_deliveries (subscriber_email);
+
+-- The deliveries that are still around. The ones that went through
+-- before this migration left no trace.
+INSERT INTO issue_deliveries (newsletter_issue_id, subscriber_email, status, queued_at)
+SELECT newsletter_issue_id, subscriber_email, 'queued', now()
+FROM issue_delivery_queue;
+INSERT INTO issue_deliveries (
+    newsletter_issue_id, subscriber_email, status, last_error, queued_at, settled_at
+)
+SELECT newsletter_issue_id, subscriber_email, 'failed', last_error, failed_at, failed_at
+FROM issue_delivery_dead_letters
+ON CONFLICT DO NOTHING;
+
+-- Who published the issue, or scheduled it, which may not be its author.
+ALTER TABLE newsletter_issues
+    ADD COLUMN published_by uuid NULL REFERENCES users (user_id) ON DELETE SET NULL;
+UPDATE newsletter_issues
+SET published_by = author_id
+WHERE published_at IS NOT NULL OR scheduled_for IS NOT NULL;
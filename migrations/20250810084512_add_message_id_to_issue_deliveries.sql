@@ -0,0 +1,6 @@
+-- Add migration script here
+-- The id the email provider gave the message: bounces are reported by
+-- message, and an address may bounce an old issue after a newer one went
+-- through.
+ALTER TABLE issue_deliveries ADD COLUMN message_id TEXT NULL;
+CREATE INDEX issue_deliveries_message_id_idx ON issue_deliveries (message_id);
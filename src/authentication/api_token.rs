@@ -0,0 +1,445 @@
+//! src/authentication/api_token.rs
+use crate::authentication::{
+    AuthError, Credentials, LoginThrottle, Role, UserStatus, get_active_user,
+    is_two_factor_enabled, validate_credentials,
+};
+use crate::configurations::{ApiSettings, PasswordHashingSettings};
+use crate::routes::error_chain_fmt;
+use actix_web::dev::Payload;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::{FromRequest, HttpRequest, HttpResponse, ResponseError, web};
+use anyhow::Context;
+use base64::{Engine as _, engine::general_purpose};
+use chrono::{DateTime, Utc};
+use rand::{Rng, distributions::Alphanumeric, thread_rng};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr};
+use std::pin::Pin;
+use uuid::Uuid;
+
+/// Makes our tokens easy to spot, e.g. by secret scanners.
+const TOKEN_PREFIX: &str = "z2p_";
+
+/// What an API token allows its bearer to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiScope {
+    PublishNewsletters,
+    ViewReports,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 2] = [ApiScope::PublishNewsletters, ApiScope::ViewReports];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::PublishNewsletters => "newsletters:publish",
+            ApiScope::ViewReports => "reports:read",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        Self::ALL
+            .into_iter()
+            .find(|scope| scope.as_str() == s)
+            .ok_or_else(|| format!("`{}` is not a known scope.", s))
+    }
+}
+
+impl std::fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An API token as listed to its owner - the token itself is only shown
+/// once, when it is created.
+pub struct ApiToken {
+    pub id: Uuid,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    pub fn is_active(&self) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|e| e > Utc::now())
+    }
+}
+
+fn generate_token() -> String {
+    let mut rng = thread_rng();
+    let secret: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(40)
+        .collect();
+    format!("{}{}", TOKEN_PREFIX, secret)
+}
+
+/// Tokens are long random strings, not passwords: a fast hash is enough
+/// and it lets us look them up by hash.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Create a new token for `user_id` and return it.
+/// It cannot be retrieved afterwards.
+#[tracing::instrument(skip(pool))]
+pub async fn create_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    name: &str,
+    scopes: &[ApiScope],
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<SecretString, anyhow::Error> {
+    let token = generate_token();
+    let scopes: Vec<String> = scopes.iter().map(|s| s.as_str().to_owned()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, now(), $6)
+        "#,
+        Uuid::new_v4(),
+        user_id,
+        name,
+        hash_token(&token),
+        &scopes,
+        expires_at
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a new API token.")?;
+    Ok(SecretString::new(token.into_boxed_str()))
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_api_tokens(pool: &PgPool, user_id: Uuid) -> Result<Vec<ApiToken>, anyhow::Error> {
+    let tokens = sqlx::query_as!(
+        ApiToken,
+        r#"
+        SELECT id, name, scopes, created_at, last_used_at, expires_at, revoked_at
+        FROM api_tokens
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve API tokens.")?;
+    Ok(tokens)
+}
+
+/// Revoke one of the tokens of `user_id`.
+/// Returns `false` if there was no such token, or if it was already revoked.
+#[tracing::instrument(skip(pool))]
+pub async fn revoke_api_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    token_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let n_revoked = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked_at = now()
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+        token_id,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke an API token.")?
+    .rows_affected();
+    Ok(n_revoked > 0)
+}
+
+/// Look up a token that is neither revoked nor expired, and whose owner is
+/// active, recording that it has just been used.
+#[tracing::instrument(name = "Validate API token", skip(token, pool))]
+async fn validate_api_token(pool: &PgPool, token: &SecretString) -> Result<ApiUser, AuthError> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET last_used_at = now()
+        FROM users
+        WHERE
+            api_tokens.token_hash = $1 AND
+            api_tokens.revoked_at IS NULL AND
+            (api_tokens.expires_at IS NULL OR api_tokens.expires_at > now()) AND
+            users.user_id = api_tokens.user_id AND
+            users.status = $2
+        RETURNING api_tokens.id, api_tokens.user_id, api_tokens.scopes, users.role AS "role: Role"
+        "#,
+        hash_token(token.expose_secret()),
+        UserStatus::Active as UserStatus
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to perform a query to validate an API token.")?
+    .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Unknown API token.")))?;
+    Ok(ApiUser {
+        user_id: row.user_id,
+        token_id: Some(row.id),
+        role: row.role,
+        scopes: row
+            .scopes
+            .iter()
+            .filter_map(|s| ApiScope::parse(s).ok())
+            .collect(),
+    })
+}
+
+#[derive(thiserror::Error)]
+pub enum ApiAuthError {
+    #[error("Authentication failed.")]
+    InvalidCredentials {
+        #[source]
+        source: anyhow::Error,
+        allow_basic_auth: bool,
+    },
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ApiAuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for ApiAuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiAuthError::InvalidCredentials { .. } => StatusCode::UNAUTHORIZED,
+            ApiAuthError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
+        let mut response = HttpResponse::new(self.status_code());
+        if let ApiAuthError::InvalidCredentials {
+            allow_basic_auth, ..
+        } = self
+        {
+            let headers = response.headers_mut();
+            if *allow_basic_auth {
+                headers.append(
+                    header::WWW_AUTHENTICATE,
+                    HeaderValue::from_static(r#"Basic realm="publish""#),
+                );
+            }
+            headers.append(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static(r#"Bearer realm="publish""#),
+            );
+        }
+        response
+    }
+}
+
+/// The user on whose behalf an API request is made.
+///
+/// Extracted from an `Authorization: Bearer <token>` header or, if
+/// `api.allow_basic_auth` is set, from the username and password of the
+/// user in an `Authorization: Basic` header - which grants every scope, and
+/// is refused to users with two-factor authentication enabled.
+#[derive(Debug)]
+pub struct ApiUser {
+    pub user_id: Uuid,
+    /// `None` when authenticated with a username and password.
+    pub token_id: Option<Uuid>,
+    /// The role of the user the credentials belong to.
+    pub role: Role,
+    scopes: Vec<ApiScope>,
+}
+
+impl ApiUser {
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        self.scopes.contains(&scope)
+    }
+}
+
+impl FromRequest for ApiUser {
+    type Error = ApiAuthError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let pool = req
+                .app_data::<web::Data<PgPool>>()
+                .context("The connection pool is not registered as application data.")?;
+            let settings = req
+                .app_data::<web::Data<ApiSettings>>()
+                .context("The API settings are not registered as application data.")?;
+            let hashing = req
+                .app_data::<web::Data<PasswordHashingSettings>>()
+                .context("The password hashing settings are not registered as application data.")?;
+            let throttle = req
+                .app_data::<web::Data<LoginThrottle>>()
+                .context("The login throttle is not registered as application data.")?;
+            let basic_auth = settings.allow_basic_auth.then(|| BasicAuth {
+                hashing,
+                throttle,
+                // The peer address is only missing for requests built by hand.
+                ip: throttle
+                    .client_ip(&req)
+                    .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            });
+            authenticate(req.headers(), pool, basic_auth)
+                .await
+                .map_err(|e| match e {
+                    AuthError::InvalidCredentials(source) => ApiAuthError::InvalidCredentials {
+                        source,
+                        allow_basic_auth: settings.allow_basic_auth,
+                    },
+                    AuthError::UnexpectedError(e) => ApiAuthError::UnexpectedError(e),
+                })
+        })
+    }
+}
+
+/// What it takes to check a username and password, when they are allowed.
+struct BasicAuth<'a> {
+    hashing: &'a PasswordHashingSettings,
+    throttle: &'a LoginThrottle,
+    ip: IpAddr,
+}
+
+async fn authenticate(
+    headers: &HeaderMap,
+    pool: &PgPool,
+    basic_auth: Option<BasicAuth<'_>>,
+) -> Result<ApiUser, AuthError> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing")
+        .and_then(|h| {
+            h.to_str()
+                .context("The 'Authorization' header was not a valid UTF8 string.")
+        })
+        .map_err(AuthError::InvalidCredentials)?;
+    if let Some(token) = header_value.strip_prefix("Bearer ") {
+        let token = SecretString::new(token.trim().into());
+        return validate_api_token(pool, &token).await;
+    }
+    let Some(BasicAuth {
+        hashing,
+        throttle,
+        ip,
+    }) = basic_auth
+    else {
+        return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+            "The authorization scheme was not 'Bearer'."
+        )));
+    };
+    let credentials = basic_authentication(headers).map_err(AuthError::InvalidCredentials)?;
+    let username = credentials.username.clone();
+    // Guessing passwords is throttled and locked out as on `/login`.
+    if let Some(lockout) = throttle.check(&username, ip).await? {
+        return Err(AuthError::InvalidCredentials(anyhow::anyhow!(
+            "Too many failed logins: locked out for {} more second(s).",
+            lockout.remaining().as_secs()
+        )));
+    }
+    let outcome = match validate_credentials(credentials, hashing, pool).await {
+        // A password alone must not bypass the second factor: the refusal
+        // counts as a failed login, like a wrong password.
+        Ok(user_id) if is_two_factor_enabled(pool, user_id).await? => Err(anyhow::anyhow!(
+            "The user has two-factor authentication enabled: an API token is required."
+        )),
+        Ok(user_id) => Ok(user_id),
+        Err(AuthError::InvalidCredentials(e)) => Err(e),
+        Err(e) => return Err(e),
+    };
+    let user_id = match outcome {
+        Ok(user_id) => user_id,
+        Err(e) => {
+            let failure = throttle.record_failure(&username, ip).await?;
+            tokio::time::sleep(failure.delay).await;
+            return Err(AuthError::InvalidCredentials(e));
+        }
+    };
+    throttle.record_success(&username).await?;
+    let role = get_active_user(pool, user_id)
+        .await?
+        .ok_or_else(|| AuthError::InvalidCredentials(anyhow::anyhow!("Inactive user.")))?
+        .role;
+    Ok(ApiUser {
+        user_id,
+        token_id: None,
+        role,
+        scopes: ApiScope::ALL.to_vec(),
+    })
+}
+
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string.")?;
+
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+
+    let decoded_bytes = general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials.")?;
+
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+
+    // Split into two segments, using ':' as delimitator
+    let mut credentials = decoded_credentials.splitn(2, ':');
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
+        .to_string();
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
+        .to_string();
+
+    Ok(Credentials {
+        username,
+        password: SecretString::new(password.into_boxed_str()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiScope, TOKEN_PREFIX, generate_token, hash_token};
+
+    #[test]
+    fn tokens_are_prefixed_and_random() {
+        let token = generate_token();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        assert_eq!(token.len(), TOKEN_PREFIX.len() + 40);
+        assert_ne!(token, generate_token());
+    }
+
+    #[test]
+    fn the_hash_does_not_contain_the_token() {
+        let token = generate_token();
+        let hash = hash_token(&token);
+        assert_eq!(hash, hash_token(&token));
+        assert!(!hash.contains(&token[TOKEN_PREFIX.len()..]));
+    }
+
+    #[test]
+    fn scopes_round_trip_through_their_string_representation() {
+        for scope in ApiScope::ALL {
+            assert_eq!(ApiScope::parse(scope.as_str()), Ok(scope));
+        }
+        assert!(ApiScope::parse("newsletters:delete").is_err());
+    }
+}
@@ -0,0 +1,129 @@
+//! src/authentication/invitation.rs
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+use uuid::Uuid;
+
+type HmacSha256 = Hmac<sha2::Sha256>;
+
+/// A token identifying an invited user in the link they set their password
+/// with.
+///
+/// `<payload>.<tag>`, where `payload` is the base64-encoded user id and
+/// expiry, and `tag` an HMAC of the payload keyed with the application
+/// secret. It can only be used once: it is only accepted while the user
+/// has not set a password.
+pub struct InvitationToken(String);
+
+impl InvitationToken {
+    pub fn new(user_id: Uuid, expires_at: DateTime<Utc>, secret: &SecretString) -> Self {
+        let payload = format!("{}.{}", user_id, expires_at.timestamp());
+        let tag = hex::encode(mac(&payload, secret).finalize().into_bytes());
+        Self(format!("{}.{}", URL_SAFE_NO_PAD.encode(payload), tag))
+    }
+
+    /// Check the token signature and expiry, returning the user it was
+    /// issued for.
+    pub fn verify(token: &str, secret: &SecretString) -> Result<Uuid, String> {
+        let malformed = || "The invitation link is malformed.".to_string();
+        let (payload, tag) = token.split_once('.').ok_or_else(malformed)?;
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload)
+            .ok()
+            .and_then(|p| String::from_utf8(p).ok())
+            .ok_or_else(malformed)?;
+        let tag = hex::decode(tag).map_err(|_| malformed())?;
+        mac(&payload, secret)
+            .verify_slice(&tag)
+            .map_err(|_| "The invitation link is invalid.".to_string())?;
+        let (user_id, expires_at) = payload.split_once('.').ok_or_else(malformed)?;
+        let expires_at = expires_at
+            .parse::<i64>()
+            .ok()
+            .and_then(|t| DateTime::from_timestamp(t, 0))
+            .ok_or_else(malformed)?;
+        if expires_at <= Utc::now() {
+            return Err("The invitation link has expired.".into());
+        }
+        Uuid::parse_str(user_id).map_err(|_| malformed())
+    }
+}
+
+// Prefixed, so that a tag issued for another purpose - e.g. an unsubscribe
+// link - is never valid for an invitation.
+fn mac(payload: &str, secret: &SecretString) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(b"invitation.");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+impl AsRef<str> for InvitationToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InvitationToken;
+    use chrono::{Duration, Utc};
+    use claim::{assert_err, assert_ok_eq};
+    use secrecy::SecretString;
+    use uuid::Uuid;
+
+    fn secret(s: &str) -> SecretString {
+        SecretString::new(s.into())
+    }
+
+    #[test]
+    fn a_token_is_verified_with_the_secret_it_was_issued_with() {
+        let user_id = Uuid::new_v4();
+        let token = InvitationToken::new(
+            user_id,
+            Utc::now() + Duration::hours(1),
+            &secret("a-secret"),
+        );
+        assert_ok_eq!(
+            InvitationToken::verify(token.as_ref(), &secret("a-secret")),
+            user_id
+        );
+        assert_err!(InvitationToken::verify(
+            token.as_ref(),
+            &secret("another-secret")
+        ));
+    }
+
+    #[test]
+    fn an_expired_token_is_rejected() {
+        let token = InvitationToken::new(
+            Uuid::new_v4(),
+            Utc::now() - Duration::seconds(1),
+            &secret("a-secret"),
+        );
+        assert_eq!(
+            InvitationToken::verify(token.as_ref(), &secret("a-secret")),
+            Err("The invitation link has expired.".to_string())
+        );
+    }
+
+    #[test]
+    fn a_token_with_a_tampered_expiry_is_rejected() {
+        let token = InvitationToken::new(
+            Uuid::new_v4(),
+            Utc::now() - Duration::seconds(1),
+            &secret("a-secret"),
+        );
+        let (_, tag) = token.as_ref().split_once('.').unwrap();
+        let forged_payload = base64::Engine::encode(
+            &base64::engine::general_purpose::URL_SAFE_NO_PAD,
+            format!("{}.{}", Uuid::new_v4(), i64::MAX / 1000),
+        );
+        assert_err!(InvitationToken::verify(
+            &format!("{}.{}", forged_payload, tag),
+            &secret("a-secret")
+        ));
+    }
+}
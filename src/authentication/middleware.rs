@@ -1,4 +1,5 @@
 //! src/authentication/middleware.rs
+use crate::authentication::{get_active_user, touch_user_session};
 use crate::session_state::TypedSession;
 use crate::utils::{e500, see_other};
 use actix_web::FromRequest;
@@ -6,7 +7,8 @@ use actix_web::HttpMessage;
 use actix_web::body::MessageBody;
 use actix_web::dev::{ServiceRequest, ServiceResponse};
 use actix_web::error::InternalError;
-use actix_web::middleware;
+use actix_web::{middleware, web};
+use sqlx::PgPool;
 use std::ops::Deref;
 use uuid::Uuid;
 
@@ -34,14 +36,39 @@ pub async fn reject_anonymous_users(
         TypedSession::from_request(http_request, payload).await
     }?;
 
-    match session.get_user_id().map_err(e500)? {
-        Some(user_id) => {
+    let pool = req
+        .app_data::<web::Data<PgPool>>()
+        .ok_or_else(|| e500("The connection pool is not registered as application data."))?
+        .clone();
+    let Some(user_id) = session.get_user_id().map_err(e500)? else {
+        let response = see_other("/login");
+        let e = anyhow::anyhow!("The user has not logged in");
+        return Err(InternalError::from_response(e, response).into());
+    };
+    let session_epoch = session.get_session_epoch().map_err(e500)?;
+    let user = get_active_user(&pool, user_id)
+        .await
+        .map_err(e500)?
+        .filter(|user| Some(user.session_epoch) == session_epoch);
+    let session_is_tracked = match (&user, session.get_session_id().map_err(e500)?) {
+        (Some(_), Some(session_id)) => touch_user_session(&pool, user_id, session_id)
+            .await
+            .map_err(e500)?,
+        _ => false,
+    };
+    match user {
+        Some(user) if session_is_tracked => {
             req.extensions_mut().insert(UserId(user_id));
+            req.extensions_mut().insert(user.role);
             next.call(req).await
         }
-        None => {
+        // Disabled or deleted users are logged out on their next request,
+        // as well as those whose sessions were ended, e.g. by a password
+        // reset or from the sessions page of another one.
+        _ => {
+            session.log_out();
             let response = see_other("/login");
-            let e = anyhow::anyhow!("The user has not logged in");
+            let e = anyhow::anyhow!("The user is not active anymore");
             Err(InternalError::from_response(e, response).into())
         }
     }
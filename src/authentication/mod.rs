@@ -1,6 +1,40 @@
 //! src/authentication/mod.rs
+mod api_token;
+mod invitation;
 mod middleware;
 mod password;
+mod password_policy;
+mod password_reset;
+mod roles;
+mod sessions;
+mod throttling;
+mod two_factor;
+mod users;
+pub use api_token::{
+    ApiAuthError, ApiScope, ApiToken, ApiUser, basic_authentication, create_api_token,
+    get_api_tokens, revoke_api_token,
+};
+pub use invitation::InvitationToken;
 pub use middleware::UserId;
 pub use middleware::reject_anonymous_users;
-pub use password::{AuthError, Credentials, change_password, validate_credentials};
+pub use password::{
+    AuthError, Credentials, activate_invited_user, change_password, compute_fallback_password_hash,
+    validate_credentials,
+};
+pub use password_policy::{PasswordPolicy, PasswordPolicyError};
+pub use password_reset::{
+    PasswordResetRequest, get_password_reset_username, request_password_reset, reset_password,
+};
+pub use roles::{Permission, Role};
+pub use sessions::{
+    UserSession, end_user_session, get_user_sessions, revoke_other_user_sessions,
+    revoke_user_session, start_user_session, touch_user_session,
+};
+pub use throttling::{FailedLogin, Lockout, LoginThrottle};
+pub use two_factor::{
+    TotpSecret, disable_two_factor, enable_two_factor, is_two_factor_enabled, verify_second_factor,
+};
+pub use users::{
+    ActiveUser, User, UserManagementError, UserStatus, change_role, delete_user, disable_user,
+    enable_user, get_active_user, get_invited_user, get_users, insert_invited_user,
+};
@@ -1,5 +1,7 @@
 //! src/authentication.rs
 
+use crate::authentication::UserStatus;
+use crate::configurations::PasswordHashingSettings;
 use crate::telemetry::spawn_blocking_with_tracing_tokio;
 use anyhow::Context;
 use argon2::password_hash::SaltString;
@@ -21,41 +23,112 @@ pub struct Credentials {
     pub password: SecretString,
 }
 
-#[tracing::instrument(name = "Validate credentials", skip(credentials, pool))]
+#[tracing::instrument(name = "Validate credentials", skip(credentials, hashing, pool))]
 pub async fn validate_credentials(
     credentials: Credentials,
+    hashing: &PasswordHashingSettings,
     pool: &PgPool,
 ) -> Result<uuid::Uuid, AuthError> {
     let mut user_id = None;
-    let mut expected_password_hash = SecretString::new(
-        "$argon2id$v=19$m=15000,t=2,p=1$\
-        gZiV/M1gPc22ElAH/Jh1Hw$\
-        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
-            .to_string()
-            .into_boxed_str(),
-    );
+    let mut expected_password_hash = hashing
+        .fallback_password_hash
+        .clone()
+        .context("The fallback password hash has not been computed.")?;
 
     if let Some((stored_user_id, stored_password_hash)) =
-        get_stored_credentials(&credentials.username, &pool).await?
+        get_stored_credentials(&credentials.username, pool).await?
     {
         user_id = Some(stored_user_id);
         expected_password_hash = stored_password_hash;
     }
 
-    let _ = spawn_blocking_with_tracing_tokio(move || {
+    let stored_password_hash = expected_password_hash.clone();
+    let password = credentials.password.clone();
+    spawn_blocking_with_tracing_tokio(move || {
         verify_password_hash(expected_password_hash, credentials.password)
     })
     .await
-    .context("Failed to spawn blocking task.")?;
+    .context("Failed to spawn blocking task.")??;
 
     // This is only set to `Some` if we found credentials in the store
     // So, even if the default password ends up matching (somehow)
     // with the provided password,
     // we never authenticate a non-existing user.
     // You can easily add a unit test for that precise scenario.
-    user_id
+    let user_id = user_id
         .ok_or_else(|| anyhow::anyhow!("Unknown username."))
-        .map_err(AuthError::InvalidCredentials)
+        .map_err(AuthError::InvalidCredentials)?;
+
+    // The password is right: an outdated hash is not a reason to refuse it.
+    if let Err(e) =
+        upgrade_password_hash(user_id, stored_password_hash, password, hashing, pool).await
+    {
+        tracing::warn!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to upgrade a password hash",
+        );
+    }
+    Ok(user_id)
+}
+
+/// Re-hash the password if its stored hash was computed with other
+/// parameters than the configured ones - e.g. before their cost was raised.
+#[tracing::instrument(
+    name = "Upgrade password hash",
+    skip(stored_password_hash, password, hashing, pool)
+)]
+async fn upgrade_password_hash(
+    user_id: uuid::Uuid,
+    stored_password_hash: SecretString,
+    password: SecretString,
+    hashing: &PasswordHashingSettings,
+    pool: &PgPool,
+) -> Result<(), anyhow::Error> {
+    let params = hashing.params()?;
+    {
+        let stored = PasswordHash::new(stored_password_hash.expose_secret())
+            .map_err(|e| anyhow::anyhow!("Failed to parse hash in PHC string format: {}", e))?;
+        if !needs_rehash(&stored, &params) {
+            return Ok(());
+        }
+    }
+    let password_hash =
+        spawn_blocking_with_tracing_tokio(move || compute_password_hash(password, params))
+            .await?
+            .context("Failed to hash password")?;
+    // Leave the hash alone if the password changed in the meantime.
+    sqlx::query!(
+        r#"
+UPDATE users
+SET password_hash = $1
+WHERE user_id = $2 AND password_hash = $3
+"#,
+        password_hash.expose_secret(),
+        user_id,
+        stored_password_hash.expose_secret()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to upgrade user's password hash in the database.")?;
+    tracing::info!("Upgraded the password hash to the configured parameters");
+    Ok(())
+}
+
+/// Whether `hash` is not an Argon2id hash with the `policy` parameters.
+fn needs_rehash(hash: &PasswordHash, policy: &Params) -> bool {
+    if hash.algorithm != Algorithm::Argon2id.ident() || hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    match Params::try_from(hash) {
+        Ok(params) => {
+            params.m_cost() != policy.m_cost()
+                || params.t_cost() != policy.t_cost()
+                || params.p_cost() != policy.p_cost()
+        }
+        Err(_) => true,
+    }
 }
 
 // We extracted the db-querying logic in its own function with its own span.
@@ -66,11 +139,15 @@ async fn get_stored_credentials(
 ) -> Result<Option<(uuid::Uuid, SecretString)>, anyhow::Error> {
     let row = sqlx::query!(
         r#"
-        SELECT user_id, password_hash
+        SELECT user_id, password_hash AS "password_hash!"
         FROM users
-        WHERE username = $1
+        WHERE
+            username = $1 AND
+            status = $2 AND
+            password_hash IS NOT NULL
         "#,
         username,
+        UserStatus::Active as UserStatus
     )
     .fetch_optional(pool)
     .await
@@ -105,15 +182,18 @@ fn verify_password_hash(
         .map_err(AuthError::InvalidCredentials)
 }
 
-#[tracing::instrument(name = "Change password", skip(password, pool))]
+#[tracing::instrument(name = "Change password", skip(password, hashing, pool))]
 pub async fn change_password(
     user_id: uuid::Uuid,
     password: SecretString,
+    hashing: &PasswordHashingSettings,
     pool: &PgPool,
 ) -> Result<(), anyhow::Error> {
-    let password_hash = spawn_blocking_with_tracing_tokio(move || compute_password_hash(password))
-        .await?
-        .context("Failed to hash password")?;
+    let params = hashing.params()?;
+    let password_hash =
+        spawn_blocking_with_tracing_tokio(move || compute_password_hash(password, params))
+            .await?
+            .context("Failed to hash password")?;
     sqlx::query!(
         r#"
 UPDATE users
@@ -129,17 +209,114 @@ WHERE user_id = $2
     Ok(())
 }
 
-fn compute_password_hash(password: SecretString) -> Result<SecretString, anyhow::Error> {
-    let salt = SaltString::generate(&mut rand::thread_rng());
-    let password_hash = Argon2::new(
-        Algorithm::Argon2id,
-        Version::V0x13,
-        Params::new(15000, 2, 1, None).unwrap(),
+/// Set the password of an invited user, who can log in from then on.
+/// Returns `false` if the user is not waiting for a password anymore.
+#[tracing::instrument(name = "Activate invited user", skip(password, hashing, pool))]
+pub async fn activate_invited_user(
+    user_id: uuid::Uuid,
+    password: SecretString,
+    hashing: &PasswordHashingSettings,
+    pool: &PgPool,
+) -> Result<bool, anyhow::Error> {
+    let params = hashing.params()?;
+    let password_hash =
+        spawn_blocking_with_tracing_tokio(move || compute_password_hash(password, params))
+            .await?
+            .context("Failed to hash password")?;
+    let n_activated = sqlx::query!(
+        r#"
+UPDATE users
+SET password_hash = $1, status = $3
+WHERE user_id = $2 AND status = $4
+"#,
+        password_hash.expose_secret(),
+        user_id,
+        UserStatus::Active as UserStatus,
+        UserStatus::Invited as UserStatus
     )
-    .hash_password(password.expose_secret().as_bytes(), &salt)
-    .map_err(|e| anyhow::anyhow!(e))
-    .context("Failed to hash password")?
-    .to_string();
+    .execute(pool)
+    .await
+    .context("Failed to activate an invited user in the database.")?
+    .rows_affected();
+    Ok(n_activated > 0)
+}
+
+/// A hash of a random password, with the configured parameters: verifying
+/// a password against it costs as much as against the hash of a real user.
+pub fn compute_fallback_password_hash(
+    hashing: &PasswordHashingSettings,
+) -> Result<SecretString, anyhow::Error> {
+    let password = SecretString::from(uuid::Uuid::new_v4().to_string());
+    compute_password_hash(password, hashing.params()?)
+}
+
+pub(super) fn compute_password_hash(
+    password: SecretString,
+    params: Params,
+) -> Result<SecretString, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.expose_secret().as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!(e))
+        .context("Failed to hash password")?
+        .to_string();
 
     Ok(SecretString::new(password_hash.into_boxed_str()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{compute_fallback_password_hash, compute_password_hash, needs_rehash};
+    use crate::configurations::PasswordHashingSettings;
+    use argon2::password_hash::SaltString;
+    use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, Version};
+    use secrecy::{ExposeSecret, SecretString};
+
+    fn hash_with(params: Params) -> SecretString {
+        compute_password_hash(SecretString::from("a-password"), params).unwrap()
+    }
+
+    #[test]
+    fn a_hash_with_the_configured_parameters_is_kept() {
+        let policy = Params::new(8, 1, 1, None).unwrap();
+        let hash = hash_with(policy.clone());
+        let hash = PasswordHash::new(hash.expose_secret()).unwrap();
+        assert!(!needs_rehash(&hash, &policy));
+    }
+
+    #[test]
+    fn a_hash_with_other_parameters_is_upgraded() {
+        let hash = hash_with(Params::new(16, 1, 1, None).unwrap());
+        let hash = PasswordHash::new(hash.expose_secret()).unwrap();
+        for policy in [
+            Params::new(32, 1, 1, None).unwrap(),
+            Params::new(16, 2, 1, None).unwrap(),
+            Params::new(16, 1, 2, None).unwrap(),
+        ] {
+            assert!(needs_rehash(&hash, &policy));
+        }
+    }
+
+    #[test]
+    fn a_hash_of_another_argon2_variant_is_upgraded() {
+        let policy = Params::new(8, 1, 1, None).unwrap();
+        let salt = SaltString::generate(&mut rand::thread_rng());
+        let hash = Argon2::new(Algorithm::Argon2i, Version::V0x13, policy.clone())
+            .hash_password(b"a-password", &salt)
+            .unwrap();
+        assert!(needs_rehash(&hash, &policy));
+    }
+
+    #[test]
+    fn the_fallback_hash_uses_the_configured_parameters() {
+        let hashing = PasswordHashingSettings {
+            memory_kib: 32,
+            iterations: 3,
+            parallelism: 1,
+            fallback_password_hash: None,
+        };
+        let hash = compute_fallback_password_hash(&hashing).unwrap();
+        let hash = PasswordHash::new(hash.expose_secret()).unwrap();
+        assert!(!needs_rehash(&hash, &hashing.params().unwrap()));
+    }
+}
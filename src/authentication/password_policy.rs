@@ -0,0 +1,256 @@
+//! src/authentication/password_policy.rs
+use crate::configurations::PasswordPolicySettings;
+use anyhow::Context;
+use secrecy::{ExposeSecret, SecretString};
+use sha1::{Digest, Sha1};
+use std::collections::{HashMap, HashSet};
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum PasswordPolicyError {
+    #[error("The password must be at least {0} characters long.")]
+    TooShort(usize),
+    #[error("The password must be at most {0} characters long.")]
+    TooLong(usize),
+    #[error("The new password must be different from the current one.")]
+    SameAsCurrent,
+    #[error("The password must not contain your username.")]
+    ContainsUsername,
+    #[error(
+        "The password is too easy to guess: make it longer, or mix in other kinds of characters."
+    )]
+    TooPredictable,
+    #[error("This password appeared in a data breach: choose another one.")]
+    Breached,
+}
+
+/// The rules a password has to follow when a user chooses it.
+pub struct PasswordPolicy {
+    settings: PasswordPolicySettings,
+    breached_passwords: BreachedPasswords,
+}
+
+impl PasswordPolicy {
+    pub fn new(settings: PasswordPolicySettings) -> Result<Self, anyhow::Error> {
+        let breached_passwords = BreachedPasswords::load(&settings.breached_passwords_path)?;
+        Ok(Self {
+            settings,
+            breached_passwords,
+        })
+    }
+
+    /// `current_password` is the one being replaced, if any.
+    pub fn check(
+        &self,
+        password: &SecretString,
+        username: &str,
+        current_password: Option<&SecretString>,
+    ) -> Result<(), PasswordPolicyError> {
+        let password = password.expose_secret();
+        let length = password.graphemes(true).count();
+        if length < self.settings.min_length {
+            return Err(PasswordPolicyError::TooShort(self.settings.min_length));
+        }
+        if length > self.settings.max_length {
+            return Err(PasswordPolicyError::TooLong(self.settings.max_length));
+        }
+        if current_password.is_some_and(|current| current.expose_secret() == password) {
+            return Err(PasswordPolicyError::SameAsCurrent);
+        }
+        if contains_username(password, username) {
+            return Err(PasswordPolicyError::ContainsUsername);
+        }
+        if estimate_entropy_bits(password) < self.settings.min_entropy_bits {
+            return Err(PasswordPolicyError::TooPredictable);
+        }
+        if self.breached_passwords.contains(password) {
+            return Err(PasswordPolicyError::Breached);
+        }
+        Ok(())
+    }
+}
+
+/// Short usernames are only rejected as the whole password: `bob` is in too
+/// many good passwords.
+fn contains_username(password: &str, username: &str) -> bool {
+    let password = password.to_lowercase();
+    let username = username.trim().to_lowercase();
+    if username.is_empty() {
+        return false;
+    }
+    if username.graphemes(true).count() < 4 {
+        password == username
+    } else {
+        password.contains(&username)
+    }
+}
+
+/// A rough, offline, estimate of the entropy of a password: every character
+/// is worth the size of the character classes in use, except when it
+/// repeats or continues a sequence (`aaa`, `abc`, `321`).
+fn estimate_entropy_bits(password: &str) -> f64 {
+    let (mut lower, mut upper, mut digit, mut symbol, mut other) =
+        (false, false, false, false, false);
+    for c in password.chars() {
+        match c {
+            'a'..='z' => lower = true,
+            'A'..='Z' => upper = true,
+            '0'..='9' => digit = true,
+            c if c.is_ascii() => symbol = true,
+            _ => other = true,
+        }
+    }
+    let pool = [
+        (lower, 26),
+        (upper, 26),
+        (digit, 10),
+        (symbol, 33),
+        (other, 100),
+    ]
+    .iter()
+    .filter(|(used, _)| *used)
+    .map(|(_, size)| size)
+    .sum::<u32>();
+    if pool == 0 {
+        return 0.0;
+    }
+    let bits_per_character = f64::from(pool).log2();
+
+    let mut bits = 0.0;
+    let mut previous: Option<char> = None;
+    for grapheme in password.graphemes(true) {
+        let c = grapheme.chars().next().unwrap();
+        let predictable = previous.is_some_and(|p| (c as u32).abs_diff(p as u32) <= 1);
+        bits += if predictable { 1.0 } else { bits_per_character };
+        previous = Some(c);
+    }
+    bits
+}
+
+/// SHA-1 hashes of breached passwords, grouped by their first 5 hex digits
+/// like the k-anonymity range API of Have I Been Pwned: the file uses its
+/// `HASH[:COUNT]` line format, so one of its dumps can replace ours.
+struct BreachedPasswords(HashMap<String, HashSet<String>>);
+
+impl BreachedPasswords {
+    fn load(path: &str) -> Result<Self, anyhow::Error> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the breached passwords from {}.", path))?;
+        Ok(Self::parse(&content))
+    }
+
+    fn parse(content: &str) -> Self {
+        let mut ranges: HashMap<String, HashSet<String>> = HashMap::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let hash = line.split(':').next().unwrap_or_default().to_uppercase();
+            if hash.len() != 40 {
+                continue;
+            }
+            let (prefix, suffix) = hash.split_at(5);
+            ranges
+                .entry(prefix.to_owned())
+                .or_default()
+                .insert(suffix.to_owned());
+        }
+        Self(ranges)
+    }
+
+    fn contains(&self, password: &str) -> bool {
+        let hash = data_encoding::HEXUPPER.encode(&Sha1::digest(password.as_bytes()));
+        let (prefix, suffix) = hash.split_at(5);
+        self.0
+            .get(prefix)
+            .is_some_and(|suffixes| suffixes.contains(suffix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BreachedPasswords, PasswordPolicy, PasswordPolicyError, contains_username,
+        estimate_entropy_bits,
+    };
+    use crate::configurations::PasswordPolicySettings;
+    use claim::assert_ok;
+    use secrecy::SecretString;
+
+    fn policy() -> PasswordPolicy {
+        PasswordPolicy {
+            settings: PasswordPolicySettings {
+                min_length: 12,
+                max_length: 128,
+                min_entropy_bits: 50.0,
+                breached_passwords_path: String::new(),
+            },
+            // SHA-1 of `correct horse battery staple`
+            breached_passwords: BreachedPasswords::parse(
+                "# A comment\nABF7AAD6438836DBE526AA231ABDE2D0EEF74D42:3\n",
+            ),
+        }
+    }
+
+    fn check(password: &str) -> Result<(), PasswordPolicyError> {
+        policy().check(&SecretString::from(password), "ursula", None)
+    }
+
+    #[test]
+    fn a_long_and_varied_password_is_accepted() {
+        assert_ok!(check("Tr0ub4dor&3-is-not-enough"));
+    }
+
+    #[test]
+    fn the_length_is_counted_in_graphemes() {
+        assert_eq!(
+            check(&"e\u{308}".repeat(11)),
+            Err(PasswordPolicyError::TooShort(12))
+        );
+        assert_eq!(
+            check(&"a1b2c3d4".repeat(17)),
+            Err(PasswordPolicyError::TooLong(128))
+        );
+    }
+
+    #[test]
+    fn the_current_password_is_rejected() {
+        let current = SecretString::from("Tr0ub4dor&3-is-not-enough");
+        assert_eq!(
+            policy().check(&current, "ursula", Some(&current)),
+            Err(PasswordPolicyError::SameAsCurrent)
+        );
+    }
+
+    #[test]
+    fn passwords_containing_the_username_are_rejected() {
+        assert_eq!(
+            check("my-name-is-URSULA-42"),
+            Err(PasswordPolicyError::ContainsUsername)
+        );
+        assert!(contains_username("bob", "Bob"));
+        assert!(!contains_username("bobsled-champion-1998", "bob"));
+    }
+
+    #[test]
+    fn repetitions_and_sequences_are_too_predictable() {
+        for password in ["aaaaaaaaaaaaaaaa", "abcdefghijklmnop", "1234567890123456"] {
+            assert_eq!(check(password), Err(PasswordPolicyError::TooPredictable));
+        }
+        assert!(estimate_entropy_bits("abcd") < estimate_entropy_bits("axqd"));
+    }
+
+    #[test]
+    fn breached_passwords_are_rejected() {
+        assert_eq!(
+            check("correct horse battery staple"),
+            Err(PasswordPolicyError::Breached)
+        );
+    }
+
+    #[test]
+    fn a_missing_breached_passwords_file_is_an_error() {
+        assert!(BreachedPasswords::load("does-not-exist.txt").is_err());
+    }
+}
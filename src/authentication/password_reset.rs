@@ -0,0 +1,200 @@
+//! src/authentication/password_reset.rs
+use crate::authentication::UserStatus;
+use crate::authentication::password::compute_password_hash;
+use crate::configurations::PasswordHashingSettings;
+use crate::telemetry::spawn_blocking_with_tracing_tokio;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::{Rng, distributions::Alphanumeric, thread_rng};
+use secrecy::{ExposeSecret, SecretString};
+use sha2::{Digest, Sha256};
+use sqlx::{Executor, PgPool};
+use uuid::Uuid;
+
+/// Who to send a password reset link to.
+pub struct PasswordResetRequest {
+    pub token: SecretString,
+    pub username: String,
+    pub email: String,
+}
+
+fn generate_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(40)
+        .collect()
+}
+
+/// Reset tokens are long random strings: like API tokens, a fast hash is
+/// enough and it lets us look them up by hash.
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Issue a reset token for `username`.
+///
+/// Returns `None` - without telling the caller why - if there is no active
+/// user with an email address by that name, or if they already asked for
+/// `max_per_hour` tokens in the last hour.
+#[tracing::instrument(skip(pool))]
+pub async fn request_password_reset(
+    pool: &PgPool,
+    username: &str,
+    expires_at: DateTime<Utc>,
+    max_per_hour: i64,
+) -> Result<Option<PasswordResetRequest>, anyhow::Error> {
+    let user = sqlx::query!(
+        r#"
+        SELECT
+            user_id,
+            email AS "email!",
+            (
+                SELECT COUNT(*)
+                FROM password_reset_tokens
+                WHERE
+                    password_reset_tokens.user_id = users.user_id AND
+                    created_at > now() - interval '1 hour'
+            ) AS "recent_requests!"
+        FROM users
+        WHERE username = $1 AND status = $2 AND email IS NOT NULL
+        "#,
+        username,
+        UserStatus::Active as UserStatus
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the user asking for a password reset.")?;
+    let Some(user) = user else {
+        return Ok(None);
+    };
+    if user.recent_requests >= max_per_hour {
+        tracing::warn!("Too many password reset requests, ignoring.");
+        return Ok(None);
+    }
+
+    let token = generate_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO password_reset_tokens (token_hash, user_id, created_at, expires_at)
+        VALUES ($1, $2, now(), $3)
+        "#,
+        hash_token(&token),
+        user.user_id,
+        expires_at
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a password reset token.")?;
+    Ok(Some(PasswordResetRequest {
+        token: SecretString::new(token.into_boxed_str()),
+        username: username.to_owned(),
+        email: user.email,
+    }))
+}
+
+/// The user whose password `token` resets, if it can still be used.
+#[tracing::instrument(skip(pool, token))]
+pub async fn get_password_reset_username(
+    pool: &PgPool,
+    token: &SecretString,
+) -> Result<Option<String>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT users.username
+        FROM password_reset_tokens
+        JOIN users ON users.user_id = password_reset_tokens.user_id
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+        "#,
+        hash_token(token.expose_secret())
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a password reset token.")?;
+    Ok(row.map(|r| r.username))
+}
+
+/// Set a new password with a reset token, which cannot be used again.
+/// All the sessions of the user end, as well as their other reset tokens.
+///
+/// Returns `false` if the token is unknown, used or expired.
+#[tracing::instrument(
+    skip(pool, token, password, hashing),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn reset_password(
+    pool: &PgPool,
+    token: &SecretString,
+    password: SecretString,
+    hashing: &PasswordHashingSettings,
+) -> Result<bool, anyhow::Error> {
+    let params = hashing.params()?;
+    let password_hash =
+        spawn_blocking_with_tracing_tokio(move || compute_password_hash(password, params))
+            .await?
+            .context("Failed to hash password")?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to begin a transaction.")?;
+    let row = sqlx::query!(
+        r#"
+        UPDATE password_reset_tokens
+        SET used_at = now()
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > now()
+        RETURNING user_id
+        "#,
+        hash_token(token.expose_secret())
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to use a password reset token.")?;
+    let Some(row) = row else {
+        return Ok(false);
+    };
+    let user_id: Uuid = row.user_id;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let n_updated = transaction
+        .execute(sqlx::query!(
+            r#"
+            UPDATE users
+            SET password_hash = $2, session_epoch = session_epoch + 1
+            WHERE user_id = $1 AND status = $3
+            "#,
+            user_id,
+            password_hash.expose_secret(),
+            UserStatus::Active as UserStatus
+        ))
+        .await
+        .context("Failed to reset the password of a user.")?
+        .rows_affected();
+    if n_updated == 0 {
+        // Disabled since the token was issued.
+        return Ok(false);
+    }
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            UPDATE password_reset_tokens
+            SET used_at = now()
+            WHERE user_id = $1 AND used_at IS NULL
+            "#,
+            user_id
+        ))
+        .await
+        .context("Failed to invalidate the other password reset tokens.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM user_sessions WHERE user_id = $1"#,
+            user_id
+        ))
+        .await
+        .context("Failed to end the sessions of a user.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reset a password.")?;
+    Ok(true)
+}
@@ -0,0 +1,137 @@
+//! src/authentication/roles.rs
+use crate::templates::render_with_status;
+use actix_web::HttpResponse;
+use actix_web::error::InternalError;
+use actix_web::http::StatusCode;
+use askama::Template;
+
+/// What a user is allowed to do in the admin panel and through the API.
+///
+/// `reject_anonymous_users` stores the role of the logged-in user in the
+/// request extensions: handlers extract it with `web::ReqData<Role>`,
+/// next to `UserId`, and call [`Role::require`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "user_role", rename_all = "snake_case")]
+pub enum Role {
+    /// Can do everything, including managing users and settings.
+    Owner,
+    /// Publishes newsletters and looks after subscribers.
+    Editor,
+    /// Looks at delivery reports.
+    Analyst,
+    /// Can only change their own password.
+    ReadOnly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    PublishNewsletters,
+    ManageSubscribers,
+    ViewReports,
+    /// Create and revoke one's own API tokens. What a token can do is
+    /// still bounded by the role of its owner.
+    ManageOwnApiTokens,
+    ManageUsers,
+    ManageSettings,
+}
+
+impl Role {
+    pub const ALL: [Role; 4] = [Role::Owner, Role::Editor, Role::Analyst, Role::ReadOnly];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Owner => "owner",
+            Role::Editor => "editor",
+            Role::Analyst => "analyst",
+            Role::ReadOnly => "read_only",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        Role::ALL
+            .into_iter()
+            .find(|r| r.as_str() == s)
+            .ok_or_else(|| format!("{} is not a valid role.", s))
+    }
+
+    pub fn can(&self, permission: Permission) -> bool {
+        use Permission::*;
+        match self {
+            Role::Owner => true,
+            Role::Editor => matches!(
+                permission,
+                PublishNewsletters | ManageSubscribers | ViewReports | ManageOwnApiTokens
+            ),
+            Role::Analyst => matches!(permission, ViewReports | ManageOwnApiTokens),
+            Role::ReadOnly => false,
+        }
+    }
+
+    /// Render a 403 page if the role does not grant `permission`.
+    pub fn require(&self, permission: Permission) -> Result<(), actix_web::Error> {
+        if self.can(permission) {
+            return Ok(());
+        }
+        let e = anyhow::anyhow!(
+            "The {} role does not grant the {:?} permission",
+            self.as_str(),
+            permission
+        );
+        Err(InternalError::from_response(e, forbidden_page()).into())
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Template)]
+#[template(path = "errors/forbidden.html")]
+struct ForbiddenPage;
+
+fn forbidden_page() -> HttpResponse {
+    render_with_status(StatusCode::FORBIDDEN, &ForbiddenPage).unwrap_or_else(|e| e.error_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Permission, Role};
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn roles_round_trip_through_their_name() {
+        for role in Role::ALL {
+            assert_eq!(Role::parse(role.as_str()), Ok(role));
+        }
+        assert_err!(Role::parse("admin"));
+    }
+
+    #[test]
+    fn only_owners_manage_users_and_settings() {
+        for role in Role::ALL {
+            let expected = role == Role::Owner;
+            assert_eq!(role.can(Permission::ManageUsers), expected);
+            assert_eq!(role.can(Permission::ManageSettings), expected);
+        }
+    }
+
+    #[test]
+    fn editors_publish_but_analysts_do_not() {
+        assert!(Role::Editor.can(Permission::PublishNewsletters));
+        assert!(Role::Editor.can(Permission::ManageSubscribers));
+        assert!(!Role::Analyst.can(Permission::PublishNewsletters));
+        assert!(!Role::Analyst.can(Permission::ManageSubscribers));
+        assert_ok!(Role::Analyst.require(Permission::ViewReports));
+        assert_err!(Role::ReadOnly.require(Permission::ViewReports));
+    }
+
+    #[test]
+    fn every_role_but_read_only_manages_its_own_api_tokens() {
+        for role in Role::ALL {
+            let expected = role != Role::ReadOnly;
+            assert_eq!(role.can(Permission::ManageOwnApiTokens), expected);
+        }
+    }
+}
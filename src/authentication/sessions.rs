@@ -0,0 +1,155 @@
+//! src/authentication/sessions.rs
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+/// Long user agents are cut: they are only shown to tell sessions apart.
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+/// A session opened by logging in, as listed to its owner.
+pub struct UserSession {
+    pub id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Record a session for `user_id`, who just logged in.
+/// Returns its id, to be stored in the session state.
+#[tracing::instrument(skip(pool, user_agent))]
+pub async fn start_user_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    ip_address: Option<IpAddr>,
+    user_agent: Option<&str>,
+) -> Result<Uuid, anyhow::Error> {
+    // Redis forgets idle sessions after a day: so do we, lazily.
+    sqlx::query!(
+        r#"
+        DELETE FROM user_sessions
+        WHERE user_id = $1 AND last_seen_at < now() - interval '1 day'
+        "#,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to delete the expired sessions of a user.")?;
+    let session_id = Uuid::new_v4();
+    let user_agent =
+        user_agent.map(|ua| ua.chars().take(MAX_USER_AGENT_LENGTH).collect::<String>());
+    sqlx::query!(
+        r#"
+        INSERT INTO user_sessions (id, user_id, created_at, last_seen_at, ip_address, user_agent)
+        VALUES ($1, $2, now(), now(), $3, $4)
+        "#,
+        session_id,
+        user_id,
+        ip_address.map(|ip| ip.to_string()),
+        user_agent
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a new session.")?;
+    Ok(session_id)
+}
+
+/// Record that a session has just been used.
+/// Returns `false` if it was ended in the meantime.
+#[tracing::instrument(skip(pool))]
+pub async fn touch_user_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    session_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE user_sessions
+        SET last_seen_at = now()
+        WHERE id = $1 AND user_id = $2
+        "#,
+        session_id,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the activity of a session.")?
+    .rows_affected();
+    Ok(n_updated > 0)
+}
+
+/// Forget a session, when its user logs out.
+#[tracing::instrument(skip(pool))]
+pub async fn end_user_session(pool: &PgPool, session_id: Uuid) -> Result<(), anyhow::Error> {
+    sqlx::query!(r#"DELETE FROM user_sessions WHERE id = $1"#, session_id)
+        .execute(pool)
+        .await
+        .context("Failed to end a session.")?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_user_sessions(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Vec<UserSession>, anyhow::Error> {
+    let sessions = sqlx::query_as!(
+        UserSession,
+        r#"
+        SELECT id, created_at, last_seen_at, ip_address, user_agent
+        FROM user_sessions
+        WHERE user_id = $1 AND last_seen_at >= now() - interval '1 day'
+        ORDER BY last_seen_at DESC
+        "#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the sessions of a user.")?;
+    Ok(sessions)
+}
+
+/// End one of the sessions of `user_id`: it is logged out on its next
+/// request. Returns `false` if there was no such session.
+#[tracing::instrument(skip(pool))]
+pub async fn revoke_user_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    session_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let n_deleted = sqlx::query!(
+        r#"DELETE FROM user_sessions WHERE id = $1 AND user_id = $2"#,
+        session_id,
+        user_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke a session.")?
+    .rows_affected();
+    Ok(n_deleted > 0)
+}
+
+/// End all the sessions of `user_id` but `current_session_id`.
+/// Returns how many were ended.
+#[tracing::instrument(skip(pool))]
+pub async fn revoke_other_user_sessions(
+    pool: &PgPool,
+    user_id: Uuid,
+    current_session_id: Option<Uuid>,
+) -> Result<u64, anyhow::Error> {
+    let n_deleted = sqlx::query!(
+        r#"
+        DELETE FROM user_sessions
+        WHERE user_id = $1 AND id IS DISTINCT FROM $2
+        "#,
+        user_id,
+        current_session_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to revoke the other sessions of a user.")?
+    .rows_affected();
+    Ok(n_deleted)
+}
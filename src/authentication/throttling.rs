@@ -0,0 +1,345 @@
+//! src/authentication/throttling.rs
+use crate::configurations::LoginThrottlingSettings;
+use actix_web::HttpRequest;
+use actix_web::http::header::X_FORWARDED_FOR;
+use anyhow::Context;
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Why a login attempt is refused before the password is even checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lockout {
+    /// Too many failures for this username.
+    Account { remaining: Duration },
+    /// Too many failures from this IP address, whatever the username.
+    Network { remaining: Duration },
+}
+
+impl Lockout {
+    pub fn remaining(&self) -> Duration {
+        match self {
+            Lockout::Account { remaining } | Lockout::Network { remaining } => *remaining,
+        }
+    }
+}
+
+/// What happens after a failed login attempt.
+#[derive(Debug)]
+pub struct FailedLogin {
+    /// How long to wait before answering.
+    pub delay: Duration,
+    /// Set when this attempt was the one too many.
+    pub lockout: Option<Lockout>,
+}
+
+/// Counts failed logins per username and per IP address in Redis, so that
+/// they are shared by all the instances of the application.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    connection: ConnectionManager,
+    settings: LoginThrottlingSettings,
+}
+
+impl LoginThrottle {
+    pub async fn new(
+        redis_uri: &str,
+        settings: LoginThrottlingSettings,
+    ) -> Result<Self, anyhow::Error> {
+        let connection = redis::Client::open(redis_uri)
+            .context("Invalid Redis URI.")?
+            .get_connection_manager()
+            .await
+            .context("Failed to connect to Redis.")?;
+        Ok(Self {
+            connection,
+            settings,
+        })
+    }
+
+    /// The address the attempt comes from.
+    pub fn client_ip(&self, request: &HttpRequest) -> Option<IpAddr> {
+        let peer_ip = request.peer_addr().map(|addr| addr.ip());
+        if !self.settings.trust_forwarded_headers {
+            return peer_ip;
+        }
+        let entries = request
+            .headers()
+            .get_all(X_FORWARDED_FOR)
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(','));
+        forwarded_client_ip(entries, self.settings.trusted_proxy_hops).or(peer_ip)
+    }
+
+    fn key(&self, suffix: std::fmt::Arguments) -> String {
+        format!("{}login:{}", self.settings.key_prefix, suffix)
+    }
+
+    fn locked_users_key(&self) -> String {
+        self.key(format_args!("locked-users"))
+    }
+
+    /// Whether the username or the IP address is currently locked out.
+    #[tracing::instrument(name = "Check login lockouts", skip(self))]
+    pub async fn check(
+        &self,
+        username: &str,
+        ip: IpAddr,
+    ) -> Result<Option<Lockout>, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let account_ttl: i64 = connection
+            .ttl(self.key(format_args!("lock:user:{username}")))
+            .await
+            .context("Failed to check the account lockout.")?;
+        if account_ttl > 0 {
+            return Ok(Some(Lockout::Account {
+                remaining: Duration::from_secs(account_ttl as u64),
+            }));
+        }
+        let network_ttl: i64 = connection
+            .ttl(self.key(format_args!("lock:ip:{ip}")))
+            .await
+            .context("Failed to check the network lockout.")?;
+        if network_ttl > 0 {
+            return Ok(Some(Lockout::Network {
+                remaining: Duration::from_secs(network_ttl as u64),
+            }));
+        }
+        Ok(None)
+    }
+
+    /// Count a failed attempt, locking the username or the IP address out
+    /// once they reach their threshold within the window.
+    #[tracing::instrument(name = "Record a failed login", skip(self))]
+    pub async fn record_failure(
+        &self,
+        username: &str,
+        ip: IpAddr,
+    ) -> Result<FailedLogin, anyhow::Error> {
+        let user_failures = self.count_failure(format_args!("user:{username}")).await?;
+        let ip_failures = self.count_failure(format_args!("ip:{ip}")).await?;
+
+        let lockout_duration = self.settings.lockout();
+        let mut lockout = None;
+        if ip_failures >= self.settings.max_failures_per_ip {
+            self.lock(format_args!("ip:{ip}")).await?;
+            tracing::warn!(%ip, "Too many failed logins: locking the IP address out");
+            lockout = Some(Lockout::Network {
+                remaining: lockout_duration,
+            });
+        }
+        if user_failures >= self.settings.max_failures_per_username {
+            self.lock(format_args!("user:{username}")).await?;
+            let _: () = self
+                .connection
+                .clone()
+                .sadd(self.locked_users_key(), username)
+                .await
+                .context("Failed to record the locked account.")?;
+            tracing::warn!("Too many failed logins: locking the account out");
+            lockout = Some(Lockout::Account {
+                remaining: lockout_duration,
+            });
+        }
+        Ok(FailedLogin {
+            delay: progressive_delay(
+                user_failures.max(ip_failures),
+                self.settings.base_delay(),
+                self.settings.max_delay(),
+            ),
+            lockout,
+        })
+    }
+
+    /// Forget the failures of a username once it logs in.
+    ///
+    /// The IP counter is left alone: a valid account must not help guessing
+    /// the passwords of the others.
+    pub async fn record_success(&self, username: &str) -> Result<(), anyhow::Error> {
+        let _: () = self
+            .connection
+            .clone()
+            .del(self.key(format_args!("failures:user:{username}")))
+            .await
+            .context("Failed to reset the failed login counter.")?;
+        Ok(())
+    }
+
+    /// The usernames currently locked out, with how long they still are.
+    #[tracing::instrument(name = "Get locked accounts", skip(self))]
+    pub async fn locked_accounts(&self) -> Result<Vec<(String, Duration)>, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let mut usernames: Vec<String> = connection
+            .smembers(self.locked_users_key())
+            .await
+            .context("Failed to retrieve the locked accounts.")?;
+        usernames.sort();
+        let mut accounts = Vec::with_capacity(usernames.len());
+        for username in usernames {
+            let ttl: i64 = connection
+                .ttl(self.key(format_args!("lock:user:{username}")))
+                .await
+                .context("Failed to check the account lockout.")?;
+            if ttl > 0 {
+                accounts.push((username, Duration::from_secs(ttl as u64)));
+            } else {
+                // The lock expired on its own: tidy up the set.
+                let _: () = connection
+                    .srem(self.locked_users_key(), &username)
+                    .await
+                    .context("Failed to forget an expired lockout.")?;
+            }
+        }
+        Ok(accounts)
+    }
+
+    /// Lift the lockout of a username and reset its failed attempts.
+    #[tracing::instrument(name = "Unlock an account", skip(self))]
+    pub async fn unlock(&self, username: &str) -> Result<(), anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let _: () = connection
+            .del(&[
+                self.key(format_args!("lock:user:{username}")),
+                self.key(format_args!("failures:user:{username}")),
+            ])
+            .await
+            .context("Failed to unlock the account.")?;
+        let _: () = connection
+            .srem(self.locked_users_key(), username)
+            .await
+            .context("Failed to forget the locked account.")?;
+        Ok(())
+    }
+
+    async fn count_failure(&self, subject: std::fmt::Arguments<'_>) -> Result<u64, anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let key = self.key(format_args!("failures:{subject}"));
+        // The window starts with the first failure. Both commands run as
+        // one transaction, so the counter can never be left without a TTL.
+        let (failures,): (u64,) = redis::pipe()
+            .atomic()
+            .cmd("SET")
+            .arg(&key)
+            .arg(0)
+            .arg("EX")
+            .arg(self.settings.window_seconds)
+            .arg("NX")
+            .ignore()
+            .incr(&key, 1)
+            .query_async(&mut connection)
+            .await
+            .context("Failed to count a failed login.")?;
+        Ok(failures)
+    }
+
+    async fn lock(&self, subject: std::fmt::Arguments<'_>) -> Result<(), anyhow::Error> {
+        let mut connection = self.connection.clone();
+        let _: () = connection
+            .set_ex(
+                self.key(format_args!("lock:{subject}")),
+                1,
+                self.settings.lockout_seconds,
+            )
+            .await
+            .context("Failed to lock out a login.")?;
+        // Start afresh once the lockout is over.
+        let _: () = connection
+            .del(self.key(format_args!("failures:{subject}")))
+            .await
+            .context("Failed to reset the failed login counter.")?;
+        Ok(())
+    }
+}
+
+/// The client address recorded in `X-Forwarded-For` by the outermost of our
+/// `trusted_hops` proxies.
+///
+/// Every proxy appends the address it received the request from, so only
+/// the right-most `trusted_hops` entries can be trusted: whatever is on
+/// their left was sent by the client.
+fn forwarded_client_ip<'a>(
+    entries: impl Iterator<Item = &'a str>,
+    trusted_hops: usize,
+) -> Option<IpAddr> {
+    let entries: Vec<&str> = entries.map(str::trim).collect();
+    let index = entries.len().checked_sub(trusted_hops.max(1))?;
+    parse_ip(entries[index])
+}
+
+/// Forwarded addresses may come with a port, and IPv6 within brackets.
+fn parse_ip(addr: &str) -> Option<IpAddr> {
+    addr.parse::<IpAddr>()
+        .ok()
+        .or_else(|| addr.parse::<std::net::SocketAddr>().ok().map(|a| a.ip()))
+}
+
+/// Doubles with every failure: `base`, `2 * base`, `4 * base`... up to `max`.
+fn progressive_delay(failures: u64, base: Duration, max: Duration) -> Duration {
+    if failures == 0 {
+        return Duration::ZERO;
+    }
+    let factor = 1u32
+        .checked_shl((failures - 1).min(31) as u32)
+        .unwrap_or(u32::MAX);
+    base.saturating_mul(factor).min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{forwarded_client_ip, progressive_delay};
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    const BASE: Duration = Duration::from_millis(250);
+    const MAX: Duration = Duration::from_secs(4);
+
+    #[test]
+    fn there_is_no_delay_without_failures() {
+        assert_eq!(progressive_delay(0, BASE, MAX), Duration::ZERO);
+    }
+
+    #[test]
+    fn the_delay_doubles_with_every_failure() {
+        assert_eq!(progressive_delay(1, BASE, MAX), Duration::from_millis(250));
+        assert_eq!(progressive_delay(2, BASE, MAX), Duration::from_millis(500));
+        assert_eq!(progressive_delay(3, BASE, MAX), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn the_delay_is_capped() {
+        assert_eq!(progressive_delay(5, BASE, MAX), MAX);
+        assert_eq!(progressive_delay(1_000, BASE, MAX), MAX);
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn the_address_appended_by_the_proxy_is_used() {
+        let entries = "1.1.1.1, 203.0.113.7".split(',');
+        assert_eq!(forwarded_client_ip(entries, 1), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn entries_beyond_the_trusted_hops_are_ignored() {
+        let entries = ["6.6.6.6, 203.0.113.7", "10.0.0.2"].into_iter();
+        assert_eq!(
+            forwarded_client_ip(entries.flat_map(|h| h.split(',')), 2),
+            ip("203.0.113.7")
+        );
+    }
+
+    #[test]
+    fn there_is_no_forwarded_address_without_enough_hops() {
+        assert_eq!(forwarded_client_ip("203.0.113.7".split(','), 2), None);
+        assert_eq!(forwarded_client_ip(std::iter::empty(), 1), None);
+    }
+
+    #[test]
+    fn forwarded_addresses_may_have_a_port() {
+        let entries = "[2001:db8::1]:4711".split(',');
+        assert_eq!(forwarded_client_ip(entries, 1), ip("2001:db8::1"));
+    }
+}
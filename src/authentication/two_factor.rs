@@ -0,0 +1,388 @@
+//! src/authentication/two_factor.rs
+use crate::configurations::TwoFactorSettings;
+use aes_gcm::aead::{Aead, AeadCore, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::{Rng, RngCore, distributions::Alphanumeric, thread_rng};
+use secrecy::{ExposeSecret, SecretSlice};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use sqlx::{Executor, PgPool};
+use uuid::Uuid;
+
+/// RFC 6238 defaults, the only ones authenticator apps reliably support.
+const TIME_STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+/// Accept the codes of the previous and next time steps as well, to cope
+/// with clock drift.
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+const N_RECOVERY_CODES: usize = 10;
+/// Length of the AES-GCM nonce stored in front of the ciphertext.
+const NONCE_LENGTH: usize = 12;
+
+/// The shared secret of a TOTP authenticator.
+#[derive(Debug)]
+pub struct TotpSecret(SecretSlice<u8>);
+
+impl TotpSecret {
+    /// 160 bits, as recommended by RFC 4226.
+    pub fn generate() -> Self {
+        let mut bytes = vec![0u8; 20];
+        thread_rng().fill_bytes(&mut bytes);
+        Self(bytes.into())
+    }
+
+    pub fn parse_base32(s: &str) -> Result<Self, anyhow::Error> {
+        let bytes = data_encoding::BASE32_NOPAD
+            .decode(s.as_bytes())
+            .context("The TOTP secret is not valid base32.")?;
+        Ok(Self(bytes.into()))
+    }
+
+    /// The representation authenticator apps expect when the secret is
+    /// typed in by hand.
+    pub fn to_base32(&self) -> String {
+        data_encoding::BASE32_NOPAD.encode(self.0.expose_secret())
+    }
+
+    /// The URI encoded in the QR code scanned by authenticator apps.
+    pub fn otpauth_uri(&self, issuer: &str, username: &str) -> String {
+        format!(
+            "otpauth://totp/{issuer}:{username}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={TIME_STEP_SECONDS}",
+            issuer = urlencoding::encode(issuer),
+            username = urlencoding::encode(username),
+            secret = self.to_base32(),
+        )
+    }
+
+    /// The code an authenticator app displays at `at`.
+    pub fn code_at(&self, at: DateTime<Utc>) -> String {
+        self.code_for_step(time_step(at))
+    }
+
+    fn code_for_step(&self, step: i64) -> String {
+        let mut mac = <Hmac<Sha1> as Mac>::new_from_slice(self.0.expose_secret())
+            .expect("HMAC can take a key of any size");
+        mac.update(&step.to_be_bytes());
+        let digest = mac.finalize().into_bytes();
+        // Dynamic truncation, RFC 4226 section 5.3
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let binary =
+            u32::from_be_bytes(digest[offset..offset + 4].try_into().unwrap()) & 0x7fff_ffff;
+        format!(
+            "{:0width$}",
+            binary % 10u32.pow(DIGITS),
+            width = DIGITS as usize
+        )
+    }
+
+    /// The time step `code` belongs to, if it is valid around `at`.
+    fn matching_step(&self, code: &str, at: DateTime<Utc>) -> Option<i64> {
+        let current = time_step(at);
+        (current - ALLOWED_DRIFT_STEPS..=current + ALLOWED_DRIFT_STEPS)
+            .find(|step| self.code_for_step(*step) == code)
+    }
+
+    fn encrypt(
+        &self,
+        settings: &TwoFactorSettings,
+        user_id: Uuid,
+    ) -> Result<Vec<u8>, anyhow::Error> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: self.0.expose_secret(),
+            aad: user_id.as_bytes(),
+        };
+        let ciphertext = cipher(settings)
+            .encrypt(&nonce, payload)
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt a TOTP secret."))?;
+        Ok([nonce.as_slice(), &ciphertext].concat())
+    }
+
+    /// The secret is bound to `user_id`: it cannot be copied over to
+    /// another user.
+    fn decrypt(
+        encrypted: &[u8],
+        settings: &TwoFactorSettings,
+        user_id: Uuid,
+    ) -> Result<Self, anyhow::Error> {
+        if encrypted.len() < NONCE_LENGTH {
+            anyhow::bail!("The encrypted TOTP secret is too short.");
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LENGTH);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: user_id.as_bytes(),
+        };
+        let bytes = cipher(settings)
+            .decrypt(nonce.into(), payload)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt a TOTP secret."))?;
+        Ok(Self(bytes.into()))
+    }
+}
+
+fn time_step(at: DateTime<Utc>) -> i64 {
+    at.timestamp().div_euclid(TIME_STEP_SECONDS)
+}
+
+/// The configured key can be any string: it is hashed into an AES-256 key.
+fn cipher(settings: &TwoFactorSettings) -> Aes256Gcm {
+    let key = Sha256::digest(settings.encryption_key.expose_secret().as_bytes());
+    Aes256Gcm::new(&key)
+}
+
+fn generate_recovery_code() -> String {
+    let mut rng = thread_rng();
+    let mut code: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(|c| char::from(c).to_ascii_lowercase())
+        .take(10)
+        .collect();
+    code.insert(5, '-');
+    code
+}
+
+/// Recovery codes are long random strings: like API tokens, a fast hash
+/// is enough.
+fn hash_recovery_code(code: &str) -> String {
+    hex::encode(Sha256::digest(code.trim().to_lowercase().as_bytes()))
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn is_two_factor_enabled(pool: &PgPool, user_id: Uuid) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT totp_secret IS NOT NULL AS "enabled!" FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to check whether a user enabled two-factor authentication.")?;
+    Ok(row.is_some_and(|r| r.enabled))
+}
+
+/// Turn on two-factor authentication for `user_id` once they have proven,
+/// with `code`, that their authenticator app is set up.
+///
+/// Returns `None` if `code` is wrong, and the recovery codes otherwise.
+/// They cannot be retrieved afterwards.
+#[tracing::instrument(skip(pool, secret, code, settings))]
+pub async fn enable_two_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+    secret: &TotpSecret,
+    code: &str,
+    settings: &TwoFactorSettings,
+) -> Result<Option<Vec<String>>, anyhow::Error> {
+    let Some(step) = secret.matching_step(code.trim(), Utc::now()) else {
+        return Ok(None);
+    };
+    let recovery_codes: Vec<String> = std::iter::repeat_with(generate_recovery_code)
+        .take(N_RECOVERY_CODES)
+        .collect();
+    let code_hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|c| hash_recovery_code(c))
+        .collect();
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to begin a transaction.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"UPDATE users SET totp_secret = $2, totp_last_step = $3 WHERE user_id = $1"#,
+            user_id,
+            secret.encrypt(settings, user_id)?,
+            step
+        ))
+        .await
+        .context("Failed to store a TOTP secret.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM totp_recovery_codes WHERE user_id = $1"#,
+            user_id
+        ))
+        .await
+        .context("Failed to delete old recovery codes.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"
+            INSERT INTO totp_recovery_codes (user_id, code_hash)
+            SELECT $1, * FROM UNNEST($2::text[])
+            "#,
+            user_id,
+            &code_hashes
+        ))
+        .await
+        .context("Failed to store recovery codes.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to enable two-factor authentication.")?;
+    Ok(Some(recovery_codes))
+}
+
+/// Turn off two-factor authentication, discarding the recovery codes.
+#[tracing::instrument(skip(pool))]
+pub async fn disable_two_factor(pool: &PgPool, user_id: Uuid) -> Result<(), anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to begin a transaction.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"UPDATE users SET totp_secret = NULL, totp_last_step = NULL WHERE user_id = $1"#,
+            user_id
+        ))
+        .await
+        .context("Failed to remove a TOTP secret.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM totp_recovery_codes WHERE user_id = $1"#,
+            user_id
+        ))
+        .await
+        .context("Failed to delete recovery codes.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to disable two-factor authentication.")?;
+    Ok(())
+}
+
+/// Check `code` - either the current TOTP code or an unused recovery code.
+///
+/// Both are single-use: a TOTP code is rejected if a code of the same or a
+/// later time step was already accepted.
+#[tracing::instrument(skip(pool, code, settings))]
+pub async fn verify_second_factor(
+    pool: &PgPool,
+    user_id: Uuid,
+    code: &str,
+    settings: &TwoFactorSettings,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"SELECT totp_secret AS "totp_secret!" FROM users WHERE user_id = $1 AND totp_secret IS NOT NULL"#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a TOTP secret.")?;
+    let Some(row) = row else {
+        return Ok(false);
+    };
+    let secret = TotpSecret::decrypt(&row.totp_secret, settings, user_id)?;
+
+    if let Some(step) = secret.matching_step(code.trim(), Utc::now()) {
+        let n_updated = sqlx::query!(
+            r#"
+            UPDATE users
+            SET totp_last_step = $2
+            WHERE user_id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)
+            "#,
+            user_id,
+            step
+        )
+        .execute(pool)
+        .await
+        .context("Failed to record the use of a TOTP code.")?
+        .rows_affected();
+        return Ok(n_updated > 0);
+    }
+
+    let n_used = sqlx::query!(
+        r#"
+        UPDATE totp_recovery_codes
+        SET used_at = now()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        "#,
+        user_id,
+        hash_recovery_code(code)
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record the use of a recovery code.")?
+    .rows_affected();
+    Ok(n_used > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TotpSecret, hash_recovery_code};
+    use crate::configurations::TwoFactorSettings;
+    use chrono::{DateTime, Utc};
+    use claim::{assert_err, assert_ok, assert_some_eq};
+    use uuid::Uuid;
+
+    // RFC 6238, appendix B, truncated to 6 digits.
+    fn rfc_secret() -> TotpSecret {
+        TotpSecret(b"12345678901234567890".to_vec().into())
+    }
+
+    fn at(timestamp: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(timestamp, 0).unwrap()
+    }
+
+    fn settings() -> TwoFactorSettings {
+        TwoFactorSettings {
+            issuer: "zero2prod".into(),
+            encryption_key: "a-very-secret-key".into(),
+        }
+    }
+
+    #[test]
+    fn codes_match_the_rfc_test_vectors() {
+        let secret = rfc_secret();
+        for (timestamp, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+        ] {
+            assert_eq!(secret.code_at(at(timestamp)), code);
+        }
+    }
+
+    #[test]
+    fn codes_of_adjacent_steps_are_accepted() {
+        let secret = rfc_secret();
+        let now = at(1111111111);
+        assert_some_eq!(secret.matching_step("081804", now), 37037036);
+        assert_some_eq!(secret.matching_step("050471", now), 37037037);
+        assert_eq!(secret.matching_step("005924", now), None);
+    }
+
+    #[test]
+    fn secrets_round_trip_through_base32() {
+        let secret = TotpSecret::generate();
+        let parsed = TotpSecret::parse_base32(&secret.to_base32()).unwrap();
+        assert_eq!(parsed.code_at(at(59)), secret.code_at(at(59)));
+        assert_eq!(rfc_secret().to_base32(), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+    }
+
+    #[test]
+    fn encrypted_secrets_are_bound_to_their_user() {
+        let secret = rfc_secret();
+        let user_id = Uuid::new_v4();
+        let encrypted = secret.encrypt(&settings(), user_id).unwrap();
+
+        let decrypted = assert_ok!(TotpSecret::decrypt(&encrypted, &settings(), user_id));
+        assert_eq!(decrypted.code_at(at(59)), "287082");
+        assert_err!(TotpSecret::decrypt(&encrypted, &settings(), Uuid::new_v4()));
+        let other_key = TwoFactorSettings {
+            encryption_key: "another-key".into(),
+            ..settings()
+        };
+        assert_err!(TotpSecret::decrypt(&encrypted, &other_key, user_id));
+    }
+
+    #[test]
+    fn recovery_codes_are_case_insensitive() {
+        assert_eq!(
+            hash_recovery_code(" ABCDE-12345 "),
+            hash_recovery_code("abcde-12345")
+        );
+    }
+}
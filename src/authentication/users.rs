@@ -0,0 +1,345 @@
+//! src/authentication/users.rs
+use crate::authentication::Role;
+use crate::routes::error_chain_fmt;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// ```text
+/// invited ──> active <──> disabled
+///    │                       ↑
+///    └───────────────────────┘
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "user_status", rename_all = "snake_case")]
+pub enum UserStatus {
+    /// Has not set a password yet.
+    Invited,
+    Active,
+    /// Cannot log in, nor use their API tokens.
+    Disabled,
+}
+
+impl UserStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserStatus::Invited => "invited",
+            UserStatus::Active => "active",
+            UserStatus::Disabled => "disabled",
+        }
+    }
+}
+
+pub struct User {
+    pub user_id: Uuid,
+    pub username: String,
+    pub email: Option<String>,
+    pub status: UserStatus,
+    pub role: Role,
+    pub two_factor_enabled: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(thiserror::Error)]
+pub enum UserManagementError {
+    #[error("There must be at least one active owner left.")]
+    LastActiveOwner,
+    #[error("There is no such user.")]
+    UnknownUser,
+    #[error("The username or the email is already taken.")]
+    AlreadyTaken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UserManagementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_users(pool: &PgPool) -> Result<Vec<User>, anyhow::Error> {
+    let users = sqlx::query_as!(
+        User,
+        r#"
+        SELECT
+            user_id,
+            username,
+            email,
+            status AS "status: UserStatus",
+            role AS "role: Role",
+            totp_secret IS NOT NULL AS "two_factor_enabled!",
+            created_at
+        FROM users
+        ORDER BY created_at, username
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve users.")?;
+    Ok(users)
+}
+
+/// What the session middleware needs to know about a logged-in user.
+pub struct ActiveUser {
+    pub role: Role,
+    /// Sessions started with an older epoch are over.
+    pub session_epoch: i32,
+}
+
+/// `None` if `user_id` may not use the application right now.
+#[tracing::instrument(skip(pool))]
+pub async fn get_active_user(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Option<ActiveUser>, anyhow::Error> {
+    let user = sqlx::query_as!(
+        ActiveUser,
+        r#"
+        SELECT role AS "role: Role", session_epoch
+        FROM users
+        WHERE user_id = $1 AND status = $2
+        "#,
+        user_id,
+        UserStatus::Active as UserStatus
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the status of a user.")?;
+    Ok(user)
+}
+
+/// Create a user without a password, waiting for them to accept their
+/// invitation.
+#[tracing::instrument(skip(pool))]
+pub async fn insert_invited_user(
+    pool: &PgPool,
+    username: &str,
+    email: &str,
+    role: Role,
+) -> Result<Uuid, UserManagementError> {
+    let user_id = Uuid::new_v4();
+    let n_inserted = sqlx::query!(
+        r#"
+        INSERT INTO users (user_id, username, email, status, role, created_at)
+        VALUES ($1, $2, $3, $4, $5, now())
+        ON CONFLICT DO NOTHING
+        "#,
+        user_id,
+        username,
+        email,
+        UserStatus::Invited as UserStatus,
+        role as Role
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store an invited user.")?
+    .rows_affected();
+    if n_inserted == 0 {
+        return Err(UserManagementError::AlreadyTaken);
+    }
+    Ok(user_id)
+}
+
+/// The username and email of a user still waiting to accept their
+/// invitation.
+#[tracing::instrument(skip(pool))]
+pub async fn get_invited_user(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<Option<(String, String)>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT username, email AS "email!"
+        FROM users
+        WHERE user_id = $1 AND status = $2 AND email IS NOT NULL
+        "#,
+        user_id,
+        UserStatus::Invited as UserStatus
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve an invited user.")?;
+    Ok(row.map(|r| (r.username, r.email)))
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn disable_user(pool: &PgPool, user_id: Uuid) -> Result<(), UserManagementError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to begin a transaction.")?;
+    ensure_not_last_active_owner(&mut transaction, user_id).await?;
+    let query = sqlx::query!(
+        r#"UPDATE users SET status = $2 WHERE user_id = $1"#,
+        user_id,
+        UserStatus::Disabled as UserStatus
+    );
+    let n_updated = transaction
+        .execute(query)
+        .await
+        .context("Failed to disable a user.")?
+        .rows_affected();
+    if n_updated == 0 {
+        return Err(UserManagementError::UnknownUser);
+    }
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM user_sessions WHERE user_id = $1"#,
+            user_id
+        ))
+        .await
+        .context("Failed to end the sessions of a user.")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to disable a user.")?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn change_role(
+    pool: &PgPool,
+    user_id: Uuid,
+    role: Role,
+) -> Result<(), UserManagementError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to begin a transaction.")?;
+    if role != Role::Owner {
+        ensure_not_last_active_owner(&mut transaction, user_id).await?;
+    }
+    let query = sqlx::query!(
+        r#"UPDATE users SET role = $2 WHERE user_id = $1"#,
+        user_id,
+        role as Role
+    );
+    let n_updated = transaction
+        .execute(query)
+        .await
+        .context("Failed to change the role of a user.")?
+        .rows_affected();
+    if n_updated == 0 {
+        return Err(UserManagementError::UnknownUser);
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change the role of a user.")?;
+    Ok(())
+}
+
+/// Let a disabled user back in - or back to accepting their invitation,
+/// if they never set a password.
+#[tracing::instrument(skip(pool))]
+pub async fn enable_user(pool: &PgPool, user_id: Uuid) -> Result<(), UserManagementError> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE users
+        SET status = CASE WHEN password_hash IS NULL THEN $3::user_status ELSE $4::user_status END
+        WHERE user_id = $1 AND status = $2
+        "#,
+        user_id,
+        UserStatus::Disabled as UserStatus,
+        UserStatus::Invited as UserStatus,
+        UserStatus::Active as UserStatus
+    )
+    .execute(pool)
+    .await
+    .context("Failed to enable a user.")?
+    .rows_affected();
+    if n_updated == 0 {
+        return Err(UserManagementError::UnknownUser);
+    }
+    Ok(())
+}
+
+/// Delete a user together with their API tokens, saved idempotent
+/// responses, recovery codes and password reset tokens.
+#[tracing::instrument(skip(pool))]
+pub async fn delete_user(pool: &PgPool, user_id: Uuid) -> Result<(), UserManagementError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to begin a transaction.")?;
+    ensure_not_last_active_owner(&mut transaction, user_id).await?;
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM api_tokens WHERE user_id = $1"#,
+            user_id
+        ))
+        .await
+        .context("Failed to delete the API tokens of a user.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM idempotency WHERE user_id = $1"#,
+            user_id
+        ))
+        .await
+        .context("Failed to delete the saved responses of a user.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM totp_recovery_codes WHERE user_id = $1"#,
+            user_id
+        ))
+        .await
+        .context("Failed to delete the recovery codes of a user.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM password_reset_tokens WHERE user_id = $1"#,
+            user_id
+        ))
+        .await
+        .context("Failed to delete the password reset tokens of a user.")?;
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM user_sessions WHERE user_id = $1"#,
+            user_id
+        ))
+        .await
+        .context("Failed to end the sessions of a user.")?;
+    let n_deleted = transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM users WHERE user_id = $1"#,
+            user_id
+        ))
+        .await
+        .context("Failed to delete a user.")?
+        .rows_affected();
+    if n_deleted == 0 {
+        return Err(UserManagementError::UnknownUser);
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to delete a user.")?;
+    Ok(())
+}
+
+/// Fails if `user_id` is the only active owner: nobody would be left to
+/// manage users.
+///
+/// The active owners are locked until `transaction` is over: two owners
+/// cannot demote each other at the same time.
+async fn ensure_not_last_active_owner(
+    transaction: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> Result<(), UserManagementError> {
+    let active_owners = sqlx::query!(
+        r#"SELECT user_id FROM users WHERE status = $1 AND role = $2 FOR UPDATE"#,
+        UserStatus::Active as UserStatus,
+        Role::Owner as Role
+    )
+    .fetch_all(&mut **transaction)
+    .await
+    .context("Failed to retrieve the active owners.")?;
+    if let [last] = active_owners.as_slice()
+        && last.user_id == user_id
+    {
+        return Err(UserManagementError::LastActiveOwner);
+    }
+    Ok(())
+}
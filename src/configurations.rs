@@ -1,6 +1,11 @@
 //! src/configuration.rs
 
 use crate::domain::SubscriberEmail;
+use crate::email_client::{
+    EmailClient, OutboxTransport, PostmarkTransport, SmtpTls, SmtpTransport,
+};
+use crate::issue_delivery_worker::RetryPolicy;
+use chrono_tz::Tz;
 use secrecy::{ExposeSecret, SecretString};
 use serde_aux::field_attributes::deserialize_number_from_string;
 use sqlx::{ConnectOptions, postgres::PgConnectOptions, postgres::PgSslMode};
@@ -10,6 +15,16 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub issue_delivery: IssueDeliverySettings,
+    pub issue_scheduling: IssueSchedulingSettings,
+    pub subscriptions: SubscriptionSettings,
+    pub webhooks: WebhookSettings,
+    pub api: ApiSettings,
+    pub users: UserSettings,
+    pub two_factor: TwoFactorSettings,
+    pub login_throttling: LoginThrottlingSettings,
+    pub password_hashing: PasswordHashingSettings,
+    pub password_policy: PasswordPolicySettings,
     pub redis_uri: SecretString,
 }
 
@@ -35,10 +50,156 @@ pub struct ApplicationSettings {
 
 #[derive(serde::Deserialize, Clone)]
 pub struct EmailClientSettings {
+    pub transport: EmailTransportKind,
     pub base_url: String,
     pub sender_email: String,
     pub authorization_token: SecretString,
     pub timeout_milliseconds: u64,
+    pub smtp: Option<SmtpSettings>,
+    pub outbox: Option<OutboxSettings>,
+}
+
+/// Which `EmailTransport` the application sends emails with.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransportKind {
+    Postmark,
+    Smtp,
+    Outbox,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<SecretString>,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct OutboxSettings {
+    pub directory: String,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct IssueDeliverySettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub workers: u16,
+    pub idle_backoff_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_retries: i16,
+    pub retry_base_delay_milliseconds: u64,
+    pub retry_max_delay_milliseconds: u64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct IssueSchedulingSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub poll_interval_seconds: u64,
+    /// Where editors pick the publishing time of an issue, and see it.
+    pub timezone: Tz,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct SubscriptionSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub confirmation_token_ttl_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub retention_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub purge_interval_seconds: u64,
+}
+
+/// The basic auth credentials the email provider calls our webhooks with.
+#[derive(serde::Deserialize, Clone)]
+pub struct WebhookSettings {
+    pub username: String,
+    pub password: SecretString,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct ApiSettings {
+    /// Accept the username and password of a user, through HTTP Basic auth,
+    /// on top of API tokens.
+    pub allow_basic_auth: bool,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct UserSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub invitation_ttl_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub password_reset_ttl_seconds: u64,
+    /// How many reset links a user can be sent per hour.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub password_reset_requests_per_hour: i64,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct TwoFactorSettings {
+    /// Shown next to the account in authenticator apps.
+    pub issuer: String,
+    /// Encrypts the TOTP secrets stored in the database.
+    pub encryption_key: SecretString,
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct LoginThrottlingSettings {
+    /// Failed attempts are counted over this many seconds.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub window_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failures_per_username: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_failures_per_ip: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lockout_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_delay_milliseconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_delay_milliseconds: u64,
+    /// Take the client IP address from `X-Forwarded-For`: only safe behind
+    /// a reverse proxy that sets it.
+    pub trust_forwarded_headers: bool,
+    /// How many proxies in front of the application append to
+    /// `X-Forwarded-For`: the client address is the one the outermost of
+    /// them recorded, anything further left is ignored.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub trusted_proxy_hops: usize,
+    /// Namespaces the keys stored in Redis.
+    pub key_prefix: String,
+}
+
+/// The Argon2id cost of new password hashes. Hashes stored with other
+/// parameters are upgraded the next time their user logs in.
+#[derive(serde::Deserialize, Clone)]
+pub struct PasswordHashingSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub memory_kib: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub iterations: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub parallelism: u32,
+    /// Checked against when the username is unknown, so that the answer
+    /// takes as long as for a known one. Computed with the parameters above
+    /// by `Application::build`.
+    #[serde(skip)]
+    pub fallback_password_hash: Option<SecretString>,
+}
+
+/// What a password chosen by a user has to look like.
+#[derive(serde::Deserialize, Clone)]
+pub struct PasswordPolicySettings {
+    /// In graphemes.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_length: usize,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_length: usize,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_entropy_bits: f64,
+    pub breached_passwords_path: String,
 }
 
 /// The possible runtime environment for our application.
@@ -145,6 +306,38 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
 }
 
 impl EmailClientSettings {
+    pub fn client(self) -> EmailClient {
+        let sender_email = self.sender().expect("Invalid sender email address.");
+        let timeout = self.timeout();
+        match self.transport {
+            EmailTransportKind::Postmark => EmailClient::new(
+                sender_email,
+                PostmarkTransport::new(self.base_url, self.authorization_token, timeout),
+            ),
+            EmailTransportKind::Smtp => {
+                let smtp = self
+                    .smtp
+                    .expect("`email_client.smtp` is required by the `smtp` transport.");
+                let credentials = match (smtp.username, smtp.password) {
+                    (Some(username), Some(password)) => Some((username, password)),
+                    _ => None,
+                };
+                let transport =
+                    SmtpTransport::new(&smtp.host, smtp.port, smtp.tls, credentials, timeout)
+                        .expect("Failed to build the SMTP transport.");
+                EmailClient::new(sender_email, transport)
+            }
+            EmailTransportKind::Outbox => {
+                let outbox = self
+                    .outbox
+                    .expect("`email_client.outbox` is required by the `outbox` transport.");
+                let transport = OutboxTransport::new(outbox.directory)
+                    .expect("Failed to create the outbox directory.");
+                EmailClient::new(sender_email, transport)
+            }
+        }
+    }
+
     pub fn sender(&self) -> Result<SubscriberEmail, String> {
         SubscriberEmail::parse(self.sender_email.clone())
     }
@@ -153,3 +346,68 @@ impl EmailClientSettings {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
 }
+
+impl IssueDeliverySettings {
+    pub fn idle_backoff(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.idle_backoff_milliseconds)
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_retries: self.max_retries,
+            base_delay: std::time::Duration::from_millis(self.retry_base_delay_milliseconds),
+            max_delay: std::time::Duration::from_millis(self.retry_max_delay_milliseconds),
+        }
+    }
+}
+
+impl IssueSchedulingSettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_seconds)
+    }
+}
+
+impl SubscriptionSettings {
+    pub fn confirmation_token_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.confirmation_token_ttl_seconds)
+    }
+
+    pub fn retention(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.retention_seconds)
+    }
+
+    pub fn purge_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.purge_interval_seconds)
+    }
+}
+
+impl UserSettings {
+    pub fn invitation_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.invitation_ttl_seconds)
+    }
+
+    pub fn password_reset_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.password_reset_ttl_seconds)
+    }
+}
+
+impl LoginThrottlingSettings {
+    pub fn lockout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.lockout_seconds)
+    }
+
+    pub fn base_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.base_delay_milliseconds)
+    }
+
+    pub fn max_delay(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.max_delay_milliseconds)
+    }
+}
+
+impl PasswordHashingSettings {
+    pub fn params(&self) -> Result<argon2::Params, anyhow::Error> {
+        argon2::Params::new(self.memory_kib, self.iterations, self.parallelism, None)
+            .map_err(|e| anyhow::anyhow!("Invalid password hashing parameters: {}", e))
+    }
+}
@@ -0,0 +1,111 @@
+//! src/csrf.rs
+//! Synchronizer tokens against cross-site request forgery: every form we
+//! render carries the token stored in the session, and `POST`s without it
+//! are rejected.
+use crate::session_state::TypedSession;
+use crate::templates::render_with_status;
+use crate::utils::e500;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::{Method, StatusCode};
+use actix_web::{FromRequest, HttpRequest, middleware, web};
+use askama::Template;
+use rand::{Rng, distributions::Alphanumeric, thread_rng};
+use std::future::{Ready, ready};
+use subtle::ConstantTimeEq;
+
+/// The name of the form field carrying the token.
+const FIELD_NAME: &str = "csrf_token";
+/// For requests that are not form posts.
+const HEADER_NAME: &str = "X-CSRF-Token";
+
+/// The token of the current session, created on first use.
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    /// The hidden input to add to every `POST` form.
+    pub fn form_field(&self) -> String {
+        format!(
+            r#"<input type="hidden" name="{FIELD_NAME}" value="{}">"#,
+            self.0
+        )
+    }
+}
+
+impl FromRequest for CsrfToken {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<CsrfToken, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        ready(
+            TypedSession::from_request(req, payload)
+                .into_inner()
+                .and_then(|session| get_or_insert_token(&session)),
+        )
+    }
+}
+
+fn get_or_insert_token(session: &TypedSession) -> Result<CsrfToken, actix_web::Error> {
+    if let Some(token) = session.get_csrf_token().map_err(e500)? {
+        return Ok(CsrfToken(token));
+    }
+    let mut rng = thread_rng();
+    let token: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect();
+    session.insert_csrf_token(&token).map_err(e500)?;
+    Ok(CsrfToken(token))
+}
+
+#[derive(Template)]
+#[template(path = "errors/expired_form.html")]
+struct ExpiredFormPage;
+
+#[derive(serde::Deserialize)]
+struct CsrfFormField {
+    csrf_token: Option<String>,
+}
+
+/// Reject the requests that change something without the token of the
+/// session, in a form field or in the `X-CSRF-Token` header.
+pub async fn reject_forged_requests(
+    mut req: ServiceRequest,
+    next: middleware::Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.call(req).await;
+    }
+    let session = {
+        let (http_request, payload) = req.parts_mut();
+        TypedSession::from_request(http_request, payload).await
+    }?;
+    let expected = session.get_csrf_token().map_err(e500)?;
+
+    let submitted = match req.headers().get(HEADER_NAME) {
+        Some(value) => value.to_str().ok().map(str::to_owned),
+        None => {
+            // Read the form, then put it back for the handler.
+            let body = req.extract::<web::Bytes>().await?;
+            let field = serde_urlencoded::from_bytes::<CsrfFormField>(&body)
+                .ok()
+                .and_then(|f| f.csrf_token);
+            req.set_payload(Payload::from(body));
+            field
+        }
+    };
+
+    match (expected, submitted) {
+        (Some(expected), Some(submitted))
+            if bool::from(expected.as_bytes().ct_eq(submitted.as_bytes())) =>
+        {
+            next.call(req).await
+        }
+        _ => {
+            let response = render_with_status(StatusCode::FORBIDDEN, &ExpiredFormPage)?;
+            let e = anyhow::anyhow!("The CSRF token is missing or invalid");
+            Err(InternalError::from_response(e, response).into())
+        }
+    }
+}
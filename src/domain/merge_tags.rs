@@ -0,0 +1,253 @@
+//! src/domain/merge_tags.rs
+//! Merge tags personalize the body of an issue for each of its recipients:
+//! `{{ name }}`, `{{ email }}` and `{{ unsubscribe_url }}`, with a fallback
+//! for when the value is missing - `{{ name | default: "reader" }}`.
+use askama::filters::{Escaper, Html};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MergeTag {
+    Name,
+    Email,
+    UnsubscribeUrl,
+}
+
+impl MergeTag {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "name" => Some(MergeTag::Name),
+            "email" => Some(MergeTag::Email),
+            "unsubscribe_url" => Some(MergeTag::UnsubscribeUrl),
+            _ => None,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum MergeTagError {
+    #[error(
+        "`{{{{ {0} }}}}` is not a merge tag: use `{{{{ name }}}}`, `{{{{ email }}}}` or `{{{{ unsubscribe_url }}}}`."
+    )]
+    UnknownTag(String),
+    #[error("A merge tag is not closed: every `{{{{` needs a matching `}}}}`.")]
+    Unclosed,
+    #[error("Write the fallback of `{{{{ {0} }}}}` as `default: \"...\"`.")]
+    InvalidFallback(String),
+}
+
+/// The values of the merge tags, for one recipient.
+pub struct MergeFields<'a> {
+    /// `None` if the subscriber is gone.
+    pub name: Option<&'a str>,
+    pub email: &'a str,
+    pub unsubscribe_url: &'a str,
+}
+
+impl MergeFields<'_> {
+    fn get(&self, tag: MergeTag) -> Option<&str> {
+        match tag {
+            MergeTag::Name => self.name,
+            MergeTag::Email => Some(self.email),
+            MergeTag::UnsubscribeUrl => Some(self.unsubscribe_url),
+        }
+        .filter(|value| !value.trim().is_empty())
+    }
+}
+
+/// How the values are written into a body.
+#[derive(Debug, Clone, Copy)]
+pub enum BodyFormat {
+    /// Escaped: a subscriber must not be able to inject markup.
+    Html,
+    /// As they are.
+    Text,
+}
+
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Tag {
+        tag: MergeTag,
+        fallback: Option<String>,
+    },
+}
+
+/// A body, with its merge tags parsed.
+#[derive(Debug, PartialEq)]
+pub struct MergeTemplate(Vec<Segment>);
+
+impl MergeTemplate {
+    pub fn parse(source: &str) -> Result<Self, MergeTagError> {
+        let mut segments = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_owned()));
+            }
+            let after = &rest[start + 2..];
+            let end = after.find("}}").ok_or(MergeTagError::Unclosed)?;
+            segments.push(parse_tag(after[..end].trim())?);
+            rest = &after[end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_owned()));
+        }
+        Ok(Self(segments))
+    }
+
+    /// `source` without looking for merge tags in it.
+    pub fn verbatim(source: &str) -> Self {
+        Self(vec![Segment::Text(source.to_owned())])
+    }
+
+    /// Whether `tag` appears anywhere in the body.
+    pub fn uses(&self, tag: MergeTag) -> bool {
+        self.0
+            .iter()
+            .any(|segment| matches!(segment, Segment::Tag { tag: t, .. } if *t == tag))
+    }
+
+    /// Missing values without a fallback are left out.
+    pub fn render(&self, fields: &MergeFields, format: BodyFormat) -> String {
+        let mut body = String::new();
+        for segment in &self.0 {
+            match segment {
+                Segment::Text(text) => body.push_str(text),
+                Segment::Tag { tag, fallback } => {
+                    let value = fields.get(*tag).or(fallback.as_deref()).unwrap_or_default();
+                    match format {
+                        BodyFormat::Html => Html.write_escaped_str(&mut body, value).unwrap(),
+                        BodyFormat::Text => body.push_str(value),
+                    }
+                }
+            }
+        }
+        body
+    }
+}
+
+/// `expression` is what is between the braces, e.g. `name | default: "reader"`.
+fn parse_tag(expression: &str) -> Result<Segment, MergeTagError> {
+    let (name, fallback) = match expression.split_once('|') {
+        None => (expression, None),
+        Some((name, filter)) => {
+            let fallback = filter
+                .trim()
+                .strip_prefix("default:")
+                .and_then(|value| value.trim().strip_prefix('"')?.strip_suffix('"'))
+                .ok_or_else(|| MergeTagError::InvalidFallback(expression.to_owned()))?;
+            (name.trim(), Some(fallback.to_owned()))
+        }
+    };
+    let tag = MergeTag::parse(name).ok_or_else(|| MergeTagError::UnknownTag(name.to_owned()))?;
+    Ok(Segment::Tag { tag, fallback })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BodyFormat, MergeFields, MergeTag, MergeTagError, MergeTemplate};
+    use claim::assert_err;
+
+    fn fields(name: Option<&str>) -> MergeFields<'_> {
+        MergeFields {
+            name,
+            email: "ursula@example.com",
+            unsubscribe_url: "https://example.com/unsubscribe?token=a&b",
+        }
+    }
+
+    fn render(source: &str, name: Option<&str>, format: BodyFormat) -> String {
+        MergeTemplate::parse(source)
+            .unwrap()
+            .render(&fields(name), format)
+    }
+
+    #[test]
+    fn tags_are_replaced_with_the_values_of_the_recipient() {
+        assert_eq!(
+            render(
+                "Hi {{ name }} ({{email}}), leave at {{ unsubscribe_url }}",
+                Some("Ursula"),
+                BodyFormat::Text
+            ),
+            "Hi Ursula (ursula@example.com), leave at https://example.com/unsubscribe?token=a&b"
+        );
+    }
+
+    #[test]
+    fn a_template_knows_which_tags_it_uses() {
+        let template = MergeTemplate::parse("Bye {{ name }}: {{ unsubscribe_url }}").unwrap();
+        assert!(template.uses(MergeTag::UnsubscribeUrl));
+        assert!(!template.uses(MergeTag::Email));
+        assert!(!MergeTemplate::verbatim("{{ unsubscribe_url }}").uses(MergeTag::UnsubscribeUrl));
+    }
+
+    #[test]
+    fn the_fallback_is_used_when_the_value_is_missing() {
+        let source = r#"Hi {{ name | default: "reader" }}!"#;
+        assert_eq!(render(source, None, BodyFormat::Text), "Hi reader!");
+        assert_eq!(render(source, Some(" "), BodyFormat::Text), "Hi reader!");
+        assert_eq!(
+            render(source, Some("Ursula"), BodyFormat::Text),
+            "Hi Ursula!"
+        );
+        assert_eq!(render("Hi {{ name }}!", None, BodyFormat::Text), "Hi !");
+    }
+
+    #[test]
+    fn values_are_escaped_in_html_bodies_only() {
+        let source = r#"<a href="{{ unsubscribe_url }}">{{ name }}</a>"#;
+        assert_eq!(
+            render(source, Some("Ursula & Le Guin"), BodyFormat::Html),
+            r#"<a href="https://example.com/unsubscribe?token=a&#38;b">Ursula &#38; Le Guin</a>"#
+        );
+        assert_eq!(
+            render(source, Some("Ursula & Le Guin"), BodyFormat::Text),
+            r#"<a href="https://example.com/unsubscribe?token=a&b">Ursula & Le Guin</a>"#
+        );
+        assert_eq!(
+            render(
+                r#"{{ name | default: "<b>you</b>" }}"#,
+                None,
+                BodyFormat::Html
+            ),
+            "&#60;b&#62;you&#60;/b&#62;"
+        );
+    }
+
+    #[test]
+    fn a_body_without_tags_is_unchanged() {
+        let source = "<p>Plain {curly} body</p>";
+        assert_eq!(render(source, None, BodyFormat::Html), source);
+        assert_eq!(
+            MergeTemplate::parse(source).unwrap(),
+            MergeTemplate::verbatim(source)
+        );
+    }
+
+    #[test]
+    fn unknown_tags_are_rejected() {
+        assert_eq!(
+            MergeTemplate::parse("Hi {{ first_name }}"),
+            Err(MergeTagError::UnknownTag("first_name".into()))
+        );
+        assert_eq!(
+            MergeTemplate::parse("Hi {{}}"),
+            Err(MergeTagError::UnknownTag("".into()))
+        );
+    }
+
+    #[test]
+    fn malformed_tags_are_rejected() {
+        assert_eq!(
+            MergeTemplate::parse("Hi {{ name"),
+            Err(MergeTagError::Unclosed)
+        );
+        for source in [
+            "{{ name | reader }}",
+            "{{ name | default: reader }}",
+            r#"{{ name | default: "reader }}"#,
+        ] {
+            assert_err!(MergeTemplate::parse(source));
+        }
+    }
+}
@@ -1,7 +1,15 @@
 //! src/domain/mod.rs
+mod merge_tags;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
+mod subscription_status;
+mod suppression;
+mod unsubscribe_token;
+pub use merge_tags::{BodyFormat, MergeFields, MergeTag, MergeTagError, MergeTemplate};
 pub use new_subscriber::NewSubscriber;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscription_status::{SubscriptionStatus, TransitionError, record_status, transition};
+pub use suppression::{SuppressionReason, is_suppressed, suppress};
+pub use unsubscribe_token::UnsubscribeToken;
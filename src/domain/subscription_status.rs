@@ -0,0 +1,192 @@
+//! src/domain/subscription_status.rs
+use sqlx::{Executor, Postgres, Transaction};
+use uuid::Uuid;
+
+/// The lifecycle of a subscription.
+///
+/// ```text
+/// pending ──> confirmed ──> unsubscribed ──> pending (re-subscription)
+///    │            │
+///    └────────────┴──> bounced | complained
+/// ```
+///
+/// Any status but `deleted` can move to `deleted`, and `deleted` is final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "subscription_status", rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    /// Waiting for the subscriber to click on the confirmation link.
+    Pending,
+    /// Receives newsletter issues.
+    Confirmed,
+    /// Left the list.
+    Unsubscribed,
+    /// Their mailbox does not accept our emails.
+    Bounced,
+    /// Reported one of our emails as spam.
+    Complained,
+    /// Their details must not be used anymore.
+    Deleted,
+}
+
+impl SubscriptionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::Pending => "pending",
+            SubscriptionStatus::Confirmed => "confirmed",
+            SubscriptionStatus::Unsubscribed => "unsubscribed",
+            SubscriptionStatus::Bounced => "bounced",
+            SubscriptionStatus::Complained => "complained",
+            SubscriptionStatus::Deleted => "deleted",
+        }
+    }
+
+    /// Whether a subscription can go from `self` to `next`.
+    /// Staying in the same status is not a transition.
+    pub fn can_transition_to(self, next: SubscriptionStatus) -> bool {
+        use SubscriptionStatus::*;
+
+        match (self, next) {
+            (Deleted, _) => false,
+            (_, Deleted) => true,
+            (Pending, Confirmed | Unsubscribed | Bounced | Complained) => true,
+            (Confirmed, Unsubscribed | Bounced | Complained) => true,
+            // Somebody who left the list can sign up again - going through
+            // the double opt-in once more.
+            (Unsubscribed, Pending) => true,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for SubscriptionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TransitionError {
+    #[error("A subscription cannot go from `{from}` to `{to}`.")]
+    IllegalTransition {
+        from: SubscriptionStatus,
+        to: SubscriptionStatus,
+    },
+    #[error("There is no subscriber with id {0}.")]
+    UnknownSubscriber(Uuid),
+    #[error("Failed to change the status of a subscription.")]
+    UnexpectedError(#[from] sqlx::Error),
+}
+
+/// Move a subscription to `next`, recording the change in
+/// `subscription_status_history`. Returns the status it was in.
+///
+/// The subscription row is locked until `transaction` is over, therefore
+/// concurrent transitions of the same subscription are serialised.
+#[tracing::instrument(name = "Change the status of a subscription", skip(transaction))]
+pub async fn transition(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    next: SubscriptionStatus,
+) -> Result<SubscriptionStatus, TransitionError> {
+    let current = sqlx::query!(
+        r#"
+        SELECT status AS "status: SubscriptionStatus"
+        FROM subscriptions
+        WHERE id = $1
+        FOR UPDATE
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?
+    .ok_or(TransitionError::UnknownSubscriber(subscriber_id))?
+    .status;
+    if !current.can_transition_to(next) {
+        return Err(TransitionError::IllegalTransition {
+            from: current,
+            to: next,
+        });
+    }
+    let query = sqlx::query!(
+        r#"UPDATE subscriptions SET status = $2 WHERE id = $1"#,
+        subscriber_id,
+        next as SubscriptionStatus
+    );
+    transaction.execute(query).await?;
+    record_status(transaction, subscriber_id, Some(current), next).await?;
+    Ok(current)
+}
+
+/// Append a row to `subscription_status_history`.
+/// `from` is `None` for the status a subscription is created with.
+#[tracing::instrument(skip(transaction))]
+pub async fn record_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    from: Option<SubscriptionStatus>,
+    to: SubscriptionStatus,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO subscription_status_history (subscriber_id, from_status, to_status, changed_at)
+        VALUES ($1, $2, $3, now())
+        "#,
+        subscriber_id,
+        from as Option<SubscriptionStatus>,
+        to as SubscriptionStatus
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionStatus::{self, *};
+
+    const ALL: [SubscriptionStatus; 6] = [
+        Pending,
+        Confirmed,
+        Unsubscribed,
+        Bounced,
+        Complained,
+        Deleted,
+    ];
+
+    #[test]
+    fn a_pending_subscription_can_be_confirmed() {
+        assert!(Pending.can_transition_to(Confirmed));
+    }
+
+    #[test]
+    fn a_confirmed_subscription_cannot_go_back_to_pending() {
+        assert!(!Confirmed.can_transition_to(Pending));
+    }
+
+    #[test]
+    fn an_unsubscribed_subscription_can_subscribe_again() {
+        assert!(Unsubscribed.can_transition_to(Pending));
+        assert!(!Unsubscribed.can_transition_to(Confirmed));
+    }
+
+    #[test]
+    fn a_bounce_or_a_complaint_is_only_followed_by_a_deletion() {
+        for next in ALL {
+            assert_eq!(Bounced.can_transition_to(next), next == Deleted);
+            assert_eq!(Complained.can_transition_to(next), next == Deleted);
+        }
+    }
+
+    #[test]
+    fn deleted_is_final() {
+        for next in ALL {
+            assert!(!Deleted.can_transition_to(next));
+        }
+    }
+
+    #[test]
+    fn staying_in_the_same_status_is_not_a_transition() {
+        for status in ALL {
+            assert!(!status.can_transition_to(status));
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! src/domain/suppression.rs
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+
+/// Why an address ended up on the suppression list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "suppression_reason", rename_all = "snake_case")]
+pub enum SuppressionReason {
+    /// The receiving server rejected our emails for good.
+    HardBounce,
+    /// The recipient reported one of our emails as spam.
+    SpamComplaint,
+}
+
+/// Add `email` to the suppression list.
+/// An address that is already suppressed keeps its original reason.
+#[tracing::instrument(skip(transaction))]
+pub async fn suppress(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    reason: SuppressionReason,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO email_suppressions (email, reason, suppressed_at)
+        VALUES ($1, $2, now())
+        ON CONFLICT (email) DO NOTHING
+        "#,
+        email,
+        reason as SuppressionReason
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// Whether we must refrain from sending emails to `email`.
+#[tracing::instrument(skip(pool))]
+pub async fn is_suppressed(pool: &PgPool, email: &str) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"SELECT email FROM email_suppressions WHERE email = $1"#,
+        email
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.is_some())
+}
@@ -0,0 +1,103 @@
+//! src/domain/unsubscribe_token.rs
+use super::SubscriberEmail;
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, SecretString};
+
+type HmacSha256 = Hmac<sha2::Sha256>;
+
+/// A token identifying a subscriber in an unsubscribe link.
+///
+/// It does not need to be stored anywhere: `<email>.<tag>`, where `email`
+/// is base64-encoded and `tag` is an HMAC of the email, keyed with the
+/// application secret. Only we can forge a valid tag, therefore nobody
+/// can unsubscribe somebody else.
+pub struct UnsubscribeToken(String);
+
+impl UnsubscribeToken {
+    pub fn new(email: &SubscriberEmail, secret: &SecretString) -> Self {
+        let tag = hex::encode(mac(email.as_ref(), secret).finalize().into_bytes());
+        let email = URL_SAFE_NO_PAD.encode(email.as_ref());
+        Self(format!("{}.{}", email, tag))
+    }
+
+    /// Check the token signature, returning the email it was issued for.
+    pub fn verify(token: &str, secret: &SecretString) -> Result<String, String> {
+        let (email, tag) = token
+            .split_once('.')
+            .ok_or_else(|| "The unsubscribe token is malformed.".to_string())?;
+        let email = URL_SAFE_NO_PAD
+            .decode(email)
+            .ok()
+            .and_then(|email| String::from_utf8(email).ok())
+            .ok_or_else(|| "The unsubscribe token is malformed.".to_string())?;
+        let tag =
+            hex::decode(tag).map_err(|_| "The unsubscribe token is malformed.".to_string())?;
+        mac(&email, secret)
+            .verify_slice(&tag)
+            .map_err(|_| "The unsubscribe token is invalid.".to_string())?;
+        Ok(email)
+    }
+}
+
+fn mac(email: &str, secret: &SecretString) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(email.as_bytes());
+    mac
+}
+
+impl AsRef<str> for UnsubscribeToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnsubscribeToken;
+    use crate::domain::SubscriberEmail;
+    use claim::{assert_err, assert_ok_eq};
+    use secrecy::SecretString;
+
+    fn secret(s: &str) -> SecretString {
+        SecretString::new(s.into())
+    }
+
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse("ursula@domain.com".into()).unwrap()
+    }
+
+    #[test]
+    fn a_token_is_verified_with_the_secret_it_was_issued_with() {
+        let token = UnsubscribeToken::new(&email(), &secret("a-secret"));
+        assert_ok_eq!(
+            UnsubscribeToken::verify(token.as_ref(), &secret("a-secret")),
+            "ursula@domain.com".to_string()
+        );
+    }
+
+    #[test]
+    fn a_token_is_rejected_with_another_secret() {
+        let token = UnsubscribeToken::new(&email(), &secret("a-secret"));
+        assert_err!(UnsubscribeToken::verify(
+            token.as_ref(),
+            &secret("another-secret")
+        ));
+    }
+
+    #[test]
+    fn a_token_for_another_email_is_rejected() {
+        let token = UnsubscribeToken::new(&email(), &secret("a-secret"));
+        let (_, tag) = token.as_ref().split_once('.').unwrap();
+        let forged = format!("{}.{}", "bWFsbG9yeUBkb21haW4uY29t", tag);
+        assert_err!(UnsubscribeToken::verify(&forged, &secret("a-secret")));
+    }
+
+    #[test]
+    fn a_malformed_token_is_rejected() {
+        for token in ["", "no-separator", "!!!.00", "dXJzdWxh.not-hex"] {
+            assert_err!(UnsubscribeToken::verify(token, &secret("a-secret")));
+        }
+    }
+}
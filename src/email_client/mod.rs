@@ -0,0 +1,154 @@
+//! src/email_client/mod.rs
+mod outbox;
+mod postmark;
+mod smtp;
+pub use outbox::OutboxTransport;
+pub use postmark::PostmarkTransport;
+pub use smtp::{SmtpTls, SmtpTransport};
+
+use crate::domain::SubscriberEmail;
+use crate::routes::error_chain_fmt;
+
+/// An email, as handed over to an `EmailTransport`.
+pub struct Email<'a> {
+    pub from: &'a SubscriberEmail,
+    pub to: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_body: &'a str,
+    pub text_body: &'a str,
+    /// Where the recipient can unsubscribe with a single click.
+    /// Sent as RFC 8058 `List-Unsubscribe`/`List-Unsubscribe-Post` headers.
+    pub unsubscribe_url: Option<&'a str>,
+}
+
+impl Email<'_> {
+    /// The extra headers to send along with the email.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        match self.unsubscribe_url {
+            Some(url) => vec![
+                ("List-Unsubscribe", format!("<{}>", url)),
+                (
+                    "List-Unsubscribe-Post",
+                    "List-Unsubscribe=One-Click".to_string(),
+                ),
+            ],
+            None => vec![],
+        }
+    }
+}
+
+/// An email the transport accepted.
+#[derive(Debug, Default)]
+pub struct SentEmail {
+    /// The id the provider gave the message, if it has one: bounces are
+    /// reported by message.
+    pub message_id: Option<String>,
+}
+
+/// The way an email leaves the application: an HTTP API, an SMTP relay,
+/// a folder on disk...
+#[async_trait::async_trait]
+pub trait EmailTransport: std::fmt::Debug + Send + Sync {
+    async fn send(&self, email: &Email<'_>) -> Result<(), SendEmailError>;
+
+    /// The largest number of emails `send_batch` accepts in a single call.
+    fn max_batch_size(&self) -> usize {
+        1
+    }
+
+    /// Send several emails at once, returning one outcome per email,
+    /// in the same order as `emails`.
+    ///
+    /// Transports without a batch API send the emails one by one.
+    async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<Result<SentEmail, SendEmailError>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for email in emails {
+            outcomes.push(self.send(email).await.map(|()| SentEmail::default()));
+        }
+        outcomes
+    }
+}
+
+#[derive(Debug)]
+pub struct EmailClient {
+    sender: SubscriberEmail,
+    transport: Box<dyn EmailTransport>,
+}
+
+#[derive(thiserror::Error)]
+pub enum SendEmailError {
+    /// Worth retrying: the provider timed out, was unreachable,
+    /// throttled us or failed on its side.
+    #[error("Failed to send an email, the request can be retried.")]
+    Transient(#[source] anyhow::Error),
+    /// Retrying will not help: the provider rejected the email itself
+    /// (e.g. an invalid recipient).
+    #[error("Failed to send an email, the request was rejected.")]
+    Permanent(#[source] anyhow::Error),
+}
+
+impl SendEmailError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, SendEmailError::Transient(_))
+    }
+}
+
+impl std::fmt::Debug for SendEmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl EmailClient {
+    pub fn new(sender: SubscriberEmail, transport: impl EmailTransport + 'static) -> Self {
+        Self {
+            sender,
+            transport: Box::new(transport),
+        }
+    }
+
+    /// Build an email from our sender address, e.g. to hand it over to
+    /// `send_batch`.
+    pub fn compose<'a>(
+        &'a self,
+        recipient: &'a SubscriberEmail,
+        subject: &'a str,
+        html_content: &'a str,
+        text_content: &'a str,
+    ) -> Email<'a> {
+        Email {
+            from: &self.sender,
+            to: recipient,
+            subject,
+            html_body: html_content,
+            text_body: text_content,
+            unsubscribe_url: None,
+        }
+    }
+
+    pub async fn send_email(
+        &self,
+        recipient: &SubscriberEmail,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<(), SendEmailError> {
+        let email = self.compose(recipient, subject, html_content, text_content);
+        self.transport.send(&email).await
+    }
+
+    /// The largest batch the underlying transport sends in a single call.
+    pub fn max_batch_size(&self) -> usize {
+        self.transport.max_batch_size()
+    }
+
+    /// Send `emails`, as many at a time as the transport allows, returning
+    /// one outcome per email, in the same order as `emails`.
+    pub async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<Result<SentEmail, SendEmailError>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for chunk in emails.chunks(self.max_batch_size().max(1)) {
+            outcomes.extend(self.transport.send_batch(chunk).await);
+        }
+        outcomes
+    }
+}
@@ -0,0 +1,72 @@
+//! src/email_client/outbox.rs
+use super::smtp::build_message;
+use super::{Email, EmailTransport, SendEmailError};
+use lettre::{AsyncFileTransport, AsyncTransport, Tokio1Executor};
+use std::path::PathBuf;
+
+/// Writes every email as an RFC 5322 `.eml` file in a local directory.
+///
+/// Meant for development: emails can be opened with any mail client
+/// without a mock server or a provider account.
+#[derive(Debug)]
+pub struct OutboxTransport {
+    directory: PathBuf,
+}
+
+impl OutboxTransport {
+    pub fn new(directory: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
+        let directory = directory.into();
+        std::fs::create_dir_all(&directory)?;
+        Ok(Self { directory })
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for OutboxTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<(), SendEmailError> {
+        let message = build_message(email)?;
+        AsyncFileTransport::<Tokio1Executor>::new(&self.directory)
+            .send(message)
+            .await
+            .map_err(|e| SendEmailError::Transient(e.into()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, OutboxTransport};
+    use claim::assert_ok;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn send_email_writes_an_eml_file_to_the_outbox() {
+        let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let email_client = EmailClient::new(
+            SubscriberEmail::parse("sender@example.com".into()).unwrap(),
+            OutboxTransport::new(&directory).unwrap(),
+        );
+
+        let result = email_client
+            .send_email(
+                &SubscriberEmail::parse("ursula@example.com".into()).unwrap(),
+                "Welcome!",
+                "<p>Hello</p>",
+                "Hello",
+            )
+            .await;
+        assert_ok!(result);
+
+        let files: Vec<_> = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].extension().unwrap(), "eml");
+        let eml = std::fs::read_to_string(&files[0]).unwrap();
+        assert!(eml.contains("Subject: Welcome!"));
+        assert!(eml.contains("To: ursula@example.com"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
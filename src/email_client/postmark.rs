@@ -0,0 +1,572 @@
+//! src/email_client/postmark.rs
+use super::{Email, EmailTransport, SendEmailError, SentEmail};
+use reqwest::{Client, StatusCode};
+use secrecy::{ExposeSecret, SecretString};
+use std::sync::Arc;
+
+/// Postmark accepts at most 500 messages per call to `/email/batch`.
+const MAX_BATCH_SIZE: usize = 500;
+
+/// Postmark error codes, reported per message by `/email/batch`,
+/// that are worth retrying. 405 means the account ran out of credits: the
+/// message itself is fine and goes through once the account is topped up,
+/// so it is retried with the usual backoff instead of being dead-lettered.
+const TRANSIENT_ERROR_CODES: &[i64] = &[405];
+
+/// Sends emails through Postmark's HTTP API.
+#[derive(Debug)]
+pub struct PostmarkTransport {
+    http_client: Client,
+    base_url: String,
+    authorization_token: SecretString,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    headers: Vec<MessageHeader>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct MessageHeader {
+    name: &'static str,
+    value: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchResponseEntry {
+    error_code: i64,
+    message: String,
+    /// Missing when the message was rejected.
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+}
+
+fn is_transient(e: &reqwest::Error) -> bool {
+    match e.status() {
+        Some(status) => status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+        // No status code: we never got a response back
+        // (timeout, connection failure, ...).
+        None => e.is_timeout() || e.is_connect() || e.is_request(),
+    }
+}
+
+impl From<reqwest::Error> for SendEmailError {
+    fn from(e: reqwest::Error) -> Self {
+        if is_transient(&e) {
+            SendEmailError::Transient(e.into())
+        } else {
+            SendEmailError::Permanent(e.into())
+        }
+    }
+}
+
+/// The whole batch request failed: every message in it failed the same way.
+fn batch_failed(e: reqwest::Error, n_messages: usize) -> Vec<Result<SentEmail, SendEmailError>> {
+    let transient = is_transient(&e);
+    let e = Arc::new(e);
+    (0..n_messages)
+        .map(|_| {
+            let e = anyhow::Error::new(e.clone());
+            Err(if transient {
+                SendEmailError::Transient(e)
+            } else {
+                SendEmailError::Permanent(e)
+            })
+        })
+        .collect()
+}
+
+impl BatchResponseEntry {
+    fn into_outcome(self) -> Result<SentEmail, SendEmailError> {
+        if self.error_code == 0 {
+            return Ok(SentEmail {
+                message_id: self.message_id,
+            });
+        }
+        let e = anyhow::anyhow!(
+            "Postmark rejected the message (error code {}): {}",
+            self.error_code,
+            self.message
+        );
+        if TRANSIENT_ERROR_CODES.contains(&self.error_code) {
+            Err(SendEmailError::Transient(e))
+        } else {
+            Err(SendEmailError::Permanent(e))
+        }
+    }
+}
+
+impl PostmarkTransport {
+    pub fn new(
+        base_url: String,
+        authorization_token: SecretString,
+        timeout: std::time::Duration,
+    ) -> Self {
+        let http_client = Client::builder().timeout(timeout).build().unwrap();
+        Self {
+            http_client,
+            base_url,
+            authorization_token,
+        }
+    }
+}
+
+impl<'a> From<&Email<'a>> for SendEmailRequest<'a> {
+    fn from(email: &Email<'a>) -> Self {
+        Self {
+            from: email.from.as_ref(),
+            to: email.to.as_ref(),
+            subject: email.subject,
+            html_body: email.html_body,
+            text_body: email.text_body,
+            headers: email
+                .headers()
+                .into_iter()
+                .map(|(name, value)| MessageHeader { name, value })
+                .collect(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for PostmarkTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<(), SendEmailError> {
+        let url = format!("{}/email", self.base_url);
+        let request_body = SendEmailRequest::from(email);
+        self.http_client
+            .post(&url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    fn max_batch_size(&self) -> usize {
+        MAX_BATCH_SIZE
+    }
+
+    async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<Result<SentEmail, SendEmailError>> {
+        let url = format!("{}/email/batch", self.base_url);
+        let request_body: Vec<_> = emails.iter().map(SendEmailRequest::from).collect();
+        let response = self
+            .http_client
+            .post(&url)
+            .header(
+                "X-Postmark-Server-Token",
+                self.authorization_token.expose_secret(),
+            )
+            .json(&request_body)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        let entries: Vec<BatchResponseEntry> = match response {
+            Ok(response) => match response.json().await {
+                Ok(entries) => entries,
+                Err(e) => return batch_failed(e, emails.len()),
+            },
+            Err(e) => return batch_failed(e, emails.len()),
+        };
+        // Postmark answers with one entry per message, in the same order.
+        // If that's not the case, we cannot tell which messages went through:
+        // retrying could send some of them twice, so they are left to an
+        // operator instead.
+        if entries.len() != emails.len() {
+            return (0..emails.len())
+                .map(|_| {
+                    Err(SendEmailError::Permanent(anyhow::anyhow!(
+                        "Postmark returned {} results for a batch of {} messages",
+                        entries.len(),
+                        emails.len()
+                    )))
+                })
+                .collect();
+        }
+        entries
+            .into_iter()
+            .map(BatchResponseEntry::into_outcome)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::SubscriberEmail;
+    use crate::email_client::{EmailClient, PostmarkTransport};
+    use claim::{assert_err, assert_ok};
+    use fake::Fake;
+    use fake::faker::internet::en::SafeEmail;
+    use fake::faker::lorem::en::{Paragraph, Sentence};
+    use secrecy::SecretString;
+    use uuid::Uuid;
+    use wiremock::{
+        Mock, MockServer, Request, ResponseTemplate,
+        matchers::{any, header, header_exists, method, path},
+    };
+
+    struct SendEmailBodyMatcher;
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<serde_json::Value, _> = serde_json::from_slice(&request.body);
+            if let Ok(body) = result {
+                // Check that all the mandatory fields are populated
+                // without inspecting the field values
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                // If parsing failed, do not match the request
+                false
+            }
+        }
+    }
+
+    struct SendBatchBodyMatcher(usize);
+    impl wiremock::Match for SendBatchBodyMatcher {
+        fn matches(&self, request: &Request) -> bool {
+            let result: Result<Vec<serde_json::Value>, _> = serde_json::from_slice(&request.body);
+            match result {
+                Ok(messages) => {
+                    messages.len() == self.0
+                        && messages.iter().all(|body| {
+                            body.get("From").is_some()
+                                && body.get("To").is_some()
+                                && body.get("Subject").is_some()
+                                && body.get("HtmlBody").is_some()
+                                && body.get("TextBody").is_some()
+                        })
+                }
+                Err(_) => false,
+            }
+        }
+    }
+
+    /// A `/email/batch` response where every message was accepted.
+    fn batch_accepted(n_messages: usize) -> serde_json::Value {
+        (0..n_messages)
+            .map(|_| {
+                serde_json::json!({
+                    "ErrorCode": 0,
+                    "Message": "OK",
+                    "MessageID": Uuid::new_v4(),
+                    "SubmittedAt": "2025-07-15T10:02:49.5426838-04:00",
+                    "To": "receiver@example.com"
+                })
+            })
+            .collect()
+    }
+
+    /// Generate a random email subject
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+    /// Generate a random email content
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+    /// Generate a random subscriber email
+    fn email() -> SubscriberEmail {
+        SubscriberEmail::parse(SafeEmail().fake()).unwrap()
+    }
+    /// Get a test instance of `EmailClient`.
+    fn email_client(base_url: String) -> EmailClient {
+        EmailClient::new(
+            email(),
+            PostmarkTransport::new(
+                base_url,
+                SecretString::new(Uuid::new_v4().to_string().into_boxed_str()),
+                std::time::Duration::from_millis(200),
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_expected_request() {
+        let mock_server = MockServer::start().await;
+        let email_client: EmailClient = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        let mock_server = MockServer::start().await;
+        let email_client: EmailClient = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        assert_ok!(result);
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client: EmailClient = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        assert_err!(result);
+    }
+
+    #[tokio::test]
+    async fn a_500_is_a_retryable_failure() {
+        let mock_server = MockServer::start().await;
+        let email_client: EmailClient = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        assert!(assert_err!(result).is_retryable());
+    }
+
+    #[tokio::test]
+    async fn a_429_is_a_retryable_failure() {
+        let mock_server = MockServer::start().await;
+        let email_client: EmailClient = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        assert!(assert_err!(result).is_retryable());
+    }
+
+    #[tokio::test]
+    async fn a_422_is_a_permanent_failure() {
+        let mock_server = MockServer::start().await;
+        let email_client: EmailClient = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let result = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+        assert!(!assert_err!(result).is_retryable());
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client: EmailClient = email_client(mock_server.uri());
+
+        let response = ResponseTemplate::new(200)
+            // 3 minutes!
+            .set_delay(std::time::Duration::from_secs(180));
+
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert!(assert_err!(outcome).is_retryable());
+    }
+
+    #[tokio::test]
+    async fn send_batch_sends_expected_request() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .and(SendBatchBodyMatcher(3))
+            .respond_with(ResponseTemplate::new(200).set_body_json(batch_accepted(3)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (subject, content) = (subject(), content());
+        let recipients = [email(), email(), email()];
+        let emails: Vec<_> = recipients
+            .iter()
+            .map(|r| email_client.compose(r, &subject, &content, &content))
+            .collect();
+        let outcomes = email_client.send_batch(&emails).await;
+        assert_eq!(outcomes.len(), 3);
+        assert!(outcomes.iter().all(|o| o.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_large_batches() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .and(SendBatchBodyMatcher(500))
+            .respond_with(ResponseTemplate::new(200).set_body_json(batch_accepted(500)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email/batch"))
+            .and(SendBatchBodyMatcher(1))
+            .respond_with(ResponseTemplate::new(200).set_body_json(batch_accepted(1)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (subject, content) = (subject(), content());
+        let recipients: Vec<_> = (0..501).map(|_| email()).collect();
+        let emails: Vec<_> = recipients
+            .iter()
+            .map(|r| email_client.compose(r, &subject, &content, &content))
+            .collect();
+        let outcomes = email_client.send_batch(&emails).await;
+        assert_eq!(outcomes.len(), 501);
+        assert!(outcomes.iter().all(|o| o.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn send_batch_maps_error_codes_to_each_recipient() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        let message_id = Uuid::new_v4();
+        let response = serde_json::json!([
+            {
+                "ErrorCode": 0,
+                "Message": "OK",
+                "MessageID": message_id,
+                "SubmittedAt": "2025-07-15T10:02:49.5426838-04:00",
+                "To": "first@example.com"
+            },
+            {
+                "ErrorCode": 406,
+                "Message": "You tried to send to a recipient that has been marked as inactive."
+            },
+            {
+                "ErrorCode": 405,
+                "Message": "Not allowed to send: you have run out of credits."
+            }
+        ]);
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(response))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (subject, content) = (subject(), content());
+        let recipients = [email(), email(), email()];
+        let emails: Vec<_> = recipients
+            .iter()
+            .map(|r| email_client.compose(r, &subject, &content, &content))
+            .collect();
+        let mut outcomes = email_client.send_batch(&emails).await.into_iter();
+        let sent = assert_ok!(outcomes.next().unwrap());
+        assert_eq!(sent.message_id, Some(message_id.to_string()));
+        assert!(!assert_err!(outcomes.next().unwrap()).is_retryable());
+        assert!(assert_err!(outcomes.next().unwrap()).is_retryable());
+    }
+
+    #[tokio::test]
+    async fn a_500_fails_every_message_in_the_batch_as_retryable() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (subject, content) = (subject(), content());
+        let recipients = [email(), email()];
+        let emails: Vec<_> = recipients
+            .iter()
+            .map(|r| email_client.compose(r, &subject, &content, &content))
+            .collect();
+        let outcomes = email_client.send_batch(&emails).await;
+        assert_eq!(outcomes.len(), 2);
+        for outcome in outcomes {
+            assert!(assert_err!(outcome).is_retryable());
+        }
+    }
+
+    #[tokio::test]
+    async fn a_batch_response_of_the_wrong_size_fails_every_message_for_good() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(batch_accepted(1)))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (subject, content) = (subject(), content());
+        let recipients = [email(), email()];
+        let emails: Vec<_> = recipients
+            .iter()
+            .map(|r| email_client.compose(r, &subject, &content, &content))
+            .collect();
+        let outcomes = email_client.send_batch(&emails).await;
+        assert_eq!(outcomes.len(), 2);
+        for outcome in outcomes {
+            assert!(!assert_err!(outcome).is_retryable());
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! src/email_client/smtp.rs
+use super::{Email, EmailTransport, SendEmailError};
+use lettre::message::header::{HeaderName, HeaderValue};
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use secrecy::{ExposeSecret, SecretString};
+
+/// How the connection to the SMTP relay is secured.
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain connection upgraded with `STARTTLS` (usually port 587).
+    Starttls,
+    /// TLS from the very first byte (usually port 465).
+    Tls,
+    /// No encryption at all - only for local relays (e.g. MailHog).
+    None,
+}
+
+/// Sends emails through an SMTP relay.
+#[derive(Debug)]
+pub struct SmtpTransport {
+    mailer: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpTransport {
+    pub fn new(
+        host: &str,
+        port: u16,
+        tls: SmtpTls,
+        credentials: Option<(String, SecretString)>,
+        timeout: std::time::Duration,
+    ) -> Result<Self, anyhow::Error> {
+        let mut builder = match tls {
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        }
+        .port(port)
+        .timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(
+                username,
+                password.expose_secret().to_owned(),
+            ));
+        }
+        Ok(Self {
+            mailer: builder.build(),
+        })
+    }
+}
+
+/// Build the RFC 5322 message for `email`, with both a plain text
+/// and an HTML alternative.
+pub(super) fn build_message(email: &Email<'_>) -> Result<Message, SendEmailError> {
+    let from: Mailbox = email
+        .from
+        .as_ref()
+        .parse()
+        .map_err(|e| SendEmailError::Permanent(anyhow::Error::new(e)))?;
+    let to: Mailbox = email
+        .to
+        .as_ref()
+        .parse()
+        .map_err(|e| SendEmailError::Permanent(anyhow::Error::new(e)))?;
+    let mut builder = Message::builder().from(from).to(to).subject(email.subject);
+    for (name, value) in email.headers() {
+        builder = builder.raw_header(HeaderValue::new(
+            HeaderName::new_from_ascii_str(name),
+            value,
+        ));
+    }
+    builder
+        .multipart(MultiPart::alternative_plain_html(
+            email.text_body.to_owned(),
+            email.html_body.to_owned(),
+        ))
+        .map_err(|e| SendEmailError::Permanent(anyhow::Error::new(e)))
+}
+
+impl From<lettre::transport::smtp::Error> for SendEmailError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        // 4xx replies and connection-level failures are worth retrying,
+        // 5xx replies (e.g. an unknown mailbox) are not.
+        if e.is_permanent() {
+            SendEmailError::Permanent(e.into())
+        } else {
+            SendEmailError::Transient(e.into())
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmailTransport for SmtpTransport {
+    async fn send(&self, email: &Email<'_>) -> Result<(), SendEmailError> {
+        let message = build_message(email)?;
+        self.mailer.send(message).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,501 @@
+//! src/issue_delivery_worker.rs
+use crate::domain::{
+    BodyFormat, MergeFields, MergeTag, MergeTemplate, SubscriberEmail, UnsubscribeToken,
+};
+use crate::email_client::EmailClient;
+use crate::newsletter_issues::DeliveryStatus;
+use rand::Rng;
+use secrecy::SecretString;
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::Span;
+use uuid::Uuid;
+
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// How failed deliveries are retried.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: i16,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with "equal jitter": the delay before the next
+    /// attempt is drawn uniformly from `[d / 2, d]`, where
+    /// `d = min(base_delay * 2^n_retries, max_delay)`.
+    ///
+    /// The jitter spreads out retries of deliveries that failed together,
+    /// e.g. during a provider outage.
+    pub fn backoff(&self, n_retries: i16) -> Duration {
+        let factor = 2u32.saturating_pow(n_retries.max(0) as u32);
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        let half = delay / 2;
+        half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
+
+/// Builds the unsubscribe link added to every newsletter issue.
+#[derive(Clone)]
+pub struct UnsubscribeLinks {
+    base_url: String,
+    hmac_secret: SecretString,
+}
+
+impl UnsubscribeLinks {
+    pub fn new(base_url: String, hmac_secret: SecretString) -> Self {
+        Self {
+            base_url,
+            hmac_secret,
+        }
+    }
+
+    pub fn for_recipient(&self, email: &SubscriberEmail) -> String {
+        let token = UnsubscribeToken::new(email, &self.hmac_secret);
+        format!(
+            "{}/subscriptions/unsubscribe?token={}",
+            self.base_url,
+            token.as_ref()
+        )
+    }
+}
+
+/// A background task draining `issue_delivery_queue`.
+///
+/// Several workers can run against the same database: each task is locked
+/// with `FOR UPDATE SKIP LOCKED` while it is being processed, therefore
+/// two workers never pick up the same delivery.
+pub struct IssueDeliveryWorker {
+    pool: PgPool,
+    email_client: EmailClient,
+    retry_policy: RetryPolicy,
+    unsubscribe_links: UnsubscribeLinks,
+    idle_backoff: Duration,
+}
+
+impl IssueDeliveryWorker {
+    pub fn new(
+        pool: PgPool,
+        email_client: EmailClient,
+        retry_policy: RetryPolicy,
+        unsubscribe_links: UnsubscribeLinks,
+        idle_backoff: Duration,
+    ) -> Self {
+        Self {
+            pool,
+            email_client,
+            retry_policy,
+            unsubscribe_links,
+            idle_backoff,
+        }
+    }
+
+    pub async fn run_until_stopped(self) -> Result<(), anyhow::Error> {
+        loop {
+            match try_execute_task(
+                &self.pool,
+                &self.email_client,
+                &self.retry_policy,
+                &self.unsubscribe_links,
+            )
+            .await
+            {
+                Ok(ExecutionOutcome::EmptyQueue) => {
+                    tokio::time::sleep(self.idle_backoff).await;
+                }
+                Err(_) => {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+                Ok(ExecutionOutcome::TaskCompleted) => {}
+            }
+        }
+    }
+}
+
+/// Deliver a batch of queued emails - as large as `email_client` can send
+/// in a single call.
+///
+/// Every recipient gets their own unsubscribe link, both in the body of the
+/// issue and in the `List-Unsubscribe` header, and their own values for the
+/// merge tags of the issue. Every task in the batch is
+/// then settled on its own, according to the outcome reported for its
+/// recipient, and its outcome recorded in `issue_deliveries`.
+#[tracing::instrument(skip_all, fields(n_tasks = tracing::field::Empty), err)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    retry_policy: &RetryPolicy,
+    unsubscribe_links: &UnsubscribeLinks,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let (mut transaction, tasks) = dequeue_tasks(pool, email_client.max_batch_size()).await?;
+    if tasks.is_empty() {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    }
+    Span::current().record("n_tasks", tasks.len());
+
+    let issues: HashMap<Uuid, _> = get_issues(pool, &tasks)
+        .await?
+        .into_iter()
+        .map(|(issue_id, issue)| {
+            let bodies = issue.bodies();
+            (issue_id, (issue, bodies))
+        })
+        .collect();
+    let names = get_subscriber_names(pool, &tasks).await?;
+    let mut deliveries = Vec::with_capacity(tasks.len());
+    for task in &tasks {
+        let Some(issue) = issues.get(&task.newsletter_issue_id) else {
+            tracing::error!(
+                newsletter_issue_id = %task.newsletter_issue_id,
+                subscriber_email = %task.subscriber_email,
+                "Skipping a delivery of a newsletter issue that could not be found",
+            );
+            dead_letter_task(
+                &mut transaction,
+                task,
+                "The newsletter issue could not be found.",
+            )
+            .await?;
+            continue;
+        };
+        match SubscriberEmail::parse(task.subscriber_email.clone()) {
+            Ok(email) => deliveries.push((task, email, issue)),
+            Err(e) => {
+                tracing::error!(
+                    error.message = %e,
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    "Skipping a confirmed subscriber. \
+                    Their stored contact details are invalid",
+                );
+                dead_letter_task(&mut transaction, task, &e).await?;
+            }
+        }
+    }
+    let contents: Vec<_> = deliveries
+        .iter()
+        .map(|(task, email, (_, (html_body, text_body)))| {
+            let unsubscribe_url = unsubscribe_links.for_recipient(email);
+            let fields = MergeFields {
+                name: names.get(&task.subscriber_email).map(String::as_str),
+                email: email.as_ref(),
+                unsubscribe_url: &unsubscribe_url,
+            };
+            // Bodies that place the link themselves get no footer.
+            let mut html_content = html_body.render(&fields, BodyFormat::Html);
+            if !html_body.uses(MergeTag::UnsubscribeUrl) {
+                html_content.push_str(&format!(
+                    "\n<p><a href=\"{}\">Unsubscribe</a></p>",
+                    htmlescape::encode_minimal(&unsubscribe_url)
+                ));
+            }
+            let mut text_content = text_body.render(&fields, BodyFormat::Text);
+            if !text_body.uses(MergeTag::UnsubscribeUrl) {
+                text_content.push_str(&format!("\n\nUnsubscribe: {}", unsubscribe_url));
+            }
+            (html_content, text_content, unsubscribe_url)
+        })
+        .collect();
+    let emails: Vec<_> = deliveries
+        .iter()
+        .zip(&contents)
+        .map(
+            |((_, email, (issue, _)), (html_content, text_content, unsubscribe_url))| {
+                let mut email =
+                    email_client.compose(email, &issue.title, html_content, text_content);
+                email.unsubscribe_url = Some(unsubscribe_url);
+                email
+            },
+        )
+        .collect();
+    let outcomes = email_client.send_batch(&emails).await;
+
+    for ((task, _, _), outcome) in deliveries.iter().zip(outcomes) {
+        match outcome {
+            Ok(sent) => {
+                delete_task(&mut transaction, task).await?;
+                settle_delivery(
+                    &mut transaction,
+                    task,
+                    DeliveryStatus::Sent,
+                    None,
+                    sent.message_id.as_deref(),
+                )
+                .await?;
+            }
+            Err(e) if e.is_retryable() && task.n_retries < retry_policy.max_retries => {
+                let delay = retry_policy.backoff(task.n_retries);
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    n_retries = task.n_retries,
+                    retry_in = ?delay,
+                    "Failed to deliver issue to a confirmed subscriber. \
+                    Retrying later.",
+                );
+                reschedule_task(&mut transaction, task, delay).await?;
+            }
+            Err(e) => {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    newsletter_issue_id = %task.newsletter_issue_id,
+                    subscriber_email = %task.subscriber_email,
+                    n_retries = task.n_retries,
+                    "Failed to deliver issue to a confirmed subscriber. \
+                    Moving it to the dead letters.",
+                );
+                dead_letter_task(&mut transaction, task, &format!("{:?}", e)).await?;
+            }
+        }
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct DeliveryTask {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+}
+
+/// Lock up to `batch_size` due tasks. The locks are held until the returned
+/// transaction is committed.
+#[tracing::instrument(skip(pool))]
+async fn dequeue_tasks(
+    pool: &PgPool,
+    batch_size: usize,
+) -> Result<(PgTransaction, Vec<DeliveryTask>), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let tasks = sqlx::query_as!(
+        DeliveryTask,
+        r#"
+        SELECT newsletter_issue_id, subscriber_email, n_retries
+        FROM issue_delivery_queue
+        WHERE execute_after <= now()
+        FOR UPDATE
+        SKIP LOCKED
+        LIMIT $1
+        "#,
+        i64::try_from(batch_size)?
+    )
+    .fetch_all(&mut *transaction)
+    .await?;
+    Ok((transaction, tasks))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn reschedule_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $3)
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        delay.as_secs_f64()
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn dead_letter_task(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    error: &str,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_dead_letters (
+            newsletter_issue_id,
+            subscriber_email,
+            n_retries,
+            last_error,
+            failed_at
+        )
+        VALUES ($1, $2, $3, $4, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET
+            n_retries = EXCLUDED.n_retries,
+            last_error = EXCLUDED.last_error,
+            failed_at = EXCLUDED.failed_at
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        task.n_retries,
+        error
+    );
+    transaction.execute(query).await?;
+    delete_task(transaction, task).await?;
+    settle_delivery(transaction, task, DeliveryStatus::Failed, Some(error), None).await
+}
+
+#[tracing::instrument(skip(transaction, task, error))]
+async fn settle_delivery(
+    transaction: &mut PgTransaction,
+    task: &DeliveryTask,
+    status: DeliveryStatus,
+    error: Option<&str>,
+    message_id: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    let query = sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET status = $3, last_error = $4, message_id = $5, settled_at = now()
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        status as DeliveryStatus,
+        error,
+        message_id
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+struct NewsletterIssue {
+    newsletter_issue_id: Uuid,
+    title: String,
+    text_content: String,
+    html_content: String,
+}
+
+impl NewsletterIssue {
+    /// The HTML and text bodies, ready to be filled in for each recipient.
+    ///
+    /// Issues published before merge tags were checked may not parse: they
+    /// are sent as they were written.
+    fn bodies(&self) -> (MergeTemplate, MergeTemplate) {
+        let parse = |content: &str| {
+            MergeTemplate::parse(content).unwrap_or_else(|e| {
+                tracing::warn!(
+                    error.message = %e,
+                    newsletter_issue_id = %self.newsletter_issue_id,
+                    "Sending a newsletter issue without filling in its merge tags",
+                );
+                MergeTemplate::verbatim(content)
+            })
+        };
+        (parse(&self.html_content), parse(&self.text_content))
+    }
+}
+
+/// Fetch the issues `tasks` refer to, keyed by their id.
+#[tracing::instrument(skip_all)]
+async fn get_issues(
+    pool: &PgPool,
+    tasks: &[DeliveryTask],
+) -> Result<HashMap<Uuid, NewsletterIssue>, anyhow::Error> {
+    let issue_ids: Vec<Uuid> = tasks.iter().map(|t| t.newsletter_issue_id).collect();
+    let issues = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT newsletter_issue_id, title, text_content, html_content
+        FROM newsletter_issues
+        WHERE
+            newsletter_issue_id = ANY($1)
+        "#,
+        &issue_ids
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(issues
+        .into_iter()
+        .map(|issue| (issue.newsletter_issue_id, issue))
+        .collect())
+}
+
+/// Fetch the names of the recipients of `tasks`, keyed by their email.
+#[tracing::instrument(skip_all)]
+async fn get_subscriber_names(
+    pool: &PgPool,
+    tasks: &[DeliveryTask],
+) -> Result<HashMap<String, String>, anyhow::Error> {
+    let emails: Vec<String> = tasks.iter().map(|t| t.subscriber_email.clone()).collect();
+    let subscribers = sqlx::query!(
+        r#"
+        SELECT email, name
+        FROM subscriptions
+        WHERE
+            email = ANY($1)
+        "#,
+        &emails
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(subscribers.into_iter().map(|s| (s.email, s.name)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetryPolicy;
+    use std::time::Duration;
+
+    fn retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_secs(30),
+            max_delay: Duration::from_secs(600),
+        }
+    }
+
+    #[test]
+    fn backoff_grows_exponentially() {
+        let policy = retry_policy();
+        for n_retries in 0..4 {
+            let upper = Duration::from_secs(30 * 2u64.pow(n_retries as u32));
+            let delay = policy.backoff(n_retries);
+            assert!(delay >= upper / 2 && delay <= upper, "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped_at_max_delay() {
+        let policy = retry_policy();
+        for n_retries in [5, 10, 100, i16::MAX] {
+            let delay = policy.backoff(n_retries);
+            assert!(delay >= policy.max_delay / 2 && delay <= policy.max_delay);
+        }
+    }
+}
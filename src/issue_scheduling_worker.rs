@@ -0,0 +1,72 @@
+//! src/issue_scheduling_worker.rs
+use crate::newsletter_issues::enqueue_delivery_tasks;
+use sqlx::{Executor, PgPool};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// A background task that publishes the scheduled issues once they are due.
+pub struct IssueSchedulingWorker {
+    pool: PgPool,
+    poll_interval: Duration,
+}
+
+impl IssueSchedulingWorker {
+    pub fn new(pool: PgPool, poll_interval: Duration) -> Self {
+        Self {
+            pool,
+            poll_interval,
+        }
+    }
+
+    pub async fn run_until_stopped(self) -> Result<(), anyhow::Error> {
+        loop {
+            tokio::time::sleep(self.poll_interval).await;
+            // Failures are logged by `publish_due_issues`,
+            // we will try again at the next round.
+            let _ = publish_due_issues(&self.pool).await;
+        }
+    }
+}
+
+/// Publish the scheduled issues that are due, queuing their delivery as
+/// publishing from the admin panel does. Returns how many were published.
+///
+/// It is safe to run on several instances at once: the issues are locked
+/// until they are published, and those locked by another instance are
+/// skipped, so that each issue is delivered once.
+#[tracing::instrument(skip(pool), err)]
+pub async fn publish_due_issues(pool: &PgPool) -> Result<u64, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let issue_ids: Vec<Uuid> = sqlx::query!(
+        r#"
+        SELECT newsletter_issue_id
+        FROM newsletter_issues
+        WHERE published_at IS NULL AND scheduled_for <= now()
+        FOR UPDATE SKIP LOCKED
+        "#
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.newsletter_issue_id)
+    .collect();
+    for issue_id in &issue_ids {
+        transaction
+            .execute(sqlx::query!(
+                r#"
+                UPDATE newsletter_issues
+                SET published_at = now(), updated_at = now()
+                WHERE newsletter_issue_id = $1
+                "#,
+                issue_id
+            ))
+            .await?;
+        enqueue_delivery_tasks(&mut transaction, *issue_id).await?;
+    }
+    transaction.commit().await?;
+    let n_issues = issue_ids.len() as u64;
+    if n_issues > 0 {
+        tracing::info!(n_issues, "Published scheduled newsletter issues.");
+    }
+    Ok(n_issues)
+}
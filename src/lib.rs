@@ -1,10 +1,17 @@
 //! src/lib.rs
 pub mod authentication;
 pub mod configurations;
+pub mod csrf;
 pub mod domain;
 pub mod email_client;
+pub mod idempotency;
+pub mod issue_delivery_worker;
+pub mod issue_scheduling_worker;
+pub mod newsletter_issues;
 pub mod routes;
+pub mod session_state;
 pub mod startup;
+pub mod subscription_purge_worker;
 pub mod telemetry;
-pub mod session_state;
-pub mod utils;
\ No newline at end of file
+pub mod templates;
+pub mod utils;
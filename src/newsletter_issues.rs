@@ -0,0 +1,537 @@
+//! src/newsletter_issues.rs
+//! Newsletter issues, from their first draft to their delivery: publishing
+//! an issue, through the API or from the admin panel, queues one delivery
+//! task per confirmed subscriber for `issue_delivery_worker`, and records
+//! in `issue_deliveries` how each of them turned out.
+use crate::domain::{MergeTemplate, SubscriptionStatus};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// What an editor writes: the same fields as the `POST /newsletters` body.
+pub struct IssueContent {
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+}
+
+impl IssueContent {
+    /// Drafts can be incomplete, published issues cannot.
+    pub fn ensure_publishable(&self) -> Result<(), String> {
+        if self.title.trim().is_empty()
+            || self.text_content.trim().is_empty()
+            || self.html_content.trim().is_empty()
+        {
+            return Err(
+                "A newsletter issue needs a title, an HTML and a text content to be published."
+                    .into(),
+            );
+        }
+        self.check_merge_tags()
+    }
+
+    /// The bodies can only use the merge tags `issue_delivery_worker` knows
+    /// how to fill in.
+    pub fn check_merge_tags(&self) -> Result<(), String> {
+        MergeTemplate::parse(&self.html_content).map_err(|e| format!("HTML content: {e}"))?;
+        MergeTemplate::parse(&self.text_content).map_err(|e| format!("Text content: {e}"))?;
+        Ok(())
+    }
+}
+
+/// How a delivery of an issue turned out, or `Queued` until it has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, serde::Serialize)]
+#[sqlx(type_name = "delivery_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Queued,
+    Sent,
+    /// Rejected by the email provider, or out of retries.
+    Failed,
+    /// Sent, then reported as permanently undeliverable by the provider.
+    Bounced,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Queued => "queued",
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Failed => "failed",
+            DeliveryStatus::Bounced => "bounced",
+        }
+    }
+}
+
+impl std::fmt::Display for DeliveryStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub struct NewsletterIssue {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub text_content: String,
+    pub html_content: String,
+    pub author_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// When the scheduler is to publish the draft, if it is scheduled.
+    pub scheduled_for: Option<DateTime<Utc>>,
+    /// `None` for drafts.
+    pub published_at: Option<DateTime<Utc>>,
+}
+
+impl NewsletterIssue {
+    pub fn is_draft(&self) -> bool {
+        self.published_at.is_none()
+    }
+}
+
+/// An issue as listed in the admin panel, without its content.
+pub struct IssueSummary {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    /// `None` once the author has been deleted.
+    pub author: Option<String>,
+    pub updated_at: DateTime<Utc>,
+    pub scheduled_for: Option<DateTime<Utc>>,
+    pub published_at: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(skip(pool, content))]
+pub async fn insert_draft(
+    pool: &PgPool,
+    author_id: Uuid,
+    content: &IssueContent,
+) -> Result<Uuid, anyhow::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            author_id,
+            created_at,
+            updated_at
+        )
+        VALUES ($1, $2, $3, $4, $5, now(), now())
+        "#,
+        newsletter_issue_id,
+        content.title,
+        content.text_content,
+        content.html_content,
+        author_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a newsletter draft.")?;
+    Ok(newsletter_issue_id)
+}
+
+/// Returns `false` if there is no such draft: it may have been published
+/// in the meantime.
+#[tracing::instrument(skip(pool, content))]
+pub async fn update_draft(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    content: &IssueContent,
+) -> Result<bool, anyhow::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $2, text_content = $3, html_content = $4, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        "#,
+        newsletter_issue_id,
+        content.title,
+        content.text_content,
+        content.html_content
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update a newsletter draft.")?
+    .rows_affected();
+    Ok(n_updated > 0)
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_issue(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<NewsletterIssue>, anyhow::Error> {
+    let issue = sqlx::query_as!(
+        NewsletterIssue,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            author_id,
+            created_at,
+            updated_at,
+            scheduled_for,
+            published_at
+        FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a newsletter issue.")?;
+    Ok(issue)
+}
+
+/// Drafts first, then the issues from the most recently published.
+#[tracing::instrument(skip(pool))]
+pub async fn get_issue_summaries(pool: &PgPool) -> Result<Vec<IssueSummary>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            u.username AS "author?",
+            i.updated_at,
+            i.scheduled_for,
+            i.published_at
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.author_id
+        ORDER BY i.published_at DESC NULLS FIRST, i.updated_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the newsletter issues.")?;
+    Ok(issues)
+}
+
+/// The drafts waiting for the scheduler, the next one to go out first.
+#[tracing::instrument(skip(pool))]
+pub async fn get_scheduled_issues(pool: &PgPool) -> Result<Vec<IssueSummary>, anyhow::Error> {
+    let issues = sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT
+            i.newsletter_issue_id,
+            i.title,
+            u.username AS "author?",
+            i.updated_at,
+            i.scheduled_for,
+            i.published_at
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.author_id
+        WHERE i.published_at IS NULL AND i.scheduled_for IS NOT NULL
+        ORDER BY i.scheduled_for
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the scheduled newsletter issues.")?;
+    Ok(issues)
+}
+
+/// Have the scheduler publish a draft at `scheduled_for`, or at a new time
+/// if it was already scheduled: the issue is then reported as published by
+/// `scheduled_by`.
+/// Returns `false` if there is no such draft.
+#[tracing::instrument(skip(pool))]
+pub async fn schedule_draft(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    scheduled_for: DateTime<Utc>,
+    scheduled_by: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_for = $2, published_by = $3, updated_at = now()
+        WHERE newsletter_issue_id = $1 AND published_at IS NULL
+        "#,
+        newsletter_issue_id,
+        scheduled_for,
+        scheduled_by
+    )
+    .execute(pool)
+    .await
+    .context("Failed to schedule a newsletter draft.")?
+    .rows_affected();
+    Ok(n_updated > 0)
+}
+
+/// Turn a scheduled issue back into a plain draft.
+/// Returns `false` if it was not scheduled, or was already published.
+#[tracing::instrument(skip(pool))]
+pub async fn unschedule_draft(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let n_updated = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET scheduled_for = NULL, published_by = NULL, updated_at = now()
+        WHERE
+            newsletter_issue_id = $1 AND
+            published_at IS NULL AND
+            scheduled_for IS NOT NULL
+        "#,
+        newsletter_issue_id
+    )
+    .execute(pool)
+    .await
+    .context("Failed to cancel the schedule of a newsletter draft.")?
+    .rows_affected();
+    Ok(n_updated > 0)
+}
+
+/// Publish a draft and queue its delivery.
+/// Returns `false` if there is no such draft, e.g. when the form is
+/// submitted twice.
+#[tracing::instrument(skip(pool))]
+pub async fn publish_draft(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    published_by: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let n_published = transaction
+        .execute(sqlx::query!(
+            r#"
+            UPDATE newsletter_issues
+            SET published_at = now(), published_by = $2, updated_at = now()
+            WHERE newsletter_issue_id = $1 AND published_at IS NULL
+            "#,
+            newsletter_issue_id,
+            published_by
+        ))
+        .await
+        .context("Failed to publish a newsletter draft.")?
+        .rows_affected();
+    if n_published == 0 {
+        return Ok(false);
+    }
+    enqueue_delivery_tasks(&mut transaction, newsletter_issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to publish a newsletter draft.")?;
+    Ok(true)
+}
+
+/// Store an issue that is published straight away, as the API does.
+#[tracing::instrument(skip_all)]
+pub async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    author_id: Uuid,
+    content: &IssueContent,
+) -> Result<Uuid, sqlx::Error> {
+    let newsletter_issue_id = Uuid::new_v4();
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues (
+            newsletter_issue_id,
+            title,
+            text_content,
+            html_content,
+            author_id,
+            created_at,
+            updated_at,
+            published_at,
+            published_by
+        )
+        VALUES ($1, $2, $3, $4, $5, now(), now(), now(), $5)
+        "#,
+        newsletter_issue_id,
+        content.title,
+        content.text_content,
+        content.html_content,
+        author_id
+    );
+    transaction.execute(query).await?;
+    Ok(newsletter_issue_id)
+}
+
+#[tracing::instrument(skip_all)]
+pub async fn enqueue_delivery_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        SELECT $1, email
+        FROM subscriptions
+        WHERE
+            status = $2 AND
+            NOT EXISTS (
+                SELECT 1 FROM email_suppressions
+                WHERE email_suppressions.email = subscriptions.email
+            )
+        "#,
+        newsletter_issue_id,
+        SubscriptionStatus::Confirmed as SubscriptionStatus
+    );
+    transaction.execute(query).await?;
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            newsletter_issue_id,
+            subscriber_email,
+            status,
+            queued_at
+        )
+        SELECT newsletter_issue_id, subscriber_email, $2, now()
+        FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        DeliveryStatus::Queued as DeliveryStatus
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// Drop the deliveries still queued for `email`, who must not receive them
+/// anymore, and settle them as failed: the report of an issue would
+/// otherwise never show it as finished.
+#[tracing::instrument(skip(transaction))]
+pub async fn cancel_queued_deliveries(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    let query = sqlx::query!(
+        r#"DELETE FROM issue_delivery_queue WHERE subscriber_email = $1"#,
+        email
+    );
+    transaction.execute(query).await?;
+    let query = sqlx::query!(
+        r#"
+        UPDATE issue_deliveries
+        SET status = $2, last_error = $3, settled_at = now()
+        WHERE subscriber_email = $1 AND status = $4
+        "#,
+        email,
+        DeliveryStatus::Failed as DeliveryStatus,
+        reason,
+        DeliveryStatus::Queued as DeliveryStatus
+    );
+    transaction.execute(query).await?;
+    Ok(())
+}
+
+/// Where the delivery of a published issue stands.
+#[derive(serde::Serialize)]
+pub struct DeliveryReport {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    /// `None` once the user has been deleted.
+    pub published_by: Option<String>,
+    pub published_at: DateTime<Utc>,
+    pub n_queued: i64,
+    pub n_sent: i64,
+    pub n_failed: i64,
+    pub n_bounced: i64,
+    /// When the last delivery was settled - `None` while some are queued.
+    pub finished_at: Option<DateTime<Utc>>,
+    /// The failed and bounced deliveries, the most recent first.
+    pub failures: Vec<DeliveryFailure>,
+}
+
+#[derive(serde::Serialize)]
+pub struct DeliveryFailure {
+    pub subscriber_email: String,
+    pub status: DeliveryStatus,
+    /// The error chain of the last attempt.
+    pub error: Option<String>,
+    pub settled_at: Option<DateTime<Utc>>,
+}
+
+/// Returns `None` if there is no such issue, or if it is still a draft.
+#[tracing::instrument(skip(pool))]
+pub async fn get_delivery_report(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<DeliveryReport>, anyhow::Error> {
+    let Some(issue) = sqlx::query!(
+        r#"
+        SELECT
+            i.title,
+            u.username AS "published_by?",
+            i.published_at AS "published_at!"
+        FROM newsletter_issues i
+        LEFT JOIN users u ON u.user_id = i.published_by
+        WHERE i.newsletter_issue_id = $1 AND i.published_at IS NOT NULL
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve a newsletter issue.")?
+    else {
+        return Ok(None);
+    };
+    let counts = sqlx::query!(
+        r#"
+        SELECT
+            count(*) FILTER (WHERE status = 'queued') AS "n_queued!",
+            count(*) FILTER (WHERE status = 'sent') AS "n_sent!",
+            count(*) FILTER (WHERE status = 'failed') AS "n_failed!",
+            count(*) FILTER (WHERE status = 'bounced') AS "n_bounced!",
+            max(settled_at) AS last_settled_at
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to count the deliveries of a newsletter issue.")?;
+    let failures = sqlx::query_as!(
+        DeliveryFailure,
+        r#"
+        SELECT
+            subscriber_email,
+            status AS "status: DeliveryStatus",
+            last_error AS error,
+            settled_at
+        FROM issue_deliveries
+        WHERE newsletter_issue_id = $1 AND status IN ('failed', 'bounced')
+        ORDER BY settled_at DESC, subscriber_email
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the failed deliveries of a newsletter issue.")?;
+    let finished_at = match counts.n_queued {
+        0 => counts.last_settled_at.or(Some(issue.published_at)),
+        _ => None,
+    };
+    Ok(Some(DeliveryReport {
+        newsletter_issue_id,
+        title: issue.title,
+        published_by: issue.published_by,
+        published_at: issue.published_at,
+        n_queued: counts.n_queued,
+        n_sent: counts.n_sent,
+        n_failed: counts.n_failed,
+        n_bounced: counts.n_bounced,
+        finished_at,
+        failures,
+    }))
+}
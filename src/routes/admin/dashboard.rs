@@ -1,16 +1,33 @@
 //! src/routes/admin/dashboard.rs
+use crate::authentication::Role;
+use crate::csrf::CsrfToken;
 use crate::session_state::TypedSession;
+use crate::templates::{FlashMessages, Nav, render};
 use crate::utils::e500;
-use actix_web::http::header::ContentType;
 use actix_web::http::header::LOCATION;
 use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::IncomingFlashMessages;
 use anyhow::Context;
+use askama::Template;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+#[derive(Template)]
+#[template(path = "admin/dashboard.html")]
+struct DashboardPage {
+    nav: Nav,
+    flash_messages: FlashMessages,
+    csrf_token: CsrfToken,
+    username: String,
+    role: Role,
+}
+
 pub async fn admin_dashboard(
     session: TypedSession,
     pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    role: web::ReqData<Role>,
+    csrf_token: CsrfToken,
 ) -> Result<HttpResponse, actix_web::Error> {
     let username = if let Some(user_id) = session.get_user_id().map_err(e500)? {
         get_username(user_id, &pool).await.map_err(e500)?
@@ -19,29 +36,13 @@ pub async fn admin_dashboard(
             .insert_header((LOCATION, "/login"))
             .finish());
     };
-    Ok(HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(format!(
-            r#"<!DOCTYPE html>
-                <html lang="en">
-                    <head>
-                        <meta http-equiv="content-type" content="text/html; charset=utf-8">
-                        <title>Admin dashboard</title>
-                    </head>
-                    <body>
-                        <p>Welcome {username}!</p>
-                        <p>Available actions:</p>
-                        <ol>
-                            <li><a href="/admin/password">Change password</a></li>
-                            <li>
-                                <form name="logoutForm" action="/admin/logout" method="post">
-                                    <input type="submit" value="Logout">
-                                </form>
-                            </li>
-                        </ol>
-                    </body>
-                </html>"#,
-        )))
+    render(&DashboardPage {
+        nav: Nav::new(*role),
+        flash_messages: flash_messages.into(),
+        csrf_token,
+        username,
+        role: *role,
+    })
 }
 
 #[tracing::instrument(name = "Get username", skip(pool))]
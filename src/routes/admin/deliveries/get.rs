@@ -0,0 +1,68 @@
+//! src/routes/admin/deliveries/get.rs
+use crate::authentication::{Permission, Role};
+use crate::csrf::CsrfToken;
+use crate::templates::{FlashMessages, Nav, render};
+use crate::utils::e500;
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+struct DeadLetter {
+    newsletter_issue_id: Uuid,
+    title: String,
+    subscriber_email: String,
+    n_retries: i16,
+    last_error: String,
+    failed_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/deliveries.html")]
+struct FailedDeliveriesPage {
+    nav: Nav,
+    flash_messages: FlashMessages,
+    csrf_token: CsrfToken,
+    dead_letters: Vec<DeadLetter>,
+}
+
+pub async fn failed_deliveries(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    role: web::ReqData<Role>,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::ViewReports)?;
+    let dead_letters = get_dead_letters(&pool).await.map_err(e500)?;
+    render(&FailedDeliveriesPage {
+        nav: Nav::new(*role),
+        flash_messages: flash_messages.into(),
+        csrf_token,
+        dead_letters,
+    })
+}
+
+#[tracing::instrument(name = "Get dead-lettered deliveries", skip(pool))]
+async fn get_dead_letters(pool: &PgPool) -> Result<Vec<DeadLetter>, anyhow::Error> {
+    let dead_letters = sqlx::query_as!(
+        DeadLetter,
+        r#"
+        SELECT
+            d.newsletter_issue_id,
+            i.title,
+            d.subscriber_email,
+            d.n_retries,
+            d.last_error,
+            d.failed_at
+        FROM issue_delivery_dead_letters d
+        JOIN newsletter_issues i USING (newsletter_issue_id)
+        ORDER BY d.failed_at DESC
+        "#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve dead-lettered deliveries.")?;
+    Ok(dead_letters)
+}
@@ -0,0 +1,5 @@
+//! src/routes/admin/deliveries/mod.rs
+mod get;
+mod post;
+pub use get::failed_deliveries;
+pub use post::requeue_failed_delivery;
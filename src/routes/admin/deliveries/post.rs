@@ -0,0 +1,99 @@
+//! src/routes/admin/deliveries/post.rs
+use crate::authentication::{Permission, Role};
+use crate::newsletter_issues::DeliveryStatus;
+use crate::utils::{e500, see_other};
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::{Executor, PgPool};
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+}
+
+pub async fn requeue_failed_delivery(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::ManageSubscribers)?;
+    let requeued = requeue_dead_letter(&pool, form.newsletter_issue_id, &form.subscriber_email)
+        .await
+        .map_err(e500)?;
+    if requeued {
+        FlashMessage::info(format!(
+            "The delivery to {} has been requeued.",
+            form.subscriber_email
+        ))
+        .send();
+    } else {
+        FlashMessage::error("The delivery was not found among the failed ones.").send();
+    }
+    Ok(see_other("/admin/deliveries"))
+}
+
+/// Move a dead-lettered delivery back into `issue_delivery_queue`,
+/// with a fresh retry budget.
+#[tracing::instrument(name = "Requeue a dead-lettered delivery", skip(pool))]
+async fn requeue_dead_letter(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    subscriber_email: &str,
+) -> Result<bool, anyhow::Error> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let query = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_dead_letters
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    );
+    let n_deleted_rows = transaction.execute(query).await?.rows_affected();
+    if n_deleted_rows == 0 {
+        return Ok(false);
+    }
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_queue (
+            newsletter_issue_id,
+            subscriber_email
+        )
+        VALUES ($1, $2)
+        ON CONFLICT DO NOTHING
+        "#,
+        newsletter_issue_id,
+        subscriber_email
+    );
+    transaction.execute(query).await?;
+    let query = sqlx::query!(
+        r#"
+        INSERT INTO issue_deliveries (
+            newsletter_issue_id,
+            subscriber_email,
+            status,
+            queued_at
+        )
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT (newsletter_issue_id, subscriber_email) DO UPDATE
+        SET status = EXCLUDED.status, last_error = NULL, settled_at = NULL
+        "#,
+        newsletter_issue_id,
+        subscriber_email,
+        DeliveryStatus::Queued as DeliveryStatus
+    );
+    transaction.execute(query).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to requeue a delivery.")?;
+    Ok(true)
+}
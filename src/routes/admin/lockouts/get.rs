@@ -0,0 +1,36 @@
+//! src/routes/admin/lockouts/get.rs
+use crate::authentication::{LoginThrottle, Permission, Role};
+use crate::csrf::CsrfToken;
+use crate::templates::{FlashMessages, Nav, render};
+use crate::utils::e500;
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use std::time::Duration;
+
+#[derive(Template)]
+#[template(path = "admin/lockouts.html")]
+struct LockoutsPage {
+    nav: Nav,
+    flash_messages: FlashMessages,
+    csrf_token: CsrfToken,
+    /// Usernames, with how long they stay locked for.
+    accounts: Vec<(String, Duration)>,
+}
+
+/// The accounts locked out after too many failed logins.
+pub async fn lockouts(
+    throttle: web::Data<LoginThrottle>,
+    flash_messages: IncomingFlashMessages,
+    role: web::ReqData<Role>,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::ManageUsers)?;
+    let accounts = throttle.locked_accounts().await.map_err(e500)?;
+    render(&LockoutsPage {
+        nav: Nav::new(*role),
+        flash_messages: flash_messages.into(),
+        csrf_token,
+        accounts,
+    })
+}
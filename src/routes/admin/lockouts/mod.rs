@@ -0,0 +1,5 @@
+//! src/routes/admin/lockouts/mod.rs
+mod get;
+mod post;
+pub use get::lockouts;
+pub use post::unlock_account;
@@ -0,0 +1,26 @@
+//! src/routes/admin/lockouts/post.rs
+use crate::authentication::{LoginThrottle, Permission, Role};
+use crate::utils::{e500, see_other};
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+
+#[derive(serde::Deserialize)]
+pub struct UnlockFormData {
+    username: String,
+}
+
+#[tracing::instrument(
+    name = "Unlock a locked out account",
+    skip(form, throttle, role),
+    fields(username = %form.username)
+)]
+pub async fn unlock_account(
+    form: web::Form<UnlockFormData>,
+    throttle: web::Data<LoginThrottle>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::ManageUsers)?;
+    throttle.unlock(&form.username).await.map_err(e500)?;
+    FlashMessage::info(format!("{} can log in again.", form.username)).send();
+    Ok(see_other("/admin/lockouts"))
+}
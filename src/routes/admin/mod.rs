@@ -1,6 +1,20 @@
 mod dashboard;
+mod deliveries;
+mod lockouts;
 mod logout;
+mod newsletters;
 mod password;
-pub use dashboard::admin_dashboard;
+mod sessions;
+mod tokens;
+mod two_factor;
+mod users;
+pub use dashboard::{admin_dashboard, get_username};
+pub use deliveries::*;
+pub use lockouts::*;
 pub use logout::*;
+pub use newsletters::*;
 pub use password::*;
+pub use sessions::*;
+pub use tokens::*;
+pub use two_factor::*;
+pub use users::*;
@@ -0,0 +1,208 @@
+//! src/routes/admin/newsletters/get.rs
+use crate::authentication::{Permission, Role};
+use crate::configurations::IssueSchedulingSettings;
+use crate::csrf::CsrfToken;
+use crate::newsletter_issues::{
+    DeliveryReport, IssueSummary, NewsletterIssue, get_delivery_report, get_issue,
+    get_issue_summaries, get_scheduled_issues,
+};
+use crate::templates::{FlashMessages, Nav, render};
+use crate::utils::{e500, see_other};
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use askama::Template;
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct IssueQuery {
+    newsletter_issue_id: Option<Uuid>,
+}
+
+fn unknown_issue() -> HttpResponse {
+    FlashMessage::error("This newsletter issue does not exist.").send();
+    see_other("/admin/newsletters")
+}
+
+#[derive(Template)]
+#[template(path = "admin/newsletters/list.html")]
+struct NewsletterIssuesPage {
+    nav: Nav,
+    flash_messages: FlashMessages,
+    issues: Vec<IssueSummary>,
+}
+
+impl NewsletterIssuesPage {
+    fn status(&self, issue: &IssueSummary) -> String {
+        match (issue.published_at, issue.scheduled_for) {
+            (Some(published_at), _) => format!("published at {}", published_at.to_rfc3339()),
+            (None, Some(scheduled_for)) => format!("scheduled for {}", scheduled_for.to_rfc3339()),
+            (None, None) => "draft".to_owned(),
+        }
+    }
+}
+
+pub async fn newsletter_issues(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::PublishNewsletters)?;
+    let issues = get_issue_summaries(&pool).await.map_err(e500)?;
+    render(&NewsletterIssuesPage {
+        nav: Nav::new(*role),
+        flash_messages: flash_messages.into(),
+        issues,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "admin/newsletters/edit.html")]
+struct EditIssuePage {
+    nav: Nav,
+    flash_messages: FlashMessages,
+    csrf_token: CsrfToken,
+    /// `None` for a new issue.
+    newsletter_issue_id: Option<Uuid>,
+    title: String,
+    html_content: String,
+    text_content: String,
+    scheduled_for: Option<DateTime<Utc>>,
+    /// Of the publishing times.
+    timezone: Tz,
+}
+
+/// A blank form without `newsletter_issue_id`, the draft to edit with it.
+pub async fn edit_newsletter_issue_form(
+    query: web::Query<IssueQuery>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    role: web::ReqData<Role>,
+    csrf_token: CsrfToken,
+    settings: web::Data<IssueSchedulingSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::PublishNewsletters)?;
+    let mut page = EditIssuePage {
+        nav: Nav::new(*role),
+        flash_messages: flash_messages.into(),
+        csrf_token,
+        newsletter_issue_id: None,
+        title: String::new(),
+        html_content: String::new(),
+        text_content: String::new(),
+        scheduled_for: None,
+        timezone: settings.timezone,
+    };
+    if let Some(newsletter_issue_id) = query.newsletter_issue_id {
+        let Some(issue) = get_issue(&pool, newsletter_issue_id).await.map_err(e500)? else {
+            return Ok(unknown_issue());
+        };
+        if !issue.is_draft() {
+            FlashMessage::error("This issue has already been published: it cannot be edited.")
+                .send();
+            return Ok(see_other("/admin/newsletters"));
+        }
+        page.newsletter_issue_id = Some(issue.newsletter_issue_id);
+        page.title = issue.title;
+        page.html_content = issue.html_content;
+        page.text_content = issue.text_content;
+        page.scheduled_for = issue.scheduled_for;
+    }
+    render(&page)
+}
+
+#[derive(Template)]
+#[template(path = "admin/newsletters/preview.html")]
+struct PreviewIssuePage {
+    nav: Nav,
+    flash_messages: FlashMessages,
+    csrf_token: CsrfToken,
+    issue: NewsletterIssue,
+    timezone: Tz,
+}
+
+/// The issue as subscribers will see it. The HTML content is rendered in a
+/// sandboxed frame, away from the admin panel.
+pub async fn preview_newsletter_issue(
+    query: web::Query<IssueQuery>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    role: web::ReqData<Role>,
+    csrf_token: CsrfToken,
+    settings: web::Data<IssueSchedulingSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::PublishNewsletters)?;
+    let Some(newsletter_issue_id) = query.newsletter_issue_id else {
+        return Ok(unknown_issue());
+    };
+    let Some(issue) = get_issue(&pool, newsletter_issue_id).await.map_err(e500)? else {
+        return Ok(unknown_issue());
+    };
+    render(&PreviewIssuePage {
+        nav: Nav::new(*role),
+        flash_messages: flash_messages.into(),
+        csrf_token,
+        issue,
+        timezone: settings.timezone,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "admin/newsletters/scheduled.html")]
+struct ScheduledIssuesPage {
+    nav: Nav,
+    flash_messages: FlashMessages,
+    csrf_token: CsrfToken,
+    issues: Vec<IssueSummary>,
+    timezone: Tz,
+}
+
+pub async fn scheduled_newsletter_issues(
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    role: web::ReqData<Role>,
+    csrf_token: CsrfToken,
+    settings: web::Data<IssueSchedulingSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::PublishNewsletters)?;
+    let issues = get_scheduled_issues(&pool).await.map_err(e500)?;
+    render(&ScheduledIssuesPage {
+        nav: Nav::new(*role),
+        flash_messages: flash_messages.into(),
+        csrf_token,
+        issues,
+        timezone: settings.timezone,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "admin/newsletters/report.html")]
+struct DeliveryReportPage {
+    nav: Nav,
+    flash_messages: FlashMessages,
+    report: DeliveryReport,
+}
+
+/// How the delivery of a published issue went. Reporting scripts get the
+/// same report as JSON from `GET /newsletters/{newsletter_issue_id}/report`.
+pub async fn newsletter_issue_report(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::ViewReports)?;
+    let Some(report) = get_delivery_report(&pool, *newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    else {
+        return Ok(unknown_issue());
+    };
+    render(&DeliveryReportPage {
+        nav: Nav::new(*role),
+        flash_messages: flash_messages.into(),
+        report,
+    })
+}
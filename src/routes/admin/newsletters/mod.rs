@@ -0,0 +1,8 @@
+//! src/routes/admin/newsletters/mod.rs
+mod get;
+mod post;
+pub use get::{
+    edit_newsletter_issue_form, newsletter_issue_report, newsletter_issues,
+    preview_newsletter_issue, scheduled_newsletter_issues,
+};
+pub use post::{publish_newsletter_issue, save_newsletter_issue, unschedule_newsletter_issue};
@@ -0,0 +1,288 @@
+//! src/routes/admin/newsletters/post.rs
+use crate::authentication::{Permission, Role, UserId};
+use crate::configurations::IssueSchedulingSettings;
+use crate::newsletter_issues::{
+    IssueContent, get_issue, insert_draft, publish_draft, schedule_draft, unschedule_draft,
+    update_draft,
+};
+use crate::utils::{e500, see_other};
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use chrono::{DateTime, LocalResult, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    Save,
+    Preview,
+    Publish,
+    Schedule,
+}
+
+#[derive(serde::Deserialize)]
+pub struct IssueFormData {
+    // Missing for a new issue.
+    newsletter_issue_id: Option<Uuid>,
+    title: String,
+    html_content: String,
+    text_content: String,
+    /// From a `datetime-local` input, in the configured timezone.
+    scheduled_for: Option<String>,
+    action: Action,
+}
+
+#[tracing::instrument(
+    name = "Save a newsletter draft",
+    skip(form, user_id, pool, role, settings),
+    fields(newsletter_issue_id = tracing::field::Empty)
+)]
+pub async fn save_newsletter_issue(
+    form: web::Form<IssueFormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    role: web::ReqData<Role>,
+    settings: web::Data<IssueSchedulingSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::PublishNewsletters)?;
+    let IssueFormData {
+        newsletter_issue_id,
+        title,
+        html_content,
+        text_content,
+        scheduled_for,
+        action,
+    } = form.into_inner();
+    let content = IssueContent {
+        title: title.trim().to_owned(),
+        text_content,
+        html_content,
+    };
+
+    let newsletter_issue_id = match newsletter_issue_id {
+        Some(newsletter_issue_id) => {
+            if !update_draft(&pool, newsletter_issue_id, &content)
+                .await
+                .map_err(e500)?
+            {
+                FlashMessage::error("This issue has already been published: it cannot be edited.")
+                    .send();
+                return Ok(see_other("/admin/newsletters"));
+            }
+            newsletter_issue_id
+        }
+        None => insert_draft(&pool, **user_id, &content)
+            .await
+            .map_err(e500)?,
+    };
+    tracing::Span::current().record(
+        "newsletter_issue_id",
+        tracing::field::display(&newsletter_issue_id),
+    );
+    let edit_page = format!("/admin/newsletters/edit?newsletter_issue_id={newsletter_issue_id}");
+
+    // The scheduler must not find an incomplete issue when it is due.
+    if let Err(e) = content.ensure_publishable()
+        && unschedule_draft(&pool, newsletter_issue_id)
+            .await
+            .map_err(e500)?
+    {
+        FlashMessage::error(format!("{e} The issue is not scheduled anymore.")).send();
+        return Ok(see_other(&edit_page));
+    }
+
+    match action {
+        Action::Save => {
+            FlashMessage::info("Your draft has been saved.").send();
+            Ok(see_other(&edit_page))
+        }
+        Action::Preview => Ok(see_other(&format!(
+            "/admin/newsletters/preview?newsletter_issue_id={newsletter_issue_id}"
+        ))),
+        Action::Publish => publish(&pool, newsletter_issue_id, **user_id).await,
+        Action::Schedule => {
+            let scheduled_for = match content.ensure_publishable().and_then(|_| {
+                parse_schedule(
+                    scheduled_for.as_deref().unwrap_or_default(),
+                    settings.timezone,
+                )
+            }) {
+                Ok(scheduled_for) => scheduled_for,
+                Err(e) => {
+                    FlashMessage::error(e).send();
+                    return Ok(see_other(&edit_page));
+                }
+            };
+            schedule_draft(&pool, newsletter_issue_id, scheduled_for, **user_id)
+                .await
+                .map_err(e500)?;
+            FlashMessage::info(format!(
+                "The issue will be published at {}.",
+                scheduled_for
+                    .with_timezone(&settings.timezone)
+                    .format("%Y-%m-%d %H:%M %Z")
+            ))
+            .send();
+            Ok(see_other("/admin/newsletters/scheduled"))
+        }
+    }
+}
+
+/// `value` comes from a `datetime-local` input, with or without seconds: a
+/// wall-clock time in `timezone`.
+fn parse_schedule(value: &str, timezone: Tz) -> Result<DateTime<Utc>, String> {
+    let local = ["%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value.trim(), format).ok())
+        .ok_or("Pick the date and time to publish the issue at.")?;
+    let scheduled_for = match timezone.from_local_datetime(&local) {
+        LocalResult::Single(at) => at,
+        // The clocks go back: the hour happens twice, take the first one.
+        LocalResult::Ambiguous(earliest, _) => earliest,
+        LocalResult::None => {
+            return Err(format!(
+                "{local} does not exist in {timezone}, the clocks go forward: pick another time."
+            ));
+        }
+    }
+    .with_timezone(&Utc);
+    if scheduled_for <= Utc::now() {
+        return Err("Pick a date and time in the future.".into());
+    }
+    Ok(scheduled_for)
+}
+
+#[derive(serde::Deserialize)]
+pub struct PublishFormData {
+    newsletter_issue_id: Uuid,
+}
+
+#[tracing::instrument(
+    name = "Publish a newsletter draft",
+    skip(form, pool, user_id, role),
+    fields(newsletter_issue_id = %form.newsletter_issue_id)
+)]
+pub async fn publish_newsletter_issue(
+    form: web::Form<PublishFormData>,
+    pool: web::Data<PgPool>,
+    user_id: web::ReqData<UserId>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::PublishNewsletters)?;
+    publish(&pool, form.newsletter_issue_id, **user_id).await
+}
+
+/// Queue the delivery of a draft, like `POST /newsletters` does.
+async fn publish(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    user_id: Uuid,
+) -> Result<HttpResponse, actix_web::Error> {
+    let edit_page = format!("/admin/newsletters/edit?newsletter_issue_id={newsletter_issue_id}");
+    let Some(issue) = get_issue(pool, newsletter_issue_id).await.map_err(e500)? else {
+        FlashMessage::error("This newsletter issue does not exist.").send();
+        return Ok(see_other("/admin/newsletters"));
+    };
+    let content = IssueContent {
+        title: issue.title,
+        text_content: issue.text_content,
+        html_content: issue.html_content,
+    };
+    if let Err(e) = content.ensure_publishable() {
+        FlashMessage::error(e).send();
+        return Ok(see_other(&edit_page));
+    }
+    if publish_draft(pool, newsletter_issue_id, user_id)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::info("The newsletter issue has been published!").send();
+    } else {
+        // Submitted twice: the first submission published it.
+        FlashMessage::error("This issue has already been published.").send();
+    }
+    Ok(see_other("/admin/newsletters"))
+}
+
+#[derive(serde::Deserialize)]
+pub struct UnscheduleFormData {
+    newsletter_issue_id: Uuid,
+}
+
+#[tracing::instrument(
+    name = "Cancel the schedule of a newsletter draft",
+    skip(form, pool, role),
+    fields(newsletter_issue_id = %form.newsletter_issue_id)
+)]
+pub async fn unschedule_newsletter_issue(
+    form: web::Form<UnscheduleFormData>,
+    pool: web::Data<PgPool>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::PublishNewsletters)?;
+    if unschedule_draft(&pool, form.newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        FlashMessage::info("The issue is not scheduled anymore: it is a draft again.").send();
+    } else {
+        FlashMessage::error("This issue is not scheduled: it may have been published already.")
+            .send();
+    }
+    Ok(see_other("/admin/newsletters/scheduled"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_schedule;
+    use chrono::{Duration, TimeZone, Utc};
+    use chrono_tz::{Europe::Paris, Tz};
+
+    #[test]
+    fn a_schedule_is_read_with_or_without_seconds() {
+        let tomorrow = Utc::now() + Duration::days(1);
+        for format in ["%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S"] {
+            let value = tomorrow.format(format).to_string();
+            assert_eq!(
+                parse_schedule(&value, Tz::UTC)
+                    .unwrap()
+                    .format("%Y-%m-%dT%H:%M")
+                    .to_string(),
+                tomorrow.format("%Y-%m-%dT%H:%M").to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn a_schedule_must_be_in_the_future() {
+        let yesterday = (Utc::now() - Duration::days(1)).format("%Y-%m-%dT%H:%M");
+        assert!(parse_schedule(&yesterday.to_string(), Tz::UTC).is_err());
+        assert!(parse_schedule("", Tz::UTC).is_err());
+        assert!(parse_schedule("next monday", Tz::UTC).is_err());
+    }
+
+    #[test]
+    fn a_schedule_is_read_in_the_configured_timezone() {
+        // Winter time, then summer time.
+        assert_eq!(
+            parse_schedule("2099-01-15T10:00", Paris),
+            Ok(Utc.with_ymd_and_hms(2099, 1, 15, 9, 0, 0).unwrap())
+        );
+        assert_eq!(
+            parse_schedule("2099-07-15T10:00", Paris),
+            Ok(Utc.with_ymd_and_hms(2099, 7, 15, 8, 0, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn a_time_skipped_by_the_clocks_going_forward_is_rejected() {
+        assert!(parse_schedule("2099-03-29T02:30", Paris).is_err());
+        // When the clocks go back, the first of the two is picked.
+        assert_eq!(
+            parse_schedule("2099-10-25T02:30", Paris),
+            Ok(Utc.with_ymd_and_hms(2099, 10, 25, 0, 30, 0).unwrap())
+        );
+    }
+}
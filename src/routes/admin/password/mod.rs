@@ -2,4 +2,4 @@
 mod get;
 pub use get::change_password_form;
 mod post;
-pub use post::change_password;
\ No newline at end of file
+pub use post::change_password;
@@ -1,15 +1,14 @@
 //! src/routes/admin/password/post.rs
-use crate::authentication::{AuthError, Credentials, validate_credentials};
+use crate::authentication::UserId;
+use crate::authentication::{AuthError, Credentials, PasswordPolicy, validate_credentials};
+use crate::configurations::PasswordHashingSettings;
 use crate::routes::admin::dashboard::get_username;
 use crate::session_state::TypedSession;
 use crate::utils::{e500, see_other};
-use actix_web::error::InternalError;
 use actix_web::{HttpResponse, web};
 use actix_web_flash_messages::FlashMessage;
 use secrecy::{ExposeSecret, SecretString};
 use sqlx::PgPool;
-use uuid::Uuid;
-use crate::authentication::UserId;
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
@@ -22,6 +21,9 @@ pub async fn change_password(
     form: web::Form<FormData>,
     user_id: web::ReqData<UserId>,
     pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+    policy: web::Data<PasswordPolicy>,
+    session: TypedSession,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
     if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
@@ -34,33 +36,32 @@ pub async fn change_password(
 
     let username = get_username(*user_id, &pool).await.map_err(e500)?;
     let credentials = Credentials {
-        username,
-        password: form.0.current_password,
+        username: username.clone(),
+        password: form.0.current_password.clone(),
     };
-    if let Err(e) = validate_credentials(credentials, &pool).await {
+    if let Err(e) = validate_credentials(credentials, &hashing, &pool).await {
         return match e {
             AuthError::InvalidCredentials(_) => {
                 FlashMessage::error("The current password is incorrect.").send();
                 Ok(see_other("/admin/password"))
             }
-            AuthError::UnexpectedError(_) => Err(e500(e).into()),
+            AuthError::UnexpectedError(_) => Err(e500(e)),
         };
     }
 
-    crate::authentication::change_password(*user_id, form.0.new_password, &pool)
+    if let Err(e) = policy.check(&form.new_password, &username, Some(&form.current_password)) {
+        FlashMessage::error(e.to_string()).send();
+        return Ok(see_other("/admin/password"));
+    }
+
+    crate::authentication::change_password(*user_id, form.0.new_password, &hashing, &pool)
+        .await
+        .map_err(e500)?;
+    // Whoever knew the old password is logged out everywhere else.
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    crate::authentication::revoke_other_user_sessions(&pool, *user_id, current_session_id)
         .await
         .map_err(e500)?;
     FlashMessage::error("Your password has been changed.").send();
     Ok(see_other("/admin/password"))
 }
-
-async fn reject_anonymous_users(session: TypedSession) -> Result<Uuid, actix_web::Error> {
-    match session.get_user_id().map_err(e500)? {
-        Some(user_id) => Ok(user_id),
-        None => {
-            let response = see_other("/login");
-            let e = anyhow::anyhow!("The user has not logged in");
-            Err(InternalError::from_response(e, response).into())
-        }
-    }
-}
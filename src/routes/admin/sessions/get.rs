@@ -0,0 +1,47 @@
+//! src/routes/admin/sessions/get.rs
+use crate::authentication::{Role, UserId, UserSession, get_user_sessions};
+use crate::csrf::CsrfToken;
+use crate::session_state::TypedSession;
+use crate::templates::{FlashMessages, Nav, render};
+use crate::utils::e500;
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Template)]
+#[template(path = "admin/sessions.html")]
+struct SessionsPage {
+    nav: Nav,
+    flash_messages: FlashMessages,
+    csrf_token: CsrfToken,
+    sessions: Vec<UserSession>,
+    current_session_id: Option<Uuid>,
+}
+
+impl SessionsPage {
+    /// The session of this request cannot be revoked from here.
+    fn is_current(&self, session: &UserSession) -> bool {
+        self.current_session_id == Some(session.id)
+    }
+}
+
+pub async fn sessions(
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    role: web::ReqData<Role>,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    let sessions = get_user_sessions(&pool, **user_id).await.map_err(e500)?;
+    render(&SessionsPage {
+        nav: Nav::new(*role),
+        flash_messages: flash_messages.into(),
+        csrf_token,
+        sessions,
+        current_session_id,
+    })
+}
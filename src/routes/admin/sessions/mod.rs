@@ -0,0 +1,5 @@
+//! src/routes/admin/sessions/mod.rs
+mod get;
+mod post;
+pub use get::sessions;
+pub use post::{revoke_other_sessions, revoke_session};
@@ -0,0 +1,45 @@
+//! src/routes/admin/sessions/post.rs
+use crate::authentication::{self, UserId};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct RevokeFormData {
+    session_id: Uuid,
+}
+
+#[tracing::instrument(name = "Revoke a session", skip(form, user_id, pool))]
+pub async fn revoke_session(
+    form: web::Form<RevokeFormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let revoked = authentication::revoke_user_session(&pool, **user_id, form.session_id)
+        .await
+        .map_err(e500)?;
+    if revoked {
+        FlashMessage::info("The session has been logged out.").send();
+    } else {
+        FlashMessage::error("The session was not found among your active ones.").send();
+    }
+    Ok(see_other("/admin/sessions"))
+}
+
+#[tracing::instrument(name = "Revoke the other sessions", skip(user_id, session, pool))]
+pub async fn revoke_other_sessions(
+    user_id: web::ReqData<UserId>,
+    session: TypedSession,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let current_session_id = session.get_session_id().map_err(e500)?;
+    let n_revoked =
+        authentication::revoke_other_user_sessions(&pool, **user_id, current_session_id)
+            .await
+            .map_err(e500)?;
+    FlashMessage::info(format!("{n_revoked} other session(s) logged out.")).send();
+    Ok(see_other("/admin/sessions"))
+}
@@ -0,0 +1,52 @@
+//! src/routes/admin/tokens/get.rs
+use crate::authentication::{ApiScope, ApiToken, Permission, Role, UserId, get_api_tokens};
+use crate::csrf::CsrfToken;
+use crate::templates::{FlashMessages, Nav, render};
+use crate::utils::e500;
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+#[derive(Template)]
+#[template(path = "admin/tokens/list.html")]
+struct ApiTokensPage {
+    nav: Nav,
+    flash_messages: FlashMessages,
+    csrf_token: CsrfToken,
+    tokens: Vec<ApiToken>,
+    scopes: &'static [ApiScope],
+}
+
+impl ApiTokensPage {
+    fn status(&self, token: &ApiToken) -> &'static str {
+        match (token.revoked_at, token.is_active()) {
+            (Some(_), _) => "revoked",
+            (None, false) => "expired",
+            (None, true) => "active",
+        }
+    }
+
+    fn or_never(&self, date: &Option<DateTime<Utc>>) -> String {
+        date.map_or_else(|| "never".into(), |d| d.to_rfc3339())
+    }
+}
+
+pub async fn api_tokens(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    role: web::ReqData<Role>,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::ManageOwnApiTokens)?;
+    let tokens = get_api_tokens(&pool, **user_id).await.map_err(e500)?;
+    render(&ApiTokensPage {
+        nav: Nav::new(*role),
+        flash_messages: flash_messages.into(),
+        csrf_token,
+        tokens,
+        scopes: &ApiScope::ALL,
+    })
+}
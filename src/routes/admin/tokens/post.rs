@@ -0,0 +1,118 @@
+//! src/routes/admin/tokens/post.rs
+use crate::authentication::{self, ApiScope, Permission, Role, UserId};
+use crate::templates::{FlashMessages, Nav, render};
+use crate::utils::{e500, see_other};
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use askama::Template;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+use unicode_segmentation::UnicodeSegmentation;
+use uuid::Uuid;
+
+struct NewTokenForm {
+    name: String,
+    scopes: Vec<ApiScope>,
+    expires_in_days: Option<u32>,
+}
+
+impl TryFrom<Vec<(String, String)>> for NewTokenForm {
+    type Error = String;
+
+    // Read as key-value pairs: there is one `scope` field per ticked box.
+    fn try_from(fields: Vec<(String, String)>) -> Result<Self, Self::Error> {
+        let mut name = String::new();
+        let mut scopes = Vec::new();
+        let mut expires_in_days = None;
+        for (key, value) in fields {
+            match key.as_str() {
+                "name" => name = value.trim().to_owned(),
+                "scope" => scopes.push(ApiScope::parse(&value)?),
+                "expires_in_days" if value.trim().is_empty() => {}
+                "expires_in_days" => {
+                    let days = value
+                        .trim()
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|d| *d > 0)
+                        .ok_or("The expiry must be a positive number of days.")?;
+                    expires_in_days = Some(days);
+                }
+                _ => {}
+            }
+        }
+        if name.is_empty() || name.graphemes(true).count() > 100 {
+            return Err("The name must be between 1 and 100 characters long.".into());
+        }
+        if scopes.is_empty() {
+            return Err("A token needs at least one scope.".into());
+        }
+        Ok(Self {
+            name,
+            scopes,
+            expires_in_days,
+        })
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin/tokens/created.html")]
+struct ApiTokenCreatedPage<'a> {
+    nav: Nav,
+    flash_messages: FlashMessages,
+    name: &'a str,
+    token: &'a str,
+}
+
+pub async fn create_api_token(
+    form: web::Form<Vec<(String, String)>>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::ManageOwnApiTokens)?;
+    let form = match NewTokenForm::try_from(form.into_inner()) {
+        Ok(form) => form,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/tokens"));
+        }
+    };
+    let expires_at = form
+        .expires_in_days
+        .map(|days| chrono::Utc::now() + chrono::Duration::days(days.into()));
+    let token =
+        authentication::create_api_token(&pool, **user_id, &form.name, &form.scopes, expires_at)
+            .await
+            .map_err(e500)?;
+    // Not a redirect: the token is only ever shown on this page.
+    render(&ApiTokenCreatedPage {
+        nav: Nav::new(*role),
+        flash_messages: FlashMessages::default(),
+        name: &form.name,
+        token: token.expose_secret(),
+    })
+}
+
+#[derive(serde::Deserialize)]
+pub struct RevokeFormData {
+    token_id: Uuid,
+}
+
+pub async fn revoke_api_token(
+    form: web::Form<RevokeFormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::ManageOwnApiTokens)?;
+    let revoked = authentication::revoke_api_token(&pool, **user_id, form.token_id)
+        .await
+        .map_err(e500)?;
+    if revoked {
+        FlashMessage::info("The token has been revoked.").send();
+    } else {
+        FlashMessage::error("The token was not found among your active ones.").send();
+    }
+    Ok(see_other("/admin/tokens"))
+}
@@ -0,0 +1,80 @@
+//! src/routes/admin/two_factor/get.rs
+use crate::authentication::{Role, TotpSecret, UserId, is_two_factor_enabled};
+use crate::configurations::TwoFactorSettings;
+use crate::csrf::CsrfToken;
+use crate::routes::admin::dashboard::get_username;
+use crate::session_state::TypedSession;
+use crate::templates::{FlashMessages, Nav, render};
+use crate::utils::e500;
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use qrcode::QrCode;
+use qrcode::render::svg;
+use sqlx::PgPool;
+
+#[derive(Template)]
+#[template(path = "admin/two_factor/settings.html")]
+struct TwoFactorSettingsPage {
+    nav: Nav,
+    flash_messages: FlashMessages,
+    csrf_token: CsrfToken,
+    /// `None` once two-factor authentication is enabled.
+    enrolment: Option<Enrolment>,
+}
+
+/// What the user needs to add the secret to their authenticator app.
+struct Enrolment {
+    /// An SVG image.
+    qr_code: String,
+    secret: String,
+    uri: String,
+}
+
+pub async fn two_factor_settings(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    settings: web::Data<TwoFactorSettings>,
+    flash_messages: IncomingFlashMessages,
+    role: web::ReqData<Role>,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    let enrolment = if is_two_factor_enabled(&pool, **user_id)
+        .await
+        .map_err(e500)?
+    {
+        None
+    } else {
+        // Keep showing the same secret until it is confirmed, in case the
+        // page is reloaded after scanning the QR code.
+        let secret = match session.get_totp_enrolment().map_err(e500)? {
+            Some(secret) => TotpSecret::parse_base32(&secret).map_err(e500)?,
+            None => {
+                let secret = TotpSecret::generate();
+                session
+                    .insert_totp_enrolment(&secret.to_base32())
+                    .map_err(e500)?;
+                secret
+            }
+        };
+        let username = get_username(**user_id, &pool).await.map_err(e500)?;
+        let uri = secret.otpauth_uri(&settings.issuer, &username);
+        let qr_code = QrCode::new(uri.as_bytes())
+            .map_err(e500)?
+            .render::<svg::Color>()
+            .min_dimensions(200, 200)
+            .build();
+        Some(Enrolment {
+            qr_code,
+            secret: secret.to_base32(),
+            uri,
+        })
+    };
+    render(&TwoFactorSettingsPage {
+        nav: Nav::new(*role),
+        flash_messages: flash_messages.into(),
+        csrf_token,
+        enrolment,
+    })
+}
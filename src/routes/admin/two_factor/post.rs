@@ -0,0 +1,86 @@
+//! src/routes/admin/two_factor/post.rs
+use crate::authentication::{self, Role, TotpSecret, UserId};
+use crate::configurations::TwoFactorSettings;
+use crate::session_state::TypedSession;
+use crate::templates::{FlashMessages, Nav, render};
+use crate::utils::{e500, see_other};
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use askama::Template;
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: SecretString,
+}
+
+#[derive(Template)]
+#[template(path = "admin/two_factor/recovery_codes.html")]
+struct RecoveryCodesPage {
+    nav: Nav,
+    flash_messages: FlashMessages,
+    recovery_codes: Vec<String>,
+}
+
+/// Confirm the secret shown on the settings page and show the recovery
+/// codes, once.
+pub async fn enable_two_factor(
+    form: web::Form<FormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    settings: web::Data<TwoFactorSettings>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(secret) = session.get_totp_enrolment().map_err(e500)? else {
+        FlashMessage::error("Scan the QR code again: the setup has expired.").send();
+        return Ok(see_other("/admin/two-factor"));
+    };
+    let secret = TotpSecret::parse_base32(&secret).map_err(e500)?;
+    let recovery_codes = authentication::enable_two_factor(
+        &pool,
+        **user_id,
+        &secret,
+        form.code.expose_secret(),
+        &settings,
+    )
+    .await
+    .map_err(e500)?;
+    let Some(recovery_codes) = recovery_codes else {
+        FlashMessage::error("Invalid authentication code.").send();
+        return Ok(see_other("/admin/two-factor"));
+    };
+    session.remove_totp_enrolment();
+
+    render(&RecoveryCodesPage {
+        nav: Nav::new(*role),
+        flash_messages: FlashMessages::default(),
+        recovery_codes,
+    })
+}
+
+pub async fn disable_two_factor(
+    form: web::Form<FormData>,
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<TwoFactorSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let verified = authentication::verify_second_factor(
+        &pool,
+        **user_id,
+        form.code.expose_secret(),
+        &settings,
+    )
+    .await
+    .map_err(e500)?;
+    if verified {
+        authentication::disable_two_factor(&pool, **user_id)
+            .await
+            .map_err(e500)?;
+        FlashMessage::info("Two-factor authentication has been disabled.").send();
+    } else {
+        FlashMessage::error("Invalid authentication code.").send();
+    }
+    Ok(see_other("/admin/two-factor"))
+}
@@ -0,0 +1,50 @@
+//! src/routes/admin/users/get.rs
+use crate::authentication::{Permission, Role, User, UserId, UserStatus, get_users};
+use crate::csrf::CsrfToken;
+use crate::templates::{FlashMessages, Nav, render};
+use crate::utils::e500;
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Template)]
+#[template(path = "admin/users.html")]
+struct UsersPage {
+    nav: Nav,
+    flash_messages: FlashMessages,
+    csrf_token: CsrfToken,
+    users: Vec<User>,
+    roles: &'static [Role],
+    current_user_id: Uuid,
+}
+
+impl UsersPage {
+    fn is_you(&self, user: &User) -> bool {
+        user.user_id == self.current_user_id
+    }
+
+    fn is_selected(&self, role: &Role, selected: &Role) -> bool {
+        role == selected
+    }
+}
+
+pub async fn users(
+    user_id: web::ReqData<UserId>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    role: web::ReqData<Role>,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::ManageUsers)?;
+    let users = get_users(&pool).await.map_err(e500)?;
+    render(&UsersPage {
+        nav: Nav::new(*role),
+        flash_messages: flash_messages.into(),
+        csrf_token,
+        users,
+        roles: &Role::ALL,
+        current_user_id: **user_id,
+    })
+}
@@ -0,0 +1,7 @@
+//! src/routes/admin/users/mod.rs
+mod get;
+mod post;
+pub use get::users;
+pub use post::{
+    change_user_role, delete_user, disable_user, enable_user, invite_user, reset_two_factor,
+};
@@ -0,0 +1,202 @@
+//! src/routes/admin/users/post.rs
+use crate::authentication::{self, InvitationToken, Permission, Role, UserManagementError};
+use crate::configurations::UserSettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::utils::{e500, see_other};
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::PgPool;
+use unicode_segmentation::UnicodeSegmentation;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct InviteFormData {
+    username: String,
+    email: String,
+    role: String,
+}
+
+#[tracing::instrument(
+    name = "Invite a user",
+    skip(form, pool, email_client, base_url, secret, settings),
+    fields(username = %form.username)
+)]
+pub async fn invite_user(
+    form: web::Form<InviteFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    secret: web::Data<HmacSecret>,
+    settings: web::Data<UserSettings>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::ManageUsers)?;
+    let username = form.0.username.trim().to_owned();
+    if username.is_empty() || username.graphemes(true).count() > 64 {
+        FlashMessage::error("The username must be between 1 and 64 characters long.").send();
+        return Ok(see_other("/admin/users"));
+    }
+    let email = match SubscriberEmail::parse(form.0.email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/users"));
+        }
+    };
+    let user_role = match Role::parse(&form.0.role) {
+        Ok(role) => role,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/users"));
+        }
+    };
+    let user_id = match authentication::insert_invited_user(
+        &pool,
+        &username,
+        email.as_ref(),
+        user_role,
+    )
+    .await
+    {
+        Ok(user_id) => user_id,
+        Err(e @ UserManagementError::AlreadyTaken) => {
+            FlashMessage::error(e.to_string()).send();
+            return Ok(see_other("/admin/users"));
+        }
+        Err(e) => return Err(e500(e)),
+    };
+    let expires_at =
+        chrono::Utc::now() + chrono::Duration::from_std(settings.invitation_ttl()).map_err(e500)?;
+    let token = InvitationToken::new(user_id, expires_at, &secret.0);
+    send_invitation_email(&email_client, &email, &username, &base_url.0, &token)
+        .await
+        .map_err(e500)?;
+    FlashMessage::info(format!(
+        "An invitation has been sent to {}.",
+        email.as_ref()
+    ))
+    .send();
+    Ok(see_other("/admin/users"))
+}
+
+async fn send_invitation_email(
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    username: &str,
+    base_url: &str,
+    token: &InvitationToken,
+) -> Result<(), anyhow::Error> {
+    let link = format!("{}/invitations/accept?token={}", base_url, token.as_ref());
+    let html_body = format!(
+        "You have been invited to manage our newsletter as <b>{}</b>.<br />\
+        Click <a href=\"{}\">here</a> to choose your password.",
+        htmlescape::encode_minimal(username),
+        link
+    );
+    let text_body = format!(
+        "You have been invited to manage our newsletter as {}.\n\
+        Visit {} to choose your password.",
+        username, link
+    );
+    email_client
+        .send_email(
+            recipient,
+            "You have been invited to manage our newsletter",
+            &html_body,
+            &text_body,
+        )
+        .await
+        .context("Failed to send an invitation email.")
+}
+
+#[derive(serde::Deserialize)]
+pub struct UserFormData {
+    user_id: Uuid,
+}
+
+pub async fn disable_user(
+    form: web::Form<UserFormData>,
+    pool: web::Data<PgPool>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::ManageUsers)?;
+    let outcome = authentication::disable_user(&pool, form.user_id).await;
+    report(outcome, "The user has been disabled.")
+}
+
+pub async fn enable_user(
+    form: web::Form<UserFormData>,
+    pool: web::Data<PgPool>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::ManageUsers)?;
+    let outcome = authentication::enable_user(&pool, form.user_id).await;
+    report(outcome, "The user has been enabled.")
+}
+
+pub async fn delete_user(
+    form: web::Form<UserFormData>,
+    pool: web::Data<PgPool>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::ManageUsers)?;
+    let outcome = authentication::delete_user(&pool, form.user_id).await;
+    report(outcome, "The user has been deleted.")
+}
+
+#[derive(serde::Deserialize)]
+pub struct RoleFormData {
+    user_id: Uuid,
+    role: String,
+}
+
+pub async fn change_user_role(
+    form: web::Form<RoleFormData>,
+    pool: web::Data<PgPool>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::ManageUsers)?;
+    let new_role = match Role::parse(&form.role) {
+        Ok(role) => role,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(see_other("/admin/users"));
+        }
+    };
+    let outcome = authentication::change_role(&pool, form.user_id, new_role).await;
+    report(outcome, "The role of the user has been changed.")
+}
+
+/// For users who lost both their authenticator app and their recovery
+/// codes: they log in with their password only, until they enable
+/// two-factor authentication again.
+pub async fn reset_two_factor(
+    form: web::Form<UserFormData>,
+    pool: web::Data<PgPool>,
+    role: web::ReqData<Role>,
+) -> Result<HttpResponse, actix_web::Error> {
+    role.require(Permission::ManageUsers)?;
+    let outcome = authentication::disable_two_factor(&pool, form.user_id)
+        .await
+        .map_err(UserManagementError::from);
+    report(
+        outcome,
+        "The two-factor authentication of the user has been reset.",
+    )
+}
+
+// Flash the outcome of an action on a user and go back to the list.
+fn report(
+    outcome: Result<(), UserManagementError>,
+    success: &str,
+) -> Result<HttpResponse, actix_web::Error> {
+    match outcome {
+        Ok(()) => FlashMessage::info(success).send(),
+        Err(e @ UserManagementError::UnexpectedError(_)) => return Err(e500(e)),
+        Err(e) => FlashMessage::error(e.to_string()).send(),
+    }
+    Ok(see_other("/admin/users"))
+}
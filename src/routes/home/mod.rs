@@ -1,9 +1,12 @@
 //! src/routes/home/mod.rs
+use crate::templates::render;
 use actix_web::HttpResponse;
-use actix_web::http::header::ContentType;
+use askama::Template;
 
-pub async fn home() -> HttpResponse {
-    HttpResponse::Ok()
-        .content_type(ContentType::html())
-        .body(include_str!("home.html"))
+#[derive(Template)]
+#[template(path = "home.html")]
+struct HomePage;
+
+pub async fn home() -> Result<HttpResponse, actix_web::Error> {
+    render(&HomePage)
 }
@@ -0,0 +1,42 @@
+//! src/routes/invitation/get.rs
+use super::InvitationParameters;
+use crate::authentication::{InvitationToken, get_invited_user};
+use crate::csrf::CsrfToken;
+use crate::startup::HmacSecret;
+use crate::templates::{FlashMessages, render};
+use crate::utils::{e400, e500};
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use sqlx::PgPool;
+
+#[derive(Template)]
+#[template(path = "invitation.html")]
+struct AcceptInvitationPage<'a> {
+    flash_messages: FlashMessages,
+    csrf_token: CsrfToken,
+    username: String,
+    token: &'a str,
+}
+
+/// The landing page of the link sent to invited users, where they choose
+/// their password.
+pub async fn accept_invitation_form(
+    parameters: web::Query<InvitationParameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = InvitationToken::verify(&parameters.token, &secret.0).map_err(e400)?;
+    let (username, _) = get_invited_user(&pool, user_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e400("This invitation has already been accepted."))?;
+    render(&AcceptInvitationPage {
+        flash_messages: flash_messages.into(),
+        csrf_token,
+        username,
+        token: &parameters.token,
+    })
+}
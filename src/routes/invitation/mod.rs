@@ -0,0 +1,10 @@
+//! src/routes/invitation/mod.rs
+mod get;
+mod post;
+pub use get::accept_invitation_form;
+pub use post::accept_invitation;
+
+#[derive(serde::Deserialize)]
+pub struct InvitationParameters {
+    token: String,
+}
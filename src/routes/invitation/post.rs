@@ -0,0 +1,60 @@
+//! src/routes/invitation/post.rs
+use super::InvitationParameters;
+use crate::authentication::{
+    InvitationToken, PasswordPolicy, activate_invited_user, get_invited_user,
+};
+use crate::configurations::PasswordHashingSettings;
+use crate::startup::HmacSecret;
+use crate::utils::{e400, e500, see_other};
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    password: SecretString,
+    password_check: SecretString,
+}
+
+#[tracing::instrument(
+    name = "Accept an invitation",
+    skip(parameters, form, pool, secret, hashing, policy),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn accept_invitation(
+    parameters: web::Query<InvitationParameters>,
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+    hashing: web::Data<PasswordHashingSettings>,
+    policy: web::Data<PasswordPolicy>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = InvitationToken::verify(&parameters.token, &secret.0).map_err(e400)?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    let form_location = format!(
+        "/invitations/accept?token={}",
+        urlencoding::encode(&parameters.token)
+    );
+    if form.password.expose_secret() != form.password_check.expose_secret() {
+        FlashMessage::error("You entered two different passwords - the field values must match.")
+            .send();
+        return Ok(see_other(&form_location));
+    }
+    let (username, _) = get_invited_user(&pool, user_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e400("This invitation has already been accepted."))?;
+    if let Err(e) = policy.check(&form.password, &username, None) {
+        FlashMessage::error(e.to_string()).send();
+        return Ok(see_other(&form_location));
+    }
+    let activated = activate_invited_user(user_id, form.0.password, &hashing, &pool)
+        .await
+        .map_err(e500)?;
+    if !activated {
+        return Err(e400("This invitation has already been accepted."));
+    }
+    FlashMessage::info("Your account is ready: you can now log in.").send();
+    Ok(see_other("/login"))
+}
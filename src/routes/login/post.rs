@@ -1,17 +1,20 @@
 //! src/routes/login/post.rs
-use crate::authentication::{AuthError, Credentials, validate_credentials};
+use crate::authentication::{
+    AuthError, Credentials, Lockout, LoginThrottle, get_active_user, is_two_factor_enabled,
+    start_user_session, validate_credentials,
+};
+use crate::configurations::PasswordHashingSettings;
 use crate::routes::error_chain_fmt;
-use crate::startup::HmacSecret;
 use crate::session_state::TypedSession;
-use actix_web::HttpResponse;
-use actix_web::cookie::Cookie;
 use actix_web::error::InternalError;
-use actix_web::http::{StatusCode, header::LOCATION};
-use actix_web::{ResponseError, web};
+use actix_web::http::header::{LOCATION, USER_AGENT};
+use actix_web::web;
+use actix_web::{HttpRequest, HttpResponse};
 use actix_web_flash_messages::FlashMessage;
-use hmac::{Hmac, Mac};
-use secrecy::{ExposeSecret, SecretString};
+use anyhow::Context;
+use secrecy::SecretString;
 use sqlx::PgPool;
+use std::net::{IpAddr, Ipv4Addr};
 
 #[derive(serde::Deserialize)]
 pub struct FormData {
@@ -20,56 +23,102 @@ pub struct FormData {
 }
 
 #[tracing::instrument(
-skip(form, pool, secret, session),
+skip(form, pool, hashing, session, throttle, request),
 fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
 )]
 // We are now injecting `PgPool` to retrieve stored credentials from the database
 pub async fn login(
     form: web::Form<FormData>,
     pool: web::Data<PgPool>,
-    secret: web::Data<HmacSecret>,
+    hashing: web::Data<PasswordHashingSettings>,
     session: TypedSession,
+    throttle: web::Data<LoginThrottle>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, InternalError<LoginError>> {
     let credentials = Credentials {
         username: form.0.username,
         password: form.0.password,
     };
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    let username = credentials.username.clone();
+    tracing::Span::current().record("username", tracing::field::display(&username));
+    let client_ip = throttle.client_ip(&request);
+    // The peer address is only missing for requests built by hand.
+    let ip = client_ip.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
 
-    match validate_credentials(credentials, &pool).await {
+    // Locked out attempts are refused before spending time on the hash.
+    if let Some(lockout) = throttle
+        .check(&username, ip)
+        .await
+        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?
+    {
+        return Err(login_redirect(LoginError::LockedOut(lockout)));
+    }
+
+    match validate_credentials(credentials, &hashing, &pool).await {
         Ok(user_id) => {
-            tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
+            tracing::Span::current().record("user_id", tracing::field::display(&user_id));
             session.renew();
+            let two_factor_enabled = is_two_factor_enabled(&pool, user_id)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            if two_factor_enabled {
+                // Only partially authenticated: `reject_anonymous_users`
+                // keeps the admin panel closed until the second step.
+                session
+                    .insert_two_factor_pending(user_id)
+                    .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+                // The failures are only cleared once the second factor
+                // checks out too: entering the password again must not
+                // allow more guesses of the code.
+                return Ok(HttpResponse::SeeOther()
+                    .insert_header((LOCATION, "/login/two-factor"))
+                    .finish());
+            }
+            throttle
+                .record_success(&username)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            let user = get_active_user(&pool, user_id)
+                .await
+                .and_then(|u| u.context("The user is not active anymore."))
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
             session
                 .insert_user_id(user_id)
                 .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            session
+                .insert_session_epoch(user.session_epoch)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
+            let user_agent = request
+                .headers()
+                .get(USER_AGENT)
+                .and_then(|ua| ua.to_str().ok());
+            let session_id = start_user_session(&pool, user_id, client_ip, user_agent)
+                .await
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+            session
+                .insert_session_id(session_id)
+                .map_err(|e| login_redirect(LoginError::UnexpectedError(e.into())))?;
             Ok(HttpResponse::SeeOther()
                 .insert_header((LOCATION, "/admin/dashboard"))
                 .finish())
         }
         Err(e) => {
             let e = match e {
-                AuthError::InvalidCredentials(_) => LoginError::AuthError(e.into()),
+                AuthError::InvalidCredentials(_) => {
+                    let failure = throttle
+                        .record_failure(&username, ip)
+                        .await
+                        .map_err(|e| login_redirect(LoginError::UnexpectedError(e)))?;
+                    // Slow down guessing, more and more with every failure.
+                    tokio::time::sleep(failure.delay).await;
+                    match failure.lockout {
+                        Some(lockout) => LoginError::LockedOut(lockout),
+                        None => LoginError::AuthError(e.into()),
+                    }
+                }
                 AuthError::UnexpectedError(_) => LoginError::UnexpectedError(e.into()),
             };
 
-            // let query_string = format!("error={}", urlencoding::Encoded::new(e.to_string()));
-
-            // let hmac_tag = {
-            //     let mut mac =
-            //         Hmac::<sha2::Sha256>::new_from_slice(secret.0.expose_secret().as_bytes())
-            //             .unwrap();
-            //     mac.update(query_string.as_bytes());
-            //     mac.finalize().into_bytes()
-            // };
-
-            // let response = HttpResponse::SeeOther()
-            //     .insert_header((
-            //         LOCATION,
-            //         format!("/login?{}&tag={:x}", query_string, hmac_tag),
-            //     ))
-            //     .finish();
-            // Err(InternalError::from_response(e, response))
             FlashMessage::error(e.to_string()).send();
             let response = HttpResponse::SeeOther()
                 // No cookies here now!
@@ -93,6 +142,11 @@ fn login_redirect(e: LoginError) -> InternalError<LoginError> {
 pub enum LoginError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
+    #[error(
+        "Too many failed login attempts: try again in {} minute(s).",
+        .0.remaining().as_secs().div_ceil(60)
+    )]
+    LockedOut(Lockout),
     #[error("Something went wrong")]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -102,22 +156,3 @@ impl std::fmt::Debug for LoginError {
         error_chain_fmt(self, f)
     }
 }
-
-// impl ResponseError for LoginError {
-//     fn error_response(&self) -> HttpResponse {
-//         let query_string = urlencoding::Encoded::new(self.to_string());
-//         let secret: &[u8] = todo!();
-//         let hmac_tag = {
-//             let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret).unwrap();
-//             mac.update(query_string.as_bytes());
-//             mac.finalize().into_bytes()
-//         };
-
-//         HttpResponse::build(self.status_code())
-//             .insert_header((LOCATION, format!("/login?{query_string}&tag={hmac_tag:x}")))
-//             .finish()
-//     }
-//     fn status_code(&self) -> StatusCode {
-//         StatusCode::SEE_OTHER
-//     }
-// }
@@ -2,15 +2,25 @@
 mod admin;
 mod health_check;
 mod home;
+mod invitation;
 mod login;
 mod newsletter;
+mod password_reset;
 mod subscription_confirms;
 mod subscriptions;
+mod two_factor;
+mod unsubscribe;
+mod webhooks;
 
 pub use admin::*;
 pub use health_check::*;
 pub use home::*;
+pub use invitation::*;
 pub use login::*;
 pub use newsletter::*;
+pub use password_reset::*;
 pub use subscription_confirms::*;
 pub use subscriptions::*;
+pub use two_factor::*;
+pub use unsubscribe::*;
+pub use webhooks::*;
@@ -1,23 +1,23 @@
 //! src/routes/newsletters.rs
-use crate::authentication::{AuthError, Credentials, validate_credentials};
-use crate::{domain::SubscriberEmail, email_client::EmailClient, routes::error_chain_fmt};
-use actix_web::{
-    HttpRequest, HttpResponse, ResponseError,
-    http::{
-        StatusCode,
-        header::{HeaderMap, HeaderValue},
-    },
-    web,
+use crate::authentication::{ApiScope, ApiUser, Permission, Role};
+use crate::idempotency::{IdempotencyKey, NextAction, save_response, try_processing};
+use crate::newsletter_issues::{
+    IssueContent, enqueue_delivery_tasks, get_delivery_report, insert_newsletter_issue,
 };
+use crate::routes::error_chain_fmt;
+use actix_web::{HttpResponse, ResponseError, http::StatusCode, web};
 use anyhow::Context;
-use base64::{Engine as _, engine::general_purpose};
-use secrecy::SecretString;
 use sqlx::PgPool;
+use uuid::Uuid;
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
-    #[error("Authentication failed.")]
-    AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The credentials do not grant the `{0}` scope.")]
+    MissingScope(ApiScope),
+    #[error("The {0} role is not allowed to publish newsletters.")]
+    Forbidden(Role),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -32,32 +32,19 @@ impl std::fmt::Debug for PublishError {
 impl ResponseError for PublishError {
     fn status_code(&self) -> StatusCode {
         match self {
-            PublishError::AuthError(_) => StatusCode::UNAUTHORIZED,
+            PublishError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PublishError::MissingScope(_) | PublishError::Forbidden(_) => StatusCode::FORBIDDEN,
             PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
-
-    fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
-        match self {
-            PublishError::UnexpectedError(_) => {
-                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
-            }
-            PublishError::AuthError(_) => {
-                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
-                let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
-                response
-                    .headers_mut()
-                    .insert(actix_web::http::header::WWW_AUTHENTICATE, header_value);
-                response
-            }
-        }
-    }
 }
 
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     title: String,
     content: Content,
+    // Optional, to keep clients that predate idempotency keys working.
+    idempotency_key: Option<String>,
 }
 #[derive(serde::Deserialize)]
 pub struct Content {
@@ -67,116 +54,123 @@ pub struct Content {
 
 #[tracing::instrument(
     name = "Publish a newsletter issue",
-    skip(body, pool, email_client, request),
-    fields(username=tracing::field::Empty, user_id=tracing::field::Empty)
+    skip(body, pool, api_user),
+    fields(user_id=%api_user.user_id, token_id=?api_user.token_id)
 )]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>,
-    request: HttpRequest,
+    api_user: ApiUser,
 ) -> Result<HttpResponse, PublishError> {
-    let subscribers = get_confirmed_subscribers(&pool).await?;
-
-    let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
-    let user_id = validate_credentials(credentials, &pool)
-        .await
-        .map_err(|e| match e {
-            AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
-            AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
-        })?;
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
-                    })?;
-            }
-
-            Err(error) => {
-                tracing::warn!(
-                    // We record the error chain as a structured field
-                    // on the log record.
-                    error.cause_chain = ?error,
-                    // Using `\` to split a long string literal over
-                    // two lines, without creating a `\n` character.
-                    "Skipping a confirmed subscriber. \
-                    Their stored contact details are invalid",
-                );
+    if !api_user.has_scope(ApiScope::PublishNewsletters) {
+        return Err(PublishError::MissingScope(ApiScope::PublishNewsletters));
+    }
+    if !api_user.role.can(Permission::PublishNewsletters) {
+        return Err(PublishError::Forbidden(api_user.role));
+    }
+    let user_id = api_user.user_id;
+
+    let BodyData {
+        title,
+        content,
+        idempotency_key,
+    } = body.0;
+    let idempotency_key: Option<IdempotencyKey> = idempotency_key
+        .map(TryInto::try_into)
+        .transpose()
+        .map_err(|e: anyhow::Error| PublishError::ValidationError(e.to_string()))?;
+
+    let content = IssueContent {
+        title,
+        text_content: content.text,
+        html_content: content.html,
+    };
+    content
+        .check_merge_tags()
+        .map_err(PublishError::ValidationError)?;
+
+    let mut transaction = match &idempotency_key {
+        Some(idempotency_key) => match try_processing(&pool, idempotency_key, user_id).await? {
+            NextAction::StartProcessing(t) => t,
+            NextAction::ReturnSavedResponse(saved_response) => {
+                return Ok(saved_response);
             }
+        },
+        None => pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?,
+    };
+    let issue_id = insert_newsletter_issue(&mut transaction, user_id, &content)
+        .await
+        .context("Failed to store newsletter issue details")?;
+    enqueue_delivery_tasks(&mut transaction, issue_id)
+        .await
+        .context("Failed to enqueue delivery tasks")?;
+    let response = HttpResponse::Accepted().finish();
+    let response = match &idempotency_key {
+        Some(idempotency_key) => save_response(transaction, idempotency_key, user_id, response)
+            .await
+            .context("Failed to save the response for an idempotent request")?,
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to store a newsletter issue.")?;
+            response
         }
-    }
-    Ok(HttpResponse::Ok().finish())
+    };
+    Ok(response)
 }
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
+#[derive(thiserror::Error)]
+pub enum ReportError {
+    #[error("The credentials do not grant the `{0}` scope.")]
+    MissingScope(ApiScope),
+    #[error("The {0} role is not allowed to view reports.")]
+    Forbidden(Role),
+    #[error("There is no published newsletter issue with this id.")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
 }
 
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers = sqlx::query!(
-        r#"
-        SELECT email
-        FROM subscriptions
-        WHERE status = 'confirmed'
-        "#,
-    )
-    .fetch_all(pool)
-    .await?
-    .into_iter()
-    .map(|r| match SubscriberEmail::parse(r.email) {
-        Ok(email) => Ok(ConfirmedSubscriber { email }),
-        Err(error) => Err(anyhow::anyhow!(error)),
-    })
-    .collect();
-    Ok(confirmed_subscribers)
+impl std::fmt::Debug for ReportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
 }
 
-fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
-    let header_value = headers
-        .get("Authorization")
-        .context("The 'Authorization' header was missing")?
-        .to_str()
-        .context("The 'Authorization' header was not a valid UTF8 string.")?;
-
-    let base64encoded_segment = header_value
-        .strip_prefix("Basic ")
-        .context("The authorization scheme was not 'Basic'.")?;
-
-    let decoded_bytes = general_purpose::STANDARD
-        .decode(base64encoded_segment)
-        .context("Failed to base64-decode 'Basic' credentials.")?;
-
-    let decoded_credentials = String::from_utf8(decoded_bytes)
-        .context("The decoded credential string is not valid UTF8.")?;
-
-    // Split into two segments, using ':' as delimitator
-    let mut credentials = decoded_credentials.splitn(2, ':');
-    let username = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A username must be provided in 'Basic' auth."))?
-        .to_string();
-    let password = credentials
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("A password must be provided in 'Basic' auth."))?
-        .to_string();
+impl ResponseError for ReportError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ReportError::MissingScope(_) | ReportError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ReportError::NotFound => StatusCode::NOT_FOUND,
+            ReportError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
 
-    Ok(Credentials {
-        username,
-        password: SecretString::new(password.into_boxed_str()),
-    })
+/// The delivery report of an issue, as shown on
+/// `/admin/newsletters/{newsletter_issue_id}`, for reporting scripts.
+#[tracing::instrument(
+    name = "Get a newsletter delivery report",
+    skip(pool, api_user),
+    fields(user_id=%api_user.user_id, token_id=?api_user.token_id)
+)]
+pub async fn newsletter_report(
+    newsletter_issue_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    api_user: ApiUser,
+) -> Result<HttpResponse, ReportError> {
+    if !api_user.has_scope(ApiScope::ViewReports) {
+        return Err(ReportError::MissingScope(ApiScope::ViewReports));
+    }
+    if !api_user.role.can(Permission::ViewReports) {
+        return Err(ReportError::Forbidden(api_user.role));
+    }
+    let report = get_delivery_report(&pool, *newsletter_issue_id)
+        .await?
+        .ok_or(ReportError::NotFound)?;
+    Ok(HttpResponse::Ok().json(report))
 }
@@ -0,0 +1,58 @@
+//! src/routes/password_reset/get.rs
+use super::ResetParameters;
+use crate::authentication::get_password_reset_username;
+use crate::csrf::CsrfToken;
+use crate::templates::{FlashMessages, render};
+use crate::utils::{e400, e500};
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+use secrecy::ExposeSecret;
+use sqlx::PgPool;
+
+#[derive(Template)]
+#[template(path = "password_reset/forgot.html")]
+struct ForgotPasswordPage {
+    flash_messages: FlashMessages,
+    csrf_token: CsrfToken,
+}
+
+pub async fn forgot_password_form(
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    render(&ForgotPasswordPage {
+        flash_messages: flash_messages.into(),
+        csrf_token,
+    })
+}
+
+#[derive(Template)]
+#[template(path = "password_reset/reset.html")]
+struct ResetPasswordPage {
+    flash_messages: FlashMessages,
+    csrf_token: CsrfToken,
+    /// URL-encoded, for the action of the form.
+    token: String,
+}
+
+/// The landing page of the link sent by `forgot_password`.
+pub async fn reset_password_form(
+    parameters: web::Query<ResetParameters>,
+    pool: web::Data<PgPool>,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    if get_password_reset_username(&pool, &parameters.token)
+        .await
+        .map_err(e500)?
+        .is_none()
+    {
+        return Err(e400("The reset link is invalid or has expired."));
+    }
+    render(&ResetPasswordPage {
+        flash_messages: flash_messages.into(),
+        csrf_token,
+        token: urlencoding::encode(parameters.token.expose_secret()).into_owned(),
+    })
+}
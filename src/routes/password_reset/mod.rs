@@ -0,0 +1,12 @@
+//! src/routes/password_reset/mod.rs
+mod get;
+mod post;
+pub use get::{forgot_password_form, reset_password_form};
+pub use post::{forgot_password, reset_password};
+
+use secrecy::SecretString;
+
+#[derive(serde::Deserialize)]
+pub struct ResetParameters {
+    token: SecretString,
+}
@@ -0,0 +1,145 @@
+//! src/routes/password_reset/post.rs
+use super::ResetParameters;
+use crate::authentication::{self, PasswordPolicy, PasswordResetRequest};
+use crate::configurations::{PasswordHashingSettings, UserSettings};
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::{e400, e500, see_other};
+use actix_web::{HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+use tracing::Instrument;
+
+#[derive(serde::Deserialize)]
+pub struct ForgotFormData {
+    username: String,
+}
+
+/// Email a reset link to the user, if they exist.
+///
+/// The response is the same either way - and the email is sent in the
+/// background, so that it does not take longer for existing users.
+#[tracing::instrument(
+    name = "Request a password reset",
+    skip(form, pool, email_client, base_url, settings),
+    fields(username = %form.username)
+)]
+pub async fn forgot_password(
+    form: web::Form<ForgotFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<UserSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let expires_at = chrono::Utc::now()
+        + chrono::Duration::from_std(settings.password_reset_ttl()).map_err(e500)?;
+    let request = authentication::request_password_reset(
+        &pool,
+        &form.username,
+        expires_at,
+        settings.password_reset_requests_per_hour,
+    )
+    .await
+    .map_err(e500)?;
+    if let Some(request) = request {
+        let email_client = email_client.into_inner();
+        let base_url = base_url.0.clone();
+        tokio::spawn(
+            async move {
+                if let Err(e) = send_reset_email(&email_client, &base_url, request).await {
+                    tracing::error!(
+                        error.cause_chain = ?e,
+                        error.message = %e,
+                        "Failed to send a password reset email",
+                    );
+                }
+            }
+            .instrument(tracing::Span::current()),
+        );
+    }
+    FlashMessage::info(
+        "If this user exists, a link to reset their password has been sent to their email address.",
+    )
+    .send();
+    Ok(see_other("/login/forgot"))
+}
+
+async fn send_reset_email(
+    email_client: &EmailClient,
+    base_url: &str,
+    request: PasswordResetRequest,
+) -> Result<(), anyhow::Error> {
+    let recipient = SubscriberEmail::parse(request.email).map_err(|e| anyhow::anyhow!(e))?;
+    let link = format!(
+        "{}/login/reset?token={}",
+        base_url,
+        request.token.expose_secret()
+    );
+    let html_body = format!(
+        "Someone asked to reset the password of <b>{}</b>.<br />\
+        Click <a href=\"{}\">here</a> to choose a new one.<br />\
+        If it was not you, you can ignore this email.",
+        htmlescape::encode_minimal(&request.username),
+        link
+    );
+    let text_body = format!(
+        "Someone asked to reset the password of {}.\n\
+        Visit {} to choose a new one.\n\
+        If it was not you, you can ignore this email.",
+        request.username, link
+    );
+    email_client
+        .send_email(&recipient, "Reset your password", &html_body, &text_body)
+        .await
+        .context("Failed to send a password reset email.")
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResetFormData {
+    new_password: SecretString,
+    new_password_check: SecretString,
+}
+
+#[tracing::instrument(
+    name = "Reset a password",
+    skip(parameters, form, pool, hashing, policy)
+)]
+pub async fn reset_password(
+    parameters: web::Query<ResetParameters>,
+    form: web::Form<ResetFormData>,
+    pool: web::Data<PgPool>,
+    hashing: web::Data<PasswordHashingSettings>,
+    policy: web::Data<PasswordPolicy>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let form_location = format!(
+        "/login/reset?token={}",
+        urlencoding::encode(parameters.token.expose_secret())
+    );
+    if form.new_password.expose_secret() != form.new_password_check.expose_secret() {
+        FlashMessage::error(
+            "You entered two different new passwords - the field values must match.",
+        )
+        .send();
+        return Ok(see_other(&form_location));
+    }
+    let username = authentication::get_password_reset_username(&pool, &parameters.token)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e400("The reset link is invalid or has expired."))?;
+    if let Err(e) = policy.check(&form.new_password, &username, None) {
+        FlashMessage::error(e.to_string()).send();
+        return Ok(see_other(&form_location));
+    }
+    let ResetParameters { token } = parameters.into_inner();
+    let reset = authentication::reset_password(&pool, &token, form.0.new_password, &hashing)
+        .await
+        .map_err(e500)?;
+    if !reset {
+        return Err(e400("The reset link is invalid or has expired."));
+    }
+    FlashMessage::info("Your password has been reset: you can now log in.").send();
+    Ok(see_other("/login"))
+}
@@ -1,62 +1,191 @@
 //! src/routes/subscriptions_confirm.rs
+use crate::configurations::SubscriptionSettings;
+use crate::domain::{SubscriberEmail, SubscriptionStatus, TransitionError, transition};
+use crate::email_client::EmailClient;
+use crate::routes::{new_token, send_confirmation_email};
+use crate::startup::ApplicationBaseUrl;
+use crate::templates::{render, render_with_status};
+use crate::utils::{e400, e500};
+use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, web};
-use sqlx::PgPool;
+use anyhow::Context;
+use askama::Template;
+use sqlx::{Executor, PgPool};
 
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
 }
 
+enum ConfirmOutcome {
+    Confirmed,
+    UnknownToken,
+    ExpiredToken,
+    UsedToken,
+}
+
 #[tracing::instrument(name = "Confirm a pending subscriber", skip(pool, parameters))]
 pub async fn confirm(parameters: web::Query<Parameters>, pool: web::Data<PgPool>) -> HttpResponse {
-    let id = match get_subscriber_id_from_token(&pool, &parameters.subscription_token).await {
-        Ok(id) => id,
-        Err(_) => return HttpResponse::InternalServerError().finish(),
-    };
-    match id {
+    match confirm_subscriber(&pool, &parameters.subscription_token).await {
+        Ok(ConfirmOutcome::Confirmed) => HttpResponse::Ok().finish(),
         // Non-existing token!
-        None => HttpResponse::Unauthorized().finish(),
-        Some(subscriber_id) => {
-            if confirm_subscriber(&pool, subscriber_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            }
-            HttpResponse::Ok().finish()
+        Ok(ConfirmOutcome::UnknownToken) => HttpResponse::Unauthorized().finish(),
+        Ok(ConfirmOutcome::ExpiredToken) => {
+            unusable_link_page("This confirmation link has expired.")
+        }
+        Ok(ConfirmOutcome::UsedToken) => {
+            unusable_link_page("This confirmation link has already been used.")
         }
+        // e.g. an old link, for a subscriber who has since unsubscribed
+        Err(TransitionError::IllegalTransition { .. }) => HttpResponse::Conflict().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
     }
 }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscriber_id, pool))]
-pub async fn confirm_subscriber(
-    pool: &PgPool,
-    subscriber_id: uuid::Uuid,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
-        subscriber_id,
-    )
-    .execute(pool)
-    .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    Ok(())
+#[derive(Template)]
+#[template(path = "subscriptions/unusable_link.html")]
+struct UnusableLinkPage<'a> {
+    reason: &'a str,
+}
+
+fn unusable_link_page(reason: &str) -> HttpResponse {
+    render_with_status(StatusCode::GONE, &UnusableLinkPage { reason })
+        .unwrap_or_else(|e| e.error_response())
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(subscription_token, pool))]
-pub async fn get_subscriber_id_from_token(
+/// Confirm the subscriber `subscription_token` was issued for, consuming it.
+#[tracing::instrument(name = "Mark subscriber as confirmed", skip(subscription_token, pool))]
+async fn confirm_subscriber(
     pool: &PgPool,
     subscription_token: &str,
-) -> Result<Option<uuid::Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        r#"SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1"#,
+) -> Result<ConfirmOutcome, TransitionError> {
+    let mut transaction = pool.begin().await?;
+    let token = sqlx::query!(
+        r#"
+        SELECT
+            subscriber_id,
+            expires_at <= now() AS "is_expired!",
+            consumed_at IS NOT NULL AS "is_consumed!"
+        FROM subscription_tokens
+        WHERE subscription_token = $1
+        FOR UPDATE
+        "#,
         subscription_token,
     )
-    .fetch_optional(pool)
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let Some(token) = token else {
+        return Ok(ConfirmOutcome::UnknownToken);
+    };
+    if token.is_consumed {
+        return Ok(ConfirmOutcome::UsedToken);
+    }
+    if token.is_expired {
+        return Ok(ConfirmOutcome::ExpiredToken);
+    }
+    match transition(
+        &mut transaction,
+        token.subscriber_id,
+        SubscriptionStatus::Confirmed,
+    )
     .await
-    .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
-        e
-    })?;
-    Ok(result.map(|r| r.subscriber_id))
+    {
+        // Another confirmation link, sent to the same subscriber, was used
+        // first: there is nothing left to do.
+        Ok(_)
+        | Err(TransitionError::IllegalTransition {
+            from: SubscriptionStatus::Confirmed,
+            ..
+        }) => {}
+        Err(e) => {
+            tracing::error!("Failed to confirm a subscriber: {:?}", e);
+            return Err(e);
+        }
+    }
+    transaction
+        .execute(sqlx::query!(
+            r#"UPDATE subscription_tokens SET consumed_at = now() WHERE subscription_token = $1"#,
+            subscription_token,
+        ))
+        .await?;
+    transaction.commit().await?;
+    Ok(ConfirmOutcome::Confirmed)
+}
+
+#[derive(Template)]
+#[template(path = "subscriptions/resend.html")]
+struct ResendConfirmationPage;
+
+pub async fn resend_confirmation_form() -> Result<HttpResponse, actix_web::Error> {
+    render(&ResendConfirmationPage)
+}
+
+#[derive(Template)]
+#[template(path = "subscriptions/resend_sent.html")]
+struct ConfirmationResentPage<'a> {
+    email: &'a str,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ResendFormData {
+    email: String,
+}
+
+/// Send a new confirmation link to a subscriber who is still pending.
+///
+/// The response is the same whether or not the email is waiting for
+/// a confirmation: it must not tell who is on the list.
+#[tracing::instrument(
+    name = "Resend a confirmation email",
+    skip(form, pool, email_client, base_url, settings),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn resend_confirmation(
+    form: web::Form<ResendFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<SubscriptionSettings>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = SubscriberEmail::parse(form.0.email).map_err(e400)?;
+    let subscription_token = rotate_token(&pool, &email, settings.confirmation_token_ttl())
+        .await
+        .map_err(e500)?;
+    if let Some(subscription_token) = subscription_token {
+        send_confirmation_email(&email_client, &email, &base_url.0, &subscription_token)
+            .await
+            .context("Failed to send a confirmation email.")
+            .map_err(e500)?;
+    }
+    render(&ConfirmationResentPage {
+        email: email.as_ref(),
+    })
+}
+
+/// Issue a new token for `email`, if it belongs to a pending subscriber.
+#[tracing::instrument(skip(pool))]
+async fn rotate_token(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+    ttl: std::time::Duration,
+) -> Result<Option<String>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT id, status AS "status: SubscriptionStatus"
+        FROM subscriptions
+        WHERE email = $1
+        FOR UPDATE
+        "#,
+        email.as_ref()
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    let subscriber_id = match subscriber {
+        Some(s) if s.status == SubscriptionStatus::Pending => s.id,
+        _ => return Ok(None),
+    };
+    let subscription_token = new_token(&mut transaction, subscriber_id, ttl).await?;
+    transaction.commit().await?;
+    Ok(Some(subscription_token))
 }
@@ -1,12 +1,17 @@
 //! src/routes/subscriptions.rs
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
-use crate::email_client::EmailClient;
+use crate::configurations::SubscriptionSettings;
+use crate::domain::{
+    NewSubscriber, SubscriberEmail, SubscriberName, SubscriptionStatus, is_suppressed,
+    record_status, transition,
+};
+use crate::email_client::{EmailClient, SendEmailError};
 use crate::startup::ApplicationBaseUrl;
 use actix_web::http::StatusCode;
 use actix_web::{HttpResponse, ResponseError, web};
 use anyhow::Context;
 use rand::{Rng, distributions::Alphanumeric, thread_rng};
 use sqlx::{Executor, PgPool, Postgres, Transaction};
+use std::time::Duration;
 use unicode_segmentation::UnicodeSegmentation;
 use uuid::Uuid;
 
@@ -50,7 +55,7 @@ impl ResponseError for SubscribeError {
 
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(form, pool, base_url),
+    skip(form, pool, email_client, base_url, settings),
     fields(
         subscriber_email = %form.email,
         subscriber_name= %form.name
@@ -61,36 +66,138 @@ pub async fn subscribe(
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
+    settings: web::Data<SubscriptionSettings>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let new_subscriber: NewSubscriber =
+        form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    let token_ttl = settings.confirmation_token_ttl();
+    // Addresses that bounced or complained do not get any more emails -
+    // and we do not tell.
+    if is_suppressed(&pool, new_subscriber.email.as_ref())
+        .await
+        .context("Failed to check the suppression list.")?
+    {
+        return Ok(HttpResponse::Ok().finish());
+    }
 
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
-        .await
-        .context("Failed to insert new subscriber in the database.")?;
-    let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
+    let subscription_token = match insert_subscriber(&mut transaction, &new_subscriber)
         .await
-        .context("Failed to store the confirmation token for a new subscriber.")?;
+        .context("Failed to insert new subscriber in the database.")?
+    {
+        Some(subscriber_id) => Some(new_token(&mut transaction, subscriber_id, token_ttl).await?),
+        None => resubscribe(&mut transaction, &new_subscriber, token_ttl)
+            .await
+            .context("Failed to handle the re-subscription of an existing subscriber.")?,
+    };
     transaction
         .commit()
         .await
         .context("Failed to commit SQL transaction to store a new subscriber.")?;
-    send_confirmation_email(
-        &email_client,
-        new_subscriber,
-        &base_url.0,
-        &subscription_token,
-    )
-    .await
-    .context("Failed to send a confirmation email.")?;
+    if let Some(subscription_token) = subscription_token {
+        send_confirmation_email(
+            &email_client,
+            &new_subscriber.email,
+            &base_url.0,
+            &subscription_token,
+        )
+        .await
+        .context("Failed to send a confirmation email.")?;
+    }
     Ok(HttpResponse::Ok().finish())
 }
 
+/// Someone submitted the form with an email we already know.
+/// Returns the token to send a confirmation email with, if any.
+///
+/// - pending: they lost the confirmation email, we send it again - with a
+///   fresh token if the previous one cannot be used anymore;
+/// - confirmed: nothing to do;
+/// - unsubscribed: they are back to pending and go through the double
+///   opt-in again;
+/// - bounced, complained or deleted: we must not email them, but we do not
+///   tell.
+#[tracing::instrument(skip_all)]
+async fn resubscribe(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+    token_ttl: Duration,
+) -> Result<Option<String>, anyhow::Error> {
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT id, status AS "status: SubscriptionStatus"
+        FROM subscriptions
+        WHERE email = $1
+        FOR UPDATE
+        "#,
+        new_subscriber.email.as_ref()
+    )
+    .fetch_one(&mut **transaction)
+    .await?;
+    match subscriber.status {
+        SubscriptionStatus::Pending => {
+            let token = get_token(transaction, subscriber.id).await?;
+            match token {
+                Some(token) => Ok(Some(token)),
+                None => Ok(Some(
+                    new_token(transaction, subscriber.id, token_ttl).await?,
+                )),
+            }
+        }
+        SubscriptionStatus::Unsubscribed => {
+            transition(transaction, subscriber.id, SubscriptionStatus::Pending).await?;
+            Ok(Some(
+                new_token(transaction, subscriber.id, token_ttl).await?,
+            ))
+        }
+        SubscriptionStatus::Confirmed
+        | SubscriptionStatus::Bounced
+        | SubscriptionStatus::Complained
+        | SubscriptionStatus::Deleted => Ok(None),
+    }
+}
+
+/// Generate and store a new confirmation token for `subscriber_id`.
+pub(crate) async fn new_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    ttl: Duration,
+) -> Result<String, anyhow::Error> {
+    let subscription_token = generate_subscription_token();
+    store_token(transaction, subscriber_id, &subscription_token, ttl)
+        .await
+        .context("Failed to store the confirmation token for a new subscriber.")?;
+    Ok(subscription_token)
+}
+
+/// The most recent token of `subscriber_id` that can still be used, if any.
+#[tracing::instrument(skip(transaction))]
+async fn get_token(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let token = sqlx::query!(
+        r#"
+        SELECT subscription_token
+        FROM subscription_tokens
+        WHERE
+            subscriber_id = $1 AND
+            consumed_at IS NULL AND
+            expires_at > now()
+        ORDER BY created_at DESC
+        LIMIT 1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut **transaction)
+    .await?;
+    Ok(token.map(|r| r.subscription_token))
+}
+
 #[tracing::instrument(
     name = "Store subscription token in the database",
     skip(subscription_token, transaction)
@@ -99,14 +206,16 @@ pub async fn store_token(
     transaction: &mut Transaction<'_, Postgres>,
     subscriber_id: Uuid,
     subscription_token: &str,
+    ttl: Duration,
 ) -> Result<(), StoreTokenError> {
     let query = sqlx::query!(
         r#"
-    INSERT INTO subscription_tokens (subscription_token, subscriber_id)
-    VALUES ($1, $2)
+    INSERT INTO subscription_tokens (subscription_token, subscriber_id, created_at, expires_at)
+    VALUES ($1, $2, now(), now() + make_interval(secs => $3))
         "#,
         subscription_token,
-        subscriber_id
+        subscriber_id,
+        ttl.as_secs_f64()
     );
     transaction.execute(query).await.map_err(StoreTokenError)?;
     Ok(())
@@ -147,35 +256,48 @@ fn generate_subscription_token() -> String {
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, transaction)
 )]
+/// Returns `None` if a subscriber with the same email already exists.
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
+) -> Result<Option<Uuid>, sqlx::Error> {
     let subscriber_id = Uuid::new_v4();
     let query = sqlx::query!(
         r#"
     INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-    VALUES ($1, $2, $3, $4, 'pending_confirmation')
+    VALUES ($1, $2, $3, $4, $5)
+    ON CONFLICT (email) DO NOTHING
             "#,
         subscriber_id,
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
-        chrono::Utc::now()
+        chrono::Utc::now(),
+        SubscriptionStatus::Pending as SubscriptionStatus
     );
-    transaction.execute(query).await?;
-    Ok(subscriber_id)
+    let n_inserted_rows = transaction.execute(query).await?.rows_affected();
+    if n_inserted_rows == 0 {
+        return Ok(None);
+    }
+    record_status(
+        transaction,
+        subscriber_id,
+        None,
+        SubscriptionStatus::Pending,
+    )
+    .await?;
+    Ok(Some(subscriber_id))
 }
 
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
-    skip(email_client, new_subscriber, base_url)
+    skip(email_client, recipient, base_url)
 )]
 pub async fn send_confirmation_email(
     email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+    recipient: &SubscriberEmail,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+) -> Result<(), SendEmailError> {
     let confirmation_link = &format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
@@ -191,12 +313,7 @@ pub async fn send_confirmation_email(
     );
 
     email_client
-        .send_email(
-            &new_subscriber.email,
-            "Welcome to newsletter",
-            &html_body,
-            &plain_body,
-        )
+        .send_email(recipient, "Welcome to newsletter", html_body, plain_body)
         .await
 }
 
@@ -0,0 +1,31 @@
+//! src/routes/two_factor/get.rs
+use crate::csrf::CsrfToken;
+use crate::session_state::TypedSession;
+use crate::templates::{FlashMessages, render};
+use crate::utils::{e500, see_other};
+use actix_web::HttpResponse;
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+
+#[derive(Template)]
+#[template(path = "two_factor.html")]
+struct TwoFactorPage {
+    flash_messages: FlashMessages,
+    csrf_token: CsrfToken,
+}
+
+/// The second step of the login, for users who enabled two-factor
+/// authentication.
+pub async fn two_factor_form(
+    session: TypedSession,
+    flash_messages: IncomingFlashMessages,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse, actix_web::Error> {
+    if session.get_two_factor_pending().map_err(e500)?.is_none() {
+        return Ok(see_other("/login"));
+    }
+    render(&TwoFactorPage {
+        flash_messages: flash_messages.into(),
+        csrf_token,
+    })
+}
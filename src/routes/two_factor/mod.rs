@@ -0,0 +1,5 @@
+//! src/routes/two_factor/mod.rs
+mod get;
+mod post;
+pub use get::two_factor_form;
+pub use post::verify_two_factor;
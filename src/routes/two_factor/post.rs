@@ -0,0 +1,86 @@
+//! src/routes/two_factor/post.rs
+use crate::authentication::{
+    Lockout, LoginThrottle, get_active_user, start_user_session, verify_second_factor,
+};
+use crate::configurations::TwoFactorSettings;
+use crate::routes::{LoginError, get_username};
+use crate::session_state::TypedSession;
+use crate::utils::{e500, see_other};
+use actix_web::http::header::USER_AGENT;
+use actix_web::{HttpRequest, HttpResponse, web};
+use actix_web_flash_messages::FlashMessage;
+use secrecy::{ExposeSecret, SecretString};
+use sqlx::PgPool;
+use std::net::{IpAddr, Ipv4Addr};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    code: SecretString,
+}
+
+#[tracing::instrument(
+    name = "Verify a second factor",
+    skip(form, pool, session, settings, throttle, request),
+    fields(user_id = tracing::field::Empty)
+)]
+pub async fn verify_two_factor(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    session: TypedSession,
+    settings: web::Data<TwoFactorSettings>,
+    throttle: web::Data<LoginThrottle>,
+    request: HttpRequest,
+) -> Result<HttpResponse, actix_web::Error> {
+    let Some(user_id) = session.get_two_factor_pending().map_err(e500)? else {
+        return Ok(see_other("/login"));
+    };
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+    // Wrong codes count as failed logins: guessing them is throttled and
+    // locked out the same way as guessing passwords.
+    let username = get_username(user_id, &pool).await.map_err(e500)?;
+    let ip = throttle
+        .client_ip(&request)
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    if let Some(lockout) = throttle.check(&username, ip).await.map_err(e500)? {
+        return Ok(back_to_login(&session, lockout));
+    }
+    let verified = verify_second_factor(&pool, user_id, form.code.expose_secret(), &settings)
+        .await
+        .map_err(e500)?;
+    if !verified {
+        let failure = throttle.record_failure(&username, ip).await.map_err(e500)?;
+        tokio::time::sleep(failure.delay).await;
+        if let Some(lockout) = failure.lockout {
+            return Ok(back_to_login(&session, lockout));
+        }
+        FlashMessage::error("Invalid authentication code.").send();
+        return Ok(see_other("/login/two-factor"));
+    }
+    throttle.record_success(&username).await.map_err(e500)?;
+    let user = get_active_user(&pool, user_id)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e500("The user is not active anymore."))?;
+    session.renew();
+    session.remove_two_factor_pending();
+    session.insert_user_id(user_id).map_err(e500)?;
+    session
+        .insert_session_epoch(user.session_epoch)
+        .map_err(e500)?;
+    let user_agent = request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|ua| ua.to_str().ok());
+    let session_id = start_user_session(&pool, user_id, throttle.client_ip(&request), user_agent)
+        .await
+        .map_err(e500)?;
+    session.insert_session_id(session_id).map_err(e500)?;
+    Ok(see_other("/admin/dashboard"))
+}
+
+/// Drop the half-finished login: the password has to be entered again.
+fn back_to_login(session: &TypedSession, lockout: Lockout) -> HttpResponse {
+    session.remove_two_factor_pending();
+    FlashMessage::error(LoginError::LockedOut(lockout).to_string()).send();
+    see_other("/login")
+}
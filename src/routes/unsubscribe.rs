@@ -0,0 +1,123 @@
+//! src/routes/unsubscribe.rs
+use crate::domain::{SubscriptionStatus, TransitionError, UnsubscribeToken, transition};
+use crate::newsletter_issues::cancel_queued_deliveries;
+use crate::routes::error_chain_fmt;
+use crate::startup::HmacSecret;
+use crate::templates::render;
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError, web};
+use anyhow::Context;
+use askama::Template;
+use sqlx::PgPool;
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeParameters {
+    token: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum UnsubscribeError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for UnsubscribeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for UnsubscribeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UnsubscribeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            UnsubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "subscriptions/unsubscribe.html")]
+struct UnsubscribePage<'a> {
+    email: String,
+    token: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "subscriptions/unsubscribed.html")]
+struct UnsubscribedPage;
+
+/// The landing page of the unsubscribe link found in every issue.
+///
+/// Following the link does not unsubscribe anybody: mail scanners open
+/// links too. The subscriber has to confirm by submitting the form.
+#[tracing::instrument(name = "Show the unsubscribe form", skip(parameters, secret))]
+pub async fn unsubscribe_form(
+    parameters: web::Query<UnsubscribeParameters>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = UnsubscribeToken::verify(&parameters.token, &secret.0)
+        .map_err(UnsubscribeError::ValidationError)?;
+    render(&UnsubscribePage {
+        email,
+        token: &parameters.token,
+    })
+}
+
+/// Unsubscribe the subscriber the token was issued for.
+///
+/// Mail clients call it directly, as RFC 8058 one-click unsubscribe
+/// (`List-Unsubscribe=One-Click` in the body), therefore it is idempotent
+/// and ignores the request body.
+#[tracing::instrument(name = "Unsubscribe a subscriber", skip(parameters, pool, secret))]
+pub async fn unsubscribe(
+    parameters: web::Query<UnsubscribeParameters>,
+    pool: web::Data<PgPool>,
+    secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = UnsubscribeToken::verify(&parameters.token, &secret.0)
+        .map_err(UnsubscribeError::ValidationError)?;
+    unsubscribe_subscriber(&pool, &email)
+        .await
+        .context("Failed to unsubscribe a subscriber.")
+        .map_err(UnsubscribeError::UnexpectedError)?;
+    render(&UnsubscribedPage)
+}
+
+/// Mark the subscriber as `unsubscribed` and cancel the deliveries still
+/// queued for them.
+///
+/// Subscribers who already left the list, one way or another, are left
+/// untouched.
+#[tracing::instrument(skip(pool))]
+async fn unsubscribe_subscriber(pool: &PgPool, email: &str) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let subscriber_id = sqlx::query!(r#"SELECT id FROM subscriptions WHERE email = $1"#, email)
+        .fetch_optional(&mut *transaction)
+        .await?
+        .map(|r| r.id);
+    let Some(subscriber_id) = subscriber_id else {
+        return Ok(());
+    };
+    match transition(
+        &mut transaction,
+        subscriber_id,
+        SubscriptionStatus::Unsubscribed,
+    )
+    .await
+    {
+        Ok(_) => {}
+        Err(TransitionError::IllegalTransition { .. }) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    }
+    cancel_queued_deliveries(
+        &mut transaction,
+        email,
+        "The subscriber unsubscribed before it was sent.",
+    )
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
@@ -9,6 +9,15 @@ pub struct TypedSession(Session);
 
 impl TypedSession {
     const USER_ID_KEY: &'static str = "user_id";
+    const SESSION_EPOCH_KEY: &'static str = "session_epoch";
+    /// The row of `user_sessions` tracking this session.
+    const SESSION_ID_KEY: &'static str = "session_id";
+    /// Set once the password is checked, until the second factor is.
+    const TWO_FACTOR_PENDING_KEY: &'static str = "two_factor_pending_user_id";
+    /// The secret being enrolled, until the user confirms it with a code.
+    const TOTP_ENROLMENT_KEY: &'static str = "totp_enrolment_secret";
+    /// Proves that a form was rendered by us, for this session.
+    const CSRF_TOKEN_KEY: &'static str = "csrf_token";
 
     pub fn renew(&self) {
         self.0.renew();
@@ -18,10 +27,73 @@ impl TypedSession {
         self.0.insert(Self::USER_ID_KEY, user_id)
     }
 
+    /// The `session_epoch` of the user when they logged in.
+    pub fn insert_session_epoch(
+        &self,
+        session_epoch: i32,
+    ) -> Result<(), actix_session::SessionInsertError> {
+        self.0.insert(Self::SESSION_EPOCH_KEY, session_epoch)
+    }
+
+    pub fn get_session_epoch(&self) -> Result<Option<i32>, actix_session::SessionGetError> {
+        self.0.get(Self::SESSION_EPOCH_KEY)
+    }
+
+    pub fn insert_session_id(
+        &self,
+        session_id: Uuid,
+    ) -> Result<(), actix_session::SessionInsertError> {
+        self.0.insert(Self::SESSION_ID_KEY, session_id)
+    }
+
+    pub fn get_session_id(&self) -> Result<Option<Uuid>, actix_session::SessionGetError> {
+        self.0.get(Self::SESSION_ID_KEY)
+    }
+
     pub fn get_user_id(&self) -> Result<Option<Uuid>, actix_session::SessionGetError> {
         self.0.get(Self::USER_ID_KEY)
     }
 
+    /// Mark the session as partially authenticated: `user_id` still has
+    /// to provide their second factor.
+    pub fn insert_two_factor_pending(
+        &self,
+        user_id: Uuid,
+    ) -> Result<(), actix_session::SessionInsertError> {
+        self.0.insert(Self::TWO_FACTOR_PENDING_KEY, user_id)
+    }
+
+    pub fn get_two_factor_pending(&self) -> Result<Option<Uuid>, actix_session::SessionGetError> {
+        self.0.get(Self::TWO_FACTOR_PENDING_KEY)
+    }
+
+    pub fn remove_two_factor_pending(&self) {
+        self.0.remove(Self::TWO_FACTOR_PENDING_KEY);
+    }
+
+    pub fn insert_totp_enrolment(
+        &self,
+        secret_base32: &str,
+    ) -> Result<(), actix_session::SessionInsertError> {
+        self.0.insert(Self::TOTP_ENROLMENT_KEY, secret_base32)
+    }
+
+    pub fn get_totp_enrolment(&self) -> Result<Option<String>, actix_session::SessionGetError> {
+        self.0.get(Self::TOTP_ENROLMENT_KEY)
+    }
+
+    pub fn remove_totp_enrolment(&self) {
+        self.0.remove(Self::TOTP_ENROLMENT_KEY);
+    }
+
+    pub fn insert_csrf_token(&self, token: &str) -> Result<(), actix_session::SessionInsertError> {
+        self.0.insert(Self::CSRF_TOKEN_KEY, token)
+    }
+
+    pub fn get_csrf_token(&self) -> Result<Option<String>, actix_session::SessionGetError> {
+        self.0.get(Self::CSRF_TOKEN_KEY)
+    }
+
     pub fn log_out(self) {
         self.0.purge()
     }
@@ -1,15 +1,30 @@
 //! src//startup.rs
 
-use crate::authentication::reject_anonymous_users;
+use crate::authentication::{
+    LoginThrottle, PasswordPolicy, compute_fallback_password_hash, reject_anonymous_users,
+};
 use crate::configurations::{DatabaseSettings, Settings};
+use crate::csrf::reject_forged_requests;
 use crate::email_client::EmailClient;
+use crate::issue_delivery_worker::{IssueDeliveryWorker, UnsubscribeLinks};
+use crate::issue_scheduling_worker::IssueSchedulingWorker;
 use crate::routes::{
-    admin_dashboard, change_password, change_password_form, confirm, health_check, home, log_out,
-    login, login_form, publish_newsletter, subscribe,
+    accept_invitation, accept_invitation_form, admin_dashboard, api_tokens, change_password,
+    change_password_form, change_user_role, confirm, create_api_token, delete_user,
+    disable_two_factor, disable_user, edit_newsletter_issue_form, email_events, enable_two_factor,
+    enable_user, failed_deliveries, forgot_password, forgot_password_form, health_check, home,
+    invite_user, lockouts, log_out, login, login_form, newsletter_issue_report, newsletter_issues,
+    newsletter_report, preview_newsletter_issue, publish_newsletter, publish_newsletter_issue,
+    requeue_failed_delivery, resend_confirmation, resend_confirmation_form, reset_password,
+    reset_password_form, reset_two_factor, revoke_api_token, revoke_other_sessions, revoke_session,
+    save_newsletter_issue, scheduled_newsletter_issues, sessions, subscribe, two_factor_form,
+    two_factor_settings, unlock_account, unschedule_newsletter_issue, unsubscribe,
+    unsubscribe_form, users, verify_two_factor,
 };
+use crate::subscription_purge_worker::SubscriptionPurgeWorker;
 use actix_session::SessionMiddleware;
 use actix_session::storage::RedisSessionStore;
-use actix_web::cookie::Key;
+use actix_web::cookie::{Key, SameSite};
 use actix_web::dev::Server;
 use actix_web::middleware::from_fn;
 use actix_web::{App, HttpServer, web};
@@ -21,46 +36,71 @@ use sqlx::postgres::PgPoolOptions;
 use std::net::TcpListener;
 use tracing_actix_web::TracingLogger;
 
+/// The largest newsletter issue we accept, whether published through the
+/// JSON API or written in the admin panel.
+const MAX_ISSUE_PAYLOAD_BYTES: usize = 2 * 1024 * 1024;
+
 pub struct Application {
     port: u16,
     server: Server,
+    delivery_workers: Vec<IssueDeliveryWorker>,
+    scheduling_worker: IssueSchedulingWorker,
+    purge_worker: SubscriptionPurgeWorker,
 }
 
 #[derive(Clone, serde::Deserialize)]
 pub struct HmacSecret(pub SecretString);
 
 impl Application {
-    pub async fn build(config: Settings) -> Result<Self, anyhow::Error> {
+    pub async fn build(mut config: Settings) -> Result<Self, anyhow::Error> {
+        // Fail fast rather than on the first login - unknown usernames are
+        // checked against a hash as costly as the ones of real users.
+        config.password_hashing.fallback_password_hash =
+            Some(compute_fallback_password_hash(&config.password_hashing)?);
         // Create a lazy pool with the configured options
         let connection_pool = get_connection_pool(&config.database);
         // Build an `EmailClient` using `configuration`
-        let sender_email = config
-            .email_client
-            .sender()
-            .expect("Invalid sender email address.");
-        let timeout = config.email_client.timeout();
-        let email_client = EmailClient::new(
-            config.email_client.base_url,
-            sender_email,
-            config.email_client.authorization_token,
-            timeout,
+        let email_client = config.email_client.clone().client();
+        // Each delivery worker gets its own `EmailClient` and a handle to the pool
+        let delivery_workers = (0..config.issue_delivery.workers)
+            .map(|_| {
+                IssueDeliveryWorker::new(
+                    connection_pool.clone(),
+                    config.email_client.clone().client(),
+                    config.issue_delivery.retry_policy(),
+                    UnsubscribeLinks::new(
+                        config.application.base_url.clone(),
+                        config.application.hmac_secret.clone(),
+                    ),
+                    config.issue_delivery.idle_backoff(),
+                )
+            })
+            .collect();
+
+        let scheduling_worker = IssueSchedulingWorker::new(
+            connection_pool.clone(),
+            config.issue_scheduling.poll_interval(),
+        );
+
+        let purge_worker = SubscriptionPurgeWorker::new(
+            connection_pool.clone(),
+            config.subscriptions.retention(),
+            config.subscriptions.purge_interval(),
         );
 
         let address = format!("{}:{}", config.application.host, config.application.port);
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
 
-        let server = run(
-            listener,
-            connection_pool,
-            email_client,
-            config.application.base_url,
-            config.application.hmac_secret,
-            config.redis_uri,
-        )
-        .await?;
-
-        Ok(Self { port, server })
+        let server = run(listener, connection_pool, email_client, config).await?;
+
+        Ok(Self {
+            port,
+            server,
+            delivery_workers,
+            scheduling_worker,
+            purge_worker,
+        })
     }
 
     pub fn port(&self) -> u16 {
@@ -69,7 +109,14 @@ impl Application {
 
     // A more expressive name that makes it clear that
     // this function only returns when the application is stopped.
+    // Background workers (delivery, scheduling, purge) are spawned and keep
+    // running for as long as the runtime is alive.
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
+        for worker in self.delivery_workers {
+            tokio::spawn(worker.run_until_stopped());
+        }
+        tokio::spawn(self.scheduling_worker.run_until_stopped());
+        tokio::spawn(self.purge_worker.run_until_stopped());
         self.server.await
     }
 }
@@ -80,19 +127,37 @@ pub async fn run(
     listener: TcpListener,
     connection_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
-    hmac_secret: SecretString,
-    redis_uri: SecretString,
+    config: Settings,
 ) -> Result<Server, std::io::Error> {
     // let server = HttpServer::new(|| App::new().route("/health_check", web::get().to(health_check)))
     //     .bind("127.0.0.1:8083")?
     //     .run();
+    let hmac_secret = config.application.hmac_secret;
+    let redis_uri = config.redis_uri;
     let connection_pool = web::Data::new(connection_pool);
     let email_client = web::Data::new(email_client);
-    let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let base_url = web::Data::new(ApplicationBaseUrl(config.application.base_url));
+    let subscription_settings = web::Data::new(config.subscriptions);
+    let scheduling_settings = web::Data::new(config.issue_scheduling);
+    let webhook_settings = web::Data::new(config.webhooks);
+    let api_settings = web::Data::new(config.api);
+    let user_settings = web::Data::new(config.users);
+    let second_factor_settings = web::Data::new(config.two_factor);
+    let password_hashing_settings = web::Data::new(config.password_hashing);
+    let password_policy =
+        web::Data::new(PasswordPolicy::new(config.password_policy).map_err(std::io::Error::other)?);
+    let login_throttle = web::Data::new(
+        LoginThrottle::new(redis_uri.expose_secret(), config.login_throttling)
+            .await
+            .map_err(std::io::Error::other)?,
+    );
 
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
-    let message_store = CookieMessageStore::builder(secret_key.clone()).build();
+    // Browsers do not attach `SameSite` cookies to cross-site posts: on top
+    // of the CSRF tokens, a forged form arrives without a session.
+    let message_store = CookieMessageStore::builder(secret_key.clone())
+        .same_site(SameSite::Strict)
+        .build();
     let message_framework = FlashMessagesFramework::builder(message_store).build();
     let redis_store = RedisSessionStore::new(redis_uri.expose_secret())
         .await
@@ -101,30 +166,137 @@ pub async fn run(
     let server = HttpServer::new(move || {
         App::new()
             .wrap(message_framework.clone())
-            .wrap(SessionMiddleware::new(
-                redis_store.clone(),
-                secret_key.clone(),
-            ))
+            .wrap(
+                // `Lax` rather than `Strict`, for the links in our emails to
+                // open the admin panel logged in.
+                SessionMiddleware::builder(redis_store.clone(), secret_key.clone())
+                    .cookie_same_site(SameSite::Lax)
+                    .build(),
+            )
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(health_check))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/resend",
+                web::get().to(resend_confirmation_form),
+            )
+            .route("/subscriptions/resend", web::post().to(resend_confirmation))
+            .route(
+                "/subscriptions/unsubscribe",
+                web::get().to(unsubscribe_form),
+            )
+            .route("/subscriptions/unsubscribe", web::post().to(unsubscribe))
             .route("/newsletters", web::post().to(publish_newsletter))
+            .route(
+                "/newsletters/{newsletter_issue_id}/report",
+                web::get().to(newsletter_report),
+            )
+            .route("/webhooks/email-events", web::post().to(email_events))
             .route("/", web::get().to(home))
-            .route("/login", web::get().to(login_form))
-            .route("/login", web::post().to(login))
+            .service(
+                web::scope("/invitations")
+                    .wrap(from_fn(reject_forged_requests))
+                    .route("/accept", web::get().to(accept_invitation_form))
+                    .route("/accept", web::post().to(accept_invitation)),
+            )
+            .service(
+                web::scope("/login")
+                    .wrap(from_fn(reject_forged_requests))
+                    .route("", web::get().to(login_form))
+                    .route("", web::post().to(login))
+                    .route("/forgot", web::get().to(forgot_password_form))
+                    .route("/forgot", web::post().to(forgot_password))
+                    .route("/reset", web::get().to(reset_password_form))
+                    .route("/reset", web::post().to(reset_password))
+                    .route("/two-factor", web::get().to(two_factor_form))
+                    .route("/two-factor", web::post().to(verify_two_factor)),
+            )
             .service(
                 web::scope("/admin")
+                    // Runs after `reject_anonymous_users`: anonymous posts
+                    // are redirected to the login form, as before.
+                    .wrap(from_fn(reject_forged_requests))
                     .wrap(from_fn(reject_anonymous_users))
+                    // Newsletter issues are written in forms: they get as
+                    // much room as through the JSON API. The body is
+                    // buffered by `reject_forged_requests` before the form
+                    // is parsed, so both limits have to be raised.
+                    .app_data(web::FormConfig::default().limit(MAX_ISSUE_PAYLOAD_BYTES))
+                    .app_data(web::PayloadConfig::new(MAX_ISSUE_PAYLOAD_BYTES))
                     .route("/dashboard", web::get().to(admin_dashboard))
+                    .route("/newsletters", web::get().to(newsletter_issues))
+                    .route("/newsletters", web::post().to(save_newsletter_issue))
+                    .route(
+                        "/newsletters/edit",
+                        web::get().to(edit_newsletter_issue_form),
+                    )
+                    .route(
+                        "/newsletters/preview",
+                        web::get().to(preview_newsletter_issue),
+                    )
+                    .route(
+                        "/newsletters/publish",
+                        web::post().to(publish_newsletter_issue),
+                    )
+                    .route(
+                        "/newsletters/scheduled",
+                        web::get().to(scheduled_newsletter_issues),
+                    )
+                    .route(
+                        "/newsletters/unschedule",
+                        web::post().to(unschedule_newsletter_issue),
+                    )
+                    // After the other `/newsletters/...` routes, which it
+                    // would shadow.
+                    .route(
+                        "/newsletters/{newsletter_issue_id}",
+                        web::get().to(newsletter_issue_report),
+                    )
                     .route("/password", web::get().to(change_password_form))
                     .route("/password", web::post().to(change_password))
+                    .route("/deliveries", web::get().to(failed_deliveries))
+                    .route(
+                        "/deliveries/requeue",
+                        web::post().to(requeue_failed_delivery),
+                    )
+                    .route("/two-factor", web::get().to(two_factor_settings))
+                    .route("/two-factor", web::post().to(enable_two_factor))
+                    .route("/two-factor/disable", web::post().to(disable_two_factor))
+                    .route("/tokens", web::get().to(api_tokens))
+                    .route("/tokens", web::post().to(create_api_token))
+                    .route("/tokens/revoke", web::post().to(revoke_api_token))
+                    .route("/users", web::get().to(users))
+                    .route("/users/invite", web::post().to(invite_user))
+                    .route("/users/disable", web::post().to(disable_user))
+                    .route("/users/enable", web::post().to(enable_user))
+                    .route("/users/delete", web::post().to(delete_user))
+                    .route("/users/role", web::post().to(change_user_role))
+                    .route("/users/reset-two-factor", web::post().to(reset_two_factor))
+                    .route("/lockouts", web::get().to(lockouts))
+                    .route("/lockouts/unlock", web::post().to(unlock_account))
+                    .route("/sessions", web::get().to(sessions))
+                    .route("/sessions/revoke", web::post().to(revoke_session))
+                    .route(
+                        "/sessions/revoke-others",
+                        web::post().to(revoke_other_sessions),
+                    )
                     .route("/logout", web::post().to(log_out)),
             )
+            .app_data(web::JsonConfig::default().limit(MAX_ISSUE_PAYLOAD_BYTES))
             .app_data(connection_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
-        //.app_data(web::Data::new(HmacSecret(hmac_secret.clone())))
+            .app_data(subscription_settings.clone())
+            .app_data(scheduling_settings.clone())
+            .app_data(webhook_settings.clone())
+            .app_data(api_settings.clone())
+            .app_data(user_settings.clone())
+            .app_data(second_factor_settings.clone())
+            .app_data(login_throttle.clone())
+            .app_data(password_hashing_settings.clone())
+            .app_data(password_policy.clone())
+            .app_data(web::Data::new(HmacSecret(hmac_secret.clone())))
     })
     .listen(listener)?
     .run();
@@ -0,0 +1,115 @@
+//! src/subscription_purge_worker.rs
+use crate::domain::SubscriptionStatus;
+use sqlx::{Executor, PgPool};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// What a purge removed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct PurgeOutcome {
+    pub n_subscribers: u64,
+    pub n_tokens: u64,
+}
+
+/// A background task that periodically purges the confirmation tokens
+/// and the never-confirmed subscribers we have no reason to keep.
+pub struct SubscriptionPurgeWorker {
+    pool: PgPool,
+    retention: Duration,
+    interval: Duration,
+}
+
+impl SubscriptionPurgeWorker {
+    pub fn new(pool: PgPool, retention: Duration, interval: Duration) -> Self {
+        Self {
+            pool,
+            retention,
+            interval,
+        }
+    }
+
+    pub async fn run_until_stopped(self) -> Result<(), anyhow::Error> {
+        loop {
+            tokio::time::sleep(self.interval).await;
+            // Failures are logged by `purge_stale_subscriptions`,
+            // we will try again at the next round.
+            let _ = purge_stale_subscriptions(&self.pool, self.retention).await;
+        }
+    }
+}
+
+/// Delete, once they are older than `retention`:
+/// - subscribers still pending, with no confirmation token issued since;
+/// - confirmation tokens that were used or have expired.
+///
+/// It is safe to run on several instances at once: subscribers being
+/// purged by another instance are skipped.
+#[tracing::instrument(skip(pool), err)]
+pub async fn purge_stale_subscriptions(
+    pool: &PgPool,
+    retention: Duration,
+) -> Result<PurgeOutcome, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let subscriber_ids: Vec<Uuid> = sqlx::query!(
+        r#"
+        SELECT id
+        FROM subscriptions s
+        WHERE
+            status = $1 AND
+            subscribed_at < now() - make_interval(secs => $2) AND
+            NOT EXISTS (
+                SELECT 1 FROM subscription_tokens t
+                WHERE
+                    t.subscriber_id = s.id AND
+                    t.created_at >= now() - make_interval(secs => $2)
+            )
+        FOR UPDATE SKIP LOCKED
+        "#,
+        SubscriptionStatus::Pending as SubscriptionStatus,
+        retention.as_secs_f64()
+    )
+    .fetch_all(&mut *transaction)
+    .await?
+    .into_iter()
+    .map(|r| r.id)
+    .collect();
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM subscription_tokens WHERE subscriber_id = ANY($1)"#,
+            &subscriber_ids
+        ))
+        .await?;
+    transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM subscription_status_history WHERE subscriber_id = ANY($1)"#,
+            &subscriber_ids
+        ))
+        .await?;
+    let n_subscribers = transaction
+        .execute(sqlx::query!(
+            r#"DELETE FROM subscriptions WHERE id = ANY($1)"#,
+            &subscriber_ids
+        ))
+        .await?
+        .rows_affected();
+    let n_tokens = transaction
+        .execute(sqlx::query!(
+            r#"
+            DELETE FROM subscription_tokens
+            WHERE
+                (consumed_at IS NOT NULL OR expires_at <= now()) AND
+                created_at < now() - make_interval(secs => $1)
+            "#,
+            retention.as_secs_f64()
+        ))
+        .await?
+        .rows_affected();
+    transaction.commit().await?;
+    if n_subscribers > 0 || n_tokens > 0 {
+        tracing::info!(n_subscribers, n_tokens, "Purged stale subscriptions.");
+    }
+    Ok(PurgeOutcome {
+        n_subscribers,
+        n_tokens,
+    })
+}
@@ -0,0 +1,148 @@
+//! src/templates.rs
+//! The HTML pages are askama templates, in `templates/`: they are checked
+//! when the crate is compiled and escape every value they interpolate.
+//!
+//! Pages extend `base.html`. Admin pages extend `admin/layout.html`, which
+//! adds the navigation and the flash messages: their templates need a `nav`
+//! and a `flash_messages` field.
+use crate::authentication::{Permission, Role};
+use crate::utils::e500;
+use actix_web::HttpResponse;
+use actix_web::http::StatusCode;
+use actix_web::http::header::ContentType;
+use actix_web_flash_messages::IncomingFlashMessages;
+use askama::Template;
+
+/// The admin pages, in the order they are listed, with the permission they
+/// require - if any.
+const ADMIN_PAGES: [(Option<Permission>, &str, &str); 8] = [
+    (None, "/admin/password", "Change password"),
+    (None, "/admin/two-factor", "Two-factor authentication"),
+    (None, "/admin/sessions", "Active sessions"),
+    (
+        Some(Permission::PublishNewsletters),
+        "/admin/newsletters",
+        "Newsletters",
+    ),
+    (
+        Some(Permission::ViewReports),
+        "/admin/deliveries",
+        "Failed deliveries",
+    ),
+    (
+        Some(Permission::ManageOwnApiTokens),
+        "/admin/tokens",
+        "API tokens",
+    ),
+    (Some(Permission::ManageUsers), "/admin/users", "Users"),
+    (
+        Some(Permission::ManageUsers),
+        "/admin/lockouts",
+        "Locked accounts",
+    ),
+];
+
+pub fn render(template: &impl Template) -> Result<HttpResponse, actix_web::Error> {
+    render_with_status(StatusCode::OK, template)
+}
+
+pub fn render_with_status(
+    status: StatusCode,
+    template: &impl Template,
+) -> Result<HttpResponse, actix_web::Error> {
+    let body = template.render().map_err(e500)?;
+    Ok(HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .body(body))
+}
+
+/// The flash messages of the request, for `partials/flash_messages.html`.
+#[derive(Default)]
+pub struct FlashMessages(Vec<String>);
+
+impl FlashMessages {
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+}
+
+impl From<IncomingFlashMessages> for FlashMessages {
+    fn from(flash_messages: IncomingFlashMessages) -> Self {
+        Self(
+            flash_messages
+                .iter()
+                .map(|m| m.content().to_owned())
+                .collect(),
+        )
+    }
+}
+
+/// The links of `partials/nav.html` and of the dashboard: only to the
+/// pages the role gives access to.
+pub struct Nav(Role);
+
+impl Nav {
+    pub fn new(role: Role) -> Self {
+        Self(role)
+    }
+
+    /// `(href, label)` pairs.
+    pub fn links(&self) -> Vec<(&'static str, &'static str)> {
+        ADMIN_PAGES
+            .iter()
+            .filter(|(permission, _, _)| permission.is_none_or(|p| self.0.can(p)))
+            .map(|(_, href, label)| (*href, *label))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlashMessages, Nav};
+    use crate::authentication::Role;
+    use askama::Template;
+
+    #[derive(Template)]
+    #[template(path = "partials/flash_messages.html")]
+    struct FlashMessagesPartial {
+        flash_messages: FlashMessages,
+    }
+
+    fn hrefs(role: Role) -> Vec<&'static str> {
+        Nav::new(role)
+            .links()
+            .into_iter()
+            .map(|(href, _)| href)
+            .collect()
+    }
+
+    #[test]
+    fn owners_see_every_page() {
+        assert_eq!(hrefs(Role::Owner).len(), super::ADMIN_PAGES.len());
+    }
+
+    #[test]
+    fn other_roles_only_see_the_pages_they_can_use() {
+        assert!(hrefs(Role::Editor).contains(&"/admin/newsletters"));
+        assert!(!hrefs(Role::Editor).contains(&"/admin/users"));
+        assert!(hrefs(Role::Analyst).contains(&"/admin/deliveries"));
+        assert!(!hrefs(Role::Analyst).contains(&"/admin/newsletters"));
+        assert_eq!(
+            hrefs(Role::ReadOnly),
+            ["/admin/password", "/admin/two-factor", "/admin/sessions"]
+        );
+    }
+
+    #[test]
+    fn flash_messages_are_escaped() {
+        let partial = FlashMessagesPartial {
+            flash_messages: FlashMessages(vec![
+                "<script>alert(1)</script> is not a valid role.".into(),
+            ]),
+        };
+        let html = partial.render().unwrap();
+        assert!(html.contains(
+            "<p><i>&#60;script&#62;alert(1)&#60;/script&#62; is not a valid role.</i></p>"
+        ));
+    }
+}
@@ -8,6 +8,15 @@ where
 {
     actix_web::error::ErrorInternalServerError(e)
 }
+
+// Return a 400 with the user-representation of the validation error as body.
+pub fn e400<T>(e: T) -> actix_web::Error
+where
+    T: std::fmt::Debug + std::fmt::Display + 'static,
+{
+    actix_web::error::ErrorBadRequest(e)
+}
+
 pub fn see_other(location: &str) -> HttpResponse {
     HttpResponse::SeeOther()
         .insert_header((LOCATION, location))
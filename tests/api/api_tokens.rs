@@ -0,0 +1,208 @@
+//! tests/api/api_tokens.rs
+use crate::helpers::{TestApp, assert_is_redirect_to, spawn_app, spawn_app_with};
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+async fn login(app: &TestApp) {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+}
+
+/// Create a token through the admin pages and return it.
+async fn create_token(app: &TestApp, name: &str) -> String {
+    let response = app
+        .post_create_api_token(&[("name", name), ("scope", "newsletters:publish")])
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    token_in(&response.text().await.unwrap())
+}
+
+/// The token shown, once, on the page returned after creating it.
+fn token_in(html_page: &str) -> String {
+    let start = html_page.find(r#"<pre id="token">"#).unwrap() + r#"<pre id="token">"#.len();
+    let end = start + html_page[start..].find("</pre>").unwrap();
+    html_page[start..end].to_owned()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_api_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    // Act
+    let response = app
+        .post_create_api_token(&[("name", "ci"), ("scope", "newsletters:publish")])
+        .await;
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_new_token_can_publish_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    // Act
+    let token = create_token(&app, "ci").await;
+    let response = app
+        .post_newsletters_with_token(&token, newsletter_request_body())
+        .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let saved = sqlx::query!("SELECT token_hash, last_used_at FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(saved.token_hash, token);
+    assert!(saved.last_used_at.is_some());
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<td>ci</td>"));
+    assert!(!html_page.contains(&token));
+}
+
+#[tokio::test]
+async fn a_revoked_token_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = create_token(&app, "ci").await;
+    let token_id = sqlx::query!("SELECT id FROM api_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    // Act - Part 1 - Revoke
+    let response = app
+        .post_revoke_api_token(&serde_json::json!({ "token_id": token_id }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/tokens");
+    let html_page = app.get_api_tokens_html().await;
+    assert!(html_page.contains("<p><i>The token has been revoked.</i></p>"));
+    // Act - Part 2 - Use it
+    let response = app
+        .post_newsletters_with_token(&token, newsletter_request_body())
+        .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(
+        response
+            .headers()
+            .get_all("WWW-Authenticate")
+            .iter()
+            .any(|h| h == r#"Bearer realm="publish""#)
+    );
+}
+
+#[tokio::test]
+async fn an_expired_token_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let response = app
+        .post_create_api_token(&[
+            ("name", "ci"),
+            ("scope", "newsletters:publish"),
+            ("expires_in_days", "7"),
+        ])
+        .await;
+    let token = token_in(&response.text().await.unwrap());
+    sqlx::query!("UPDATE api_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    // Act
+    let response = app
+        .post_newsletters_with_token(&token, newsletter_request_body())
+        .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_token_without_the_publish_scope_is_forbidden() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let token = create_token(&app, "ci").await;
+    sqlx::query!("UPDATE api_tokens SET scopes = '{}'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    // Act
+    let response = app
+        .post_newsletters_with_token(&token, newsletter_request_body())
+        .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn invalid_token_forms_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let test_cases = vec![
+        (
+            vec![("name", ""), ("scope", "newsletters:publish")],
+            "The name must be between 1 and 100 characters long.",
+        ),
+        (vec![("name", "ci")], "A token needs at least one scope."),
+        (
+            vec![("name", "ci"), ("scope", "newsletters:delete")],
+            "`newsletters:delete` is not a known scope.",
+        ),
+        (
+            vec![
+                ("name", "ci"),
+                ("scope", "newsletters:publish"),
+                ("expires_in_days", "0"),
+            ],
+            "The expiry must be a positive number of days.",
+        ),
+    ];
+
+    for (body, error_message) in test_cases {
+        // Act
+        let response = app.post_create_api_token(&body).await;
+        // Assert
+        assert_is_redirect_to(&response, "/admin/tokens");
+        let html_page = app.get_api_tokens_html().await;
+        assert!(
+            html_page.contains(&format!("<p><i>{}</i></p>", error_message)),
+            "The form did not fail with `{}`.",
+            error_message
+        );
+    }
+    let n_tokens = sqlx::query!(r#"SELECT count(*) AS "count!" FROM api_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 0);
+}
+
+#[tokio::test]
+async fn basic_auth_is_rejected_when_disabled() {
+    // Arrange
+    let app = spawn_app_with(|c| c.api.allow_basic_auth = false).await;
+    // Act
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let challenges: Vec<_> = response
+        .headers()
+        .get_all("WWW-Authenticate")
+        .iter()
+        .collect();
+    assert_eq!(challenges, vec![r#"Bearer realm="publish""#]);
+}
@@ -65,7 +65,6 @@ async fn current_password_must_be_valid() {
     // Arrange
     let app = spawn_app().await;
     let new_password = Uuid::new_v4().to_string();
-    let another_new_password = Uuid::new_v4().to_string();
     let wrong_password = Uuid::new_v4().to_string();
 
     // Act - Part 1 - Login
@@ -113,3 +112,70 @@ async fn changing_password_works() {
         .await;
     assert_is_redirect_to(&response, "/admin/password");
 }
+
+#[tokio::test]
+async fn new_passwords_must_follow_the_password_policy() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+    let test_cases = [
+        (
+            "short".to_string(),
+            "The password must be at least 12 characters long.",
+        ),
+        (
+            "a1b2c3d4".repeat(17),
+            "The password must be at most 128 characters long.",
+        ),
+        (
+            app.test_user.password.clone(),
+            "The new password must be different from the current one.",
+        ),
+        (
+            format!("{}-and-more", app.test_user.username),
+            "The password must not contain your username.",
+        ),
+        (
+            "aaaaaaaaaaaaaaaaaaaa".to_string(),
+            "The password is too easy to guess: make it longer, or mix in other kinds of characters.",
+        ),
+        (
+            "correct horse battery staple".to_string(),
+            "This password appeared in a data breach: choose another one.",
+        ),
+    ];
+
+    for (new_password, message) in test_cases {
+        // Act
+        let response = app
+            .post_change_password(&serde_json::json!({
+                "current_password": &app.test_user.password,
+                "new_password": &new_password,
+                "new_password_check": &new_password,
+            }))
+            .await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/admin/password");
+        let html_page = app.get_change_password_html().await;
+        assert!(
+            html_page.contains(&format!("<p><i>{}</i></p>", message)),
+            "The flash message for {} was not `{}`.",
+            new_password,
+            message
+        );
+    }
+    // The password did not change
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
@@ -2,8 +2,16 @@ use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version, password_hash::
 use once_cell::sync::Lazy;
 use secrecy::ExposeSecret;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
-use wiremock::MockServer;
-use z2p::configurations::{DatabaseSettings, get_configuration};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use z2p::configurations::{
+    DatabaseSettings, EmailTransportKind, Settings, WebhookSettings, get_configuration,
+};
+use z2p::domain::{SubscriberEmail, UnsubscribeToken};
+use z2p::email_client::EmailClient;
+use z2p::issue_delivery_worker::{
+    ExecutionOutcome, RetryPolicy, UnsubscribeLinks, try_execute_task,
+};
 use z2p::startup::{Application, get_connection_pool};
 use z2p::telemetry::{get_subscriber, init_subscriber};
 
@@ -29,6 +37,32 @@ pub struct TestApp {
     pub application_port: u16,
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
+    pub email_client: EmailClient,
+    pub retry_policy: RetryPolicy,
+    pub unsubscribe_links: UnsubscribeLinks,
+    pub webhooks: WebhookSettings,
+}
+
+/// Answers `/email/batch` the way Postmark does when it accepts
+/// every message in the batch.
+pub struct BatchAccepted;
+
+impl wiremock::Respond for BatchAccepted {
+    fn respond(&self, request: &wiremock::Request) -> wiremock::ResponseTemplate {
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = messages
+            .iter()
+            .map(|message| {
+                serde_json::json!({
+                    "ErrorCode": 0,
+                    "Message": "OK",
+                    "MessageID": uuid::Uuid::new_v4(),
+                    "To": message["To"]
+                })
+            })
+            .collect();
+        wiremock::ResponseTemplate::new(200).set_body_json(results)
+    }
 }
 
 pub struct ConfirmationLinks {
@@ -37,15 +71,31 @@ pub struct ConfirmationLinks {
 }
 
 pub struct TestUser {
-    user_id: uuid::Uuid,
+    pub user_id: uuid::Uuid,
     pub username: String,
     pub password: String,
 }
 
 impl TestApp {
+    pub async fn dispatch_all_pending_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &self.retry_policy,
+                &self.unsubscribe_links,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/subscriptions", &self.address))
+            .post(format!("{}/subscriptions", &self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -53,24 +103,49 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
-    // pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
-    //     let (username, password) = self.test_user().await;
-    //     self.api_client
-    //         .post(&format!("{}/newsletters", &self.address))
-    //         .basic_auth(username, Some(password))
-    //         .json(&body)
-    //         .send()
-    //         .await
-    //         .expect("Failed to execute request.")
-    // }
+    pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_newsletters_with_token(
+        &self,
+        token: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/newsletters", &self.address))
+            .bearer_auth(token)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_newsletter_report(&self, newsletter_issue_id: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!(
+                "{}/newsletters/{}/report",
+                &self.address, newsletter_issue_id
+            ))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
 
     pub async fn post_login<Body>(&self, body: &Body) -> reqwest::Response
     where
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/login", &self.address))
-            .form(body)
+            .post(format!("{}/login", &self.address))
+            .form(&self.with_csrf_token(body).await)
             .send()
             .await
             .expect("Failed to execute request.")
@@ -94,16 +169,31 @@ impl TestApp {
             confirmation_link
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
         ConfirmationLinks { html, plain_text }
     }
 
     // Our tests will only look at the HTML page, therefore
     // we do not expose the underlying reqwest::Response
+    /// The CSRF token of the session, as embedded in the forms we render.
+    pub async fn csrf_token(&self) -> String {
+        extract_csrf_token(&self.get_login_html().await)
+    }
+
+    /// `body`, with the CSRF token of the session that forms carry.
+    pub async fn with_csrf_token<Body>(&self, body: &Body) -> serde_json::Value
+    where
+        Body: serde::Serialize,
+    {
+        let mut body = serde_json::to_value(body).unwrap();
+        body["csrf_token"] = self.csrf_token().await.into();
+        body
+    }
+
     pub async fn get_login_html(&self) -> String {
         self.api_client
-            .get(&format!("{}/login", &self.address))
+            .get(format!("{}/login", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -114,7 +204,7 @@ impl TestApp {
 
     pub async fn get_admin_dashboard(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/dashboard", &self.address))
+            .get(format!("{}/admin/dashboard", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -124,9 +214,80 @@ impl TestApp {
         self.get_admin_dashboard().await.text().await.unwrap()
     }
 
+    pub async fn get_newsletter_issues_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/newsletters", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// `path` is the page and its query string, e.g. `edit?newsletter_issue_id=...`.
+    pub async fn get_newsletter_issue_html(&self, path: &str) -> String {
+        self.api_client
+            .get(format!("{}/admin/newsletters/{}", &self.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_newsletter_issue<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/newsletters", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_publish_newsletter_issue(
+        &self,
+        newsletter_issue_id: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/newsletters/publish", &self.address))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({
+                        "newsletter_issue_id": newsletter_issue_id
+                    }))
+                    .await,
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_unschedule_newsletter_issue(
+        &self,
+        newsletter_issue_id: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/newsletters/unschedule", &self.address))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({
+                        "newsletter_issue_id": newsletter_issue_id
+                    }))
+                    .await,
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn get_change_password(&self) -> reqwest::Response {
         self.api_client
-            .get(&format!("{}/admin/password", &self.address))
+            .get(format!("{}/admin/password", &self.address))
             .send()
             .await
             .expect("Failed to execute request.")
@@ -141,23 +302,285 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.api_client
-            .post(&format!("{}/admin/password", &self.address))
+            .post(format!("{}/admin/password", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_failed_deliveries_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/deliveries", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_requeue_failed_delivery<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/deliveries/requeue", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_api_tokens_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/tokens", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_create_api_token<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/tokens", &self.address))
+            // The scopes repeat, which a JSON object cannot hold.
+            .header("X-CSRF-Token", self.csrf_token().await)
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_revoke_api_token<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/tokens/revoke", &self.address))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_users_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/users", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// `action` is one of `invite`, `disable`, `enable` or `delete`.
+    pub async fn post_admin_users<Body>(&self, action: &str, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/admin/users/{}", &self.address, action))
+            .form(&self.with_csrf_token(body).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_lockouts_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/lockouts", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_unlock_account(&self, username: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/lockouts/unlock", &self.address))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({ "username": username }))
+                    .await,
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_sessions_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/sessions", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_revoke_session(&self, session_id: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/sessions/revoke", &self.address))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({ "session_id": session_id }))
+                    .await,
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_revoke_other_sessions(&self) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/admin/sessions/revoke-others", &self.address))
+            .form(&self.with_csrf_token(&serde_json::json!({})).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Log `user` in from a browser of its own, identified by `user_agent`.
+    pub async fn login_from_another_browser(
+        &self,
+        user: &TestUser,
+        user_agent: &str,
+    ) -> reqwest::Client {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .cookie_store(true)
+            .user_agent(user_agent)
+            .build()
+            .unwrap();
+        let login_page = client
+            .get(format!("{}/login", &self.address))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        let response = client
+            .post(format!("{}/login", &self.address))
+            .form(&serde_json::json!({
+                "username": &user.username,
+                "password": &user.password,
+                "csrf_token": extract_csrf_token(&login_page),
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_is_redirect_to(&response, "/admin/dashboard");
+        client
+    }
+
+    pub async fn post_resend_confirmation<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(format!("{}/subscriptions/resend", &self.address))
             .form(body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    pub async fn get_unsubscribe(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(format!("{}/subscriptions/unsubscribe", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// What a mail client does on a RFC 8058 one-click unsubscribe.
+    pub async fn post_unsubscribe(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/subscriptions/unsubscribe", &self.address))
+            .query(&[("token", token)])
+            .form(&[("List-Unsubscribe", "One-Click")])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// What Postmark does when it has news about one of our emails.
+    pub async fn post_email_event(&self, payload: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}/webhooks/email-events", &self.address))
+            .basic_auth(
+                &self.webhooks.username,
+                Some(self.webhooks.password.expose_secret()),
+            )
+            .header("Content-Type", "application/json")
+            .body(payload.to_owned())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
-            .post(&format!("{}/admin/logout", &self.address))
+            .post(format!("{}/admin/logout", &self.address))
+            .form(&self.with_csrf_token(&serde_json::json!({})).await)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_two_factor_html(&self) -> String {
+        self.api_client
+            .get(format!("{}/admin/two-factor", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// `path` is either `/admin/two-factor`, `/admin/two-factor/disable` or
+    /// `/login/two-factor`.
+    pub async fn post_two_factor(&self, path: &str, code: &str) -> reqwest::Response {
+        self.api_client
+            .post(format!("{}{}", &self.address, path))
+            .form(
+                &self
+                    .with_csrf_token(&serde_json::json!({ "code": code }))
+                    .await,
+            )
             .send()
             .await
             .expect("Failed to execute request.")
     }
+
+    /// Store another active user, next to `test_user`.
+    pub async fn add_user_with_role(&self, role: &str) -> TestUser {
+        let user = TestUser::generate();
+        user.store(&self.db_pool, role).await;
+        user
+    }
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application with `customise` applied on top of the test
+/// configuration.
+pub async fn spawn_app_with(customise: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
     let email_server = MockServer::start().await;
     // Randomise configuration to ensure test isolation
@@ -168,7 +591,15 @@ pub async fn spawn_app() -> TestApp {
         // Use a random OS port
         c.application.port = 0;
         // Use the mock server as email API
+        c.email_client.transport = EmailTransportKind::Postmark;
         c.email_client.base_url = email_server.uri();
+        // Tests drive delivery explicitly via `dispatch_all_pending_emails`
+        c.issue_delivery.workers = 0;
+        // ... and scheduled publishing via `publish_due_issues`
+        c.issue_scheduling.poll_interval_seconds = 3600;
+        // Redis is shared by all test cases
+        c.login_throttling.key_prefix = format!("{}:", uuid::Uuid::new_v4());
+        customise(&mut c);
         c
     };
 
@@ -181,7 +612,7 @@ pub async fn spawn_app() -> TestApp {
     // Get the port before spawning the application
     let application_port = application.port();
     let address = format!("http://127.0.0.1:{}", application_port);
-    let _ = tokio::spawn(application.run_until_stopped());
+    tokio::spawn(application.run_until_stopped());
 
     let client = reqwest::Client::builder()
         .redirect(reqwest::redirect::Policy::none())
@@ -196,33 +627,19 @@ pub async fn spawn_app() -> TestApp {
         application_port,
         test_user: TestUser::generate(),
         api_client: client,
+        email_client: configuration.email_client.client(),
+        retry_policy: configuration.issue_delivery.retry_policy(),
+        unsubscribe_links: UnsubscribeLinks::new(
+            configuration.application.base_url.clone(),
+            configuration.application.hmac_secret.clone(),
+        ),
+        webhooks: configuration.webhooks.clone(),
     };
 
-    test_app.test_user.store(&test_app.db_pool).await;
+    test_app.test_user.store(&test_app.db_pool, "owner").await;
     test_app
 }
 
-async fn configure_db(config: &DatabaseSettings) -> PgPool {
-    let mut connection =
-        PgConnection::connect(&config.connection_string_without_db().expose_secret())
-            .await
-            .expect("Failed to connect to Postgres");
-    connection
-        .execute(format!(r#"CREATE DATABASE "{}";"#, config.database_name).as_str())
-        .await
-        .expect("Failed to create database.");
-
-    let connection_pool = PgPool::connect(&config.connection_string().expose_secret())
-        .await
-        .expect("Failed to connect to Postgres.");
-    sqlx::migrate!("./migrations")
-        .run(&connection_pool)
-        .await
-        .expect("Failed to migrate the database");
-
-    connection_pool
-}
-
 pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
     // Create database
     let mut connection = PgConnection::connect_with(&config.without_db())
@@ -267,15 +684,7 @@ impl TestUser {
         }
     }
 
-    pub async fn login(&self, app: &TestApp) {
-        app.post_login(&serde_json::json!({
-            "username": &self.username,
-            "password": &self.password
-        }))
-        .await;
-    }
-
-    async fn store(&self, pool: &PgPool) {
+    async fn store(&self, pool: &PgPool, role: &str) {
         let salt = SaltString::generate(&mut rand::thread_rng());
         // Match production parameters
         let password_hash = Argon2::new(
@@ -288,20 +697,88 @@ impl TestUser {
         .to_string();
 
         sqlx::query!(
-            "INSERT INTO users (user_id, username, password_hash)
-            VALUES ($1, $2, $3)",
+            "INSERT INTO users (user_id, username, password_hash, status, role, created_at)
+            VALUES ($1, $2, $3, 'active', $4::text::user_role, now())",
             self.user_id,
             self.username,
             password_hash,
+            role,
         )
         .execute(pool)
         .await
         .expect("Failed to store test user.");
-        // dbg!(password_hash);
     }
 }
 
+/// The token found in the unsubscribe link of the issues sent to `email`.
+pub fn unsubscribe_token(email: &str) -> String {
+    let email = SubscriberEmail::parse(email.into()).unwrap();
+    let configuration = get_configuration().unwrap();
+    UnsubscribeToken::new(&email, &configuration.application.hmac_secret)
+        .as_ref()
+        .to_owned()
+}
+
+/// The value of the hidden `csrf_token` field of a form in `html_page`.
+pub fn extract_csrf_token(html_page: &str) -> String {
+    let start = html_page
+        .find(r#"name="csrf_token" value=""#)
+        .expect("No CSRF token in the page.")
+        + r#"name="csrf_token" value=""#.len();
+    let end = start + html_page[start..].find('"').unwrap();
+    html_page[start..end].to_owned()
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
 }
+
+pub async fn create_unconfirmed_suscriber(app: &TestApp) -> ConfirmationLinks {
+    create_unconfirmed_subscriber_with_email(app, "ursula_le_guin@gmail.com").await
+}
+
+pub async fn create_unconfirmed_subscriber_with_email(
+    app: &TestApp,
+    email: &str,
+) -> ConfirmationLinks {
+    let body = format!("name=le%20guin&email={}", email.replace('@', "%40"));
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(email_request)
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    create_confirmed_subscriber_with_email(app, "ursula_le_guin@gmail.com").await
+}
+
+pub async fn create_confirmed_subscriber_with_email(app: &TestApp, email: &str) {
+    // We can then reuse the same helper and just add
+    // an extra step to actually call the confirmation link!
+    let confirmation_link = create_unconfirmed_subscriber_with_email(app, email).await;
+
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
@@ -0,0 +1,192 @@
+//! tests/api/lockouts.rs
+use crate::helpers::{TestApp, TestUser, assert_is_redirect_to, spawn_app_with};
+use std::time::{Duration, Instant};
+use z2p::configurations::Settings;
+
+const LOCKED_OUT: &str = "<p><i>Too many failed login attempts: try again in 15 minute(s).</i></p>";
+
+/// Three failures lock a username out, without slowing the tests down.
+fn lock_quickly(c: &mut Settings) {
+    c.login_throttling.max_failures_per_username = 3;
+    c.login_throttling.base_delay_milliseconds = 0;
+    c.login_throttling.max_delay_milliseconds = 0;
+}
+
+async fn post_login(app: &TestApp, username: &str, password: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": username,
+        "password": password
+    }))
+    .await
+}
+
+async fn fail_to_login(app: &TestApp, username: &str, times: usize) {
+    for _ in 0..times {
+        let response = post_login(app, username, "not-the-password").await;
+        assert_is_redirect_to(&response, "/login");
+    }
+}
+
+async fn login_as(app: &TestApp, user: &TestUser) -> reqwest::Response {
+    post_login(app, &user.username, &user.password).await
+}
+
+#[tokio::test]
+async fn a_username_is_locked_out_after_too_many_failures() {
+    // Arrange
+    let app = spawn_app_with(lock_quickly).await;
+
+    // Act - Part 1 - Guess the password
+    fail_to_login(&app, &app.test_user.username, 2).await;
+    assert!(app.get_login_html().await.contains("Authentication failed"));
+    fail_to_login(&app, &app.test_user.username, 1).await;
+    assert!(app.get_login_html().await.contains(LOCKED_OUT));
+
+    // Act - Part 2 - Even the right password is refused now
+    let response = login_as(&app, &app.test_user).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains(LOCKED_OUT));
+}
+
+#[tokio::test]
+async fn an_ip_address_is_locked_out_after_too_many_failures() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        lock_quickly(c);
+        c.login_throttling.max_failures_per_username = 100;
+        c.login_throttling.max_failures_per_ip = 3;
+    })
+    .await;
+
+    // Act - A different username every time
+    for username in ["alice", "bob", "carol"] {
+        fail_to_login(&app, username, 1).await;
+    }
+    let response = login_as(&app, &app.test_user).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert!(app.get_login_html().await.contains(LOCKED_OUT));
+}
+
+#[tokio::test]
+async fn logging_in_resets_the_failures_of_a_username() {
+    // Arrange
+    let app = spawn_app_with(lock_quickly).await;
+    fail_to_login(&app, &app.test_user.username, 2).await;
+    let response = login_as(&app, &app.test_user).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    // Act
+    fail_to_login(&app, &app.test_user.username, 2).await;
+    let response = login_as(&app, &app.test_user).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn failed_logins_are_answered_more_and_more_slowly() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttling.base_delay_milliseconds = 200;
+        c.login_throttling.max_delay_milliseconds = 1000;
+    })
+    .await;
+    fail_to_login(&app, "random-username", 1).await;
+
+    // Act
+    let start = Instant::now();
+    fail_to_login(&app, "random-username", 1).await;
+
+    // Assert - the second failure waits twice the base delay
+    assert!(start.elapsed() >= Duration::from_millis(400));
+}
+
+#[tokio::test]
+async fn owners_can_see_and_unlock_locked_accounts() {
+    // Arrange
+    let app = spawn_app_with(lock_quickly).await;
+    let editor = app.add_user_with_role("editor").await;
+    fail_to_login(&app, &editor.username, 3).await;
+    let response = login_as(&app, &app.test_user).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 1 - List the locked accounts
+    let html_page = app.get_lockouts_html().await;
+    assert!(html_page.contains(&format!("<td>{}</td>", editor.username)));
+    assert!(!html_page.contains(&format!("<td>{}</td>", app.test_user.username)));
+
+    // Act - Part 2 - Unlock the editor
+    let response = app.post_unlock_account(&editor.username).await;
+    assert_is_redirect_to(&response, "/admin/lockouts");
+    let html_page = app.get_lockouts_html().await;
+    assert!(html_page.contains(&format!(
+        "<p><i>{} can log in again.</i></p>",
+        editor.username
+    )));
+    assert!(html_page.contains("No account is locked."));
+    app.post_logout().await;
+
+    // Act - Part 3 - The editor can log in again
+    let response = login_as(&app, &editor).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn only_owners_can_manage_locked_accounts() {
+    // Arrange
+    let app = spawn_app_with(lock_quickly).await;
+    let editor = app.add_user_with_role("editor").await;
+    let response = login_as(&app, &editor).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act
+    let get_response = app
+        .api_client
+        .get(format!("{}/admin/lockouts", &app.address))
+        .send()
+        .await
+        .unwrap();
+    let post_response = app.post_unlock_account(&app.test_user.username).await;
+
+    // Assert
+    assert_eq!(get_response.status().as_u16(), 403);
+    assert_eq!(post_response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn basic_auth_on_the_api_is_locked_out_after_too_many_failures() {
+    // Arrange
+    let app = spawn_app_with(lock_quickly).await;
+    let publish_with = |password: &str| {
+        app.api_client
+            .post(format!("{}/newsletters", &app.address))
+            .basic_auth(&app.test_user.username, Some(password))
+            .json(&serde_json::json!({
+                "title": "Newsletter title",
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                }
+            }))
+            .send()
+    };
+
+    // Act - Part 1 - Guess the password
+    for _ in 0..3 {
+        let response = publish_with("not-the-password").await.unwrap();
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    // Act - Part 2 - Even the right password is refused now
+    let response = publish_with(&app.test_user.password).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let response = login_as(&app, &app.test_user).await;
+    assert_is_redirect_to(&response, "/login");
+}
@@ -1,7 +1,5 @@
 //! tests/api/login.rs
-use crate::helpers::{assert_is_redirect_to, spawn_app};
-use reqwest::header::HeaderValue;
-use std::collections::HashSet;
+use crate::helpers::{TestApp, assert_is_redirect_to, spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn an_error_flash_message_is_set_on_failure() {
@@ -31,6 +29,24 @@ async fn an_error_flash_message_is_set_on_failure() {
     assert!(!html_page.contains("<p><i>Authentication failed</i></p>"));
 }
 
+#[tokio::test]
+async fn a_wrong_password_for_a_known_user_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    // Act
+    let login_body = serde_json::json!({
+        "username": &app.test_user.username,
+        "password": "wrong-password"
+    });
+
+    let response = app.post_login(&login_body).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains(r#"<p><i>Authentication failed</i></p>"#));
+}
+
 // #[tokio::test]
 // async fn redirect_to_admin_dashboard_after_login_success() {
 //     // Arrange
@@ -45,4 +61,71 @@ async fn an_error_flash_message_is_set_on_failure() {
 //     // Act - Part 2 - Follow the redirect
 //     let html_page = app.get_admin_dashboard().await;
 //     assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
-// }
\ No newline at end of file
+// }
+
+async fn stored_password_hash(app: &TestApp) -> String {
+    sqlx::query!(
+        r#"SELECT password_hash AS "password_hash!" FROM users WHERE user_id = $1"#,
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .password_hash
+}
+
+#[tokio::test]
+async fn an_outdated_password_hash_is_upgraded_on_login() {
+    // Arrange - the test user was stored with `m=15000,t=2,p=1`
+    let app = spawn_app_with(|c| {
+        c.password_hashing.memory_kib = 19456;
+        c.password_hashing.iterations = 3;
+    })
+    .await;
+    assert!(
+        stored_password_hash(&app)
+            .await
+            .contains("$m=15000,t=2,p=1$")
+    );
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Assert
+    let password_hash = stored_password_hash(&app).await;
+    assert!(password_hash.starts_with("$argon2id$v=19$m=19456,t=3,p=1$"));
+    // The password itself did not change
+    app.post_logout().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn a_failed_login_does_not_upgrade_the_password_hash() {
+    // Arrange
+    let app = spawn_app_with(|c| c.password_hashing.memory_kib = 19456).await;
+    let password_hash = stored_password_hash(&app).await;
+
+    // Act
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": "not-the-password"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Assert
+    assert_eq!(stored_password_hash(&app).await, password_hash);
+}
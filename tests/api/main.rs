@@ -1,8 +1,22 @@
+mod admin_dashboard;
+mod api_tokens;
+mod change_password;
+mod csrf;
 mod health_check;
 mod helpers;
+mod lockouts;
+mod login;
+mod merge_tags;
+mod newsletter;
+mod newsletter_drafts;
+mod newsletter_reports;
+mod newsletter_scheduling;
+mod password_reset;
+mod roles;
+mod sessions;
 mod subscription;
 mod subscription_confirms;
-mod newsletter;
-mod login;
-mod admin_dashboard;
-mod change_password;
\ No newline at end of file
+mod two_factor;
+mod unsubscribe;
+mod users;
+mod webhooks;
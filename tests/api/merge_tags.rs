@@ -0,0 +1,180 @@
+//! tests/api/merge_tags.rs
+use crate::helpers::{
+    BatchAccepted, TestApp, assert_is_redirect_to, create_confirmed_subscriber, spawn_app,
+    unsubscribe_token,
+};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn newsletter_request_body(text: &str, html: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": text,
+            "html": html,
+        }
+    })
+}
+
+/// The `(HtmlBody, TextBody)` of the only email sent.
+async fn sent_bodies(app: &TestApp) -> (String, String) {
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    let body = |field: &str| messages[0][field].as_str().unwrap().to_owned();
+    (body("HtmlBody"), body("TextBody"))
+}
+
+#[tokio::test]
+async fn merge_tags_are_filled_in_for_each_recipient() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchAccepted)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_newsletters(newsletter_request_body(
+            "Hi {{ name }}, this was sent to {{ email }}. Leave: {{ unsubscribe_url }}",
+            r#"<p>Hi {{ name }}, this was sent to {{email}}.</p><a href="{{ unsubscribe_url }}">Leave</a>"#,
+        ))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let (html_body, text_body) = sent_bodies(&app).await;
+    let link = format!(
+        "/subscriptions/unsubscribe?token={}",
+        unsubscribe_token("ursula_le_guin@gmail.com")
+    );
+    assert!(
+        text_body.starts_with("Hi le guin, this was sent to ursula_le_guin@gmail.com. Leave: http")
+    );
+    // The bodies link to the unsubscribe page themselves: no footer.
+    assert!(text_body.ends_with(&link));
+    assert!(html_body.starts_with("<p>Hi le guin, this was sent to ursula_le_guin@gmail.com.</p>"));
+    assert!(html_body.ends_with(&format!(r#"{link}">Leave</a>"#)));
+}
+
+#[tokio::test]
+async fn values_are_only_escaped_in_html_bodies() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET name = 'Ursula & Le Guin'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchAccepted)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(newsletter_request_body(
+        "Hi {{ name }}!",
+        "<p>Hi {{ name }}!</p>",
+    ))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let (html_body, text_body) = sent_bodies(&app).await;
+    assert!(html_body.starts_with("<p>Hi Ursula &#38; Le Guin!</p>"));
+    assert!(text_body.starts_with("Hi Ursula & Le Guin!"));
+}
+
+#[tokio::test]
+async fn the_api_rejects_unknown_merge_tags() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let test_cases = vec![
+        (
+            newsletter_request_body("Hi {{ first_name }}", "<p>Hi</p>"),
+            "an unknown tag in the text",
+        ),
+        (
+            newsletter_request_body("Hi", "<p>Hi {{ name </p>"),
+            "an unclosed tag in the HTML",
+        ),
+        (
+            newsletter_request_body("Hi {{ name | reader }}", "<p>Hi</p>"),
+            "an invalid fallback",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = app.post_newsletters(body).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request when the payload had {}.",
+            description
+        );
+    }
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn drafts_with_unknown_merge_tags_are_saved_but_not_published() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act
+    let response = app
+        .post_newsletter_issue(&serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>Hi {{ first_name }}</p>",
+            "text_content": "Hi {{ name }}",
+            "action": "publish",
+        }))
+        .await;
+
+    // Assert
+    let location = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    assert!(location.starts_with("/admin/newsletters/edit?newsletter_issue_id="));
+    let html_page = app
+        .get_newsletter_issue_html(&location["/admin/newsletters/".len()..])
+        .await;
+    assert!(html_page.contains("HTML content: `{{ first_name }}` is not a merge tag"));
+    let n_queued = sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(n_queued, 0);
+}
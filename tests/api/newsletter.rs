@@ -1,208 +1,501 @@
-use crate::helpers::{ConfirmationLinks, TestApp, spawn_app};
-use crate::newsletter;
+use crate::helpers::{
+    BatchAccepted, assert_is_redirect_to, create_confirmed_subscriber,
+    create_confirmed_subscriber_with_email, create_unconfirmed_suscriber, spawn_app,
+};
+use uuid::Uuid;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
 
-// #[tokio::test]
-// async fn newsletter_are_not_delivered_to_unconfirmed_suscribers() {
-//     let app = spawn_app().await;
-//     create_unconfirmed_suscriber(&app).await;
-
-//     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-//     Mock::given(any())
-//         .respond_with(ResponseTemplate::new(200))
-//         .expect(0)
-//         .mount(&app.email_server)
-//         .await;
-
-//     // Act
-//     let newsletter_request_body = serde_json::json!({"title": "Newsletter title",
-//         "content": {
-//         "text": "Newsletter body as plain text",
-//         "html": "<p>Newsletter body as HTML</p>",
-//     }});
-
-//     let response = app.post_newsletters(newsletter_request_body).await;
-
-//     assert_eq!(response.status().as_u16(), 200);
-// }
-
-// #[tokio::test]
-// async fn newsletters_are_delivered_to_confirmed_subscribers() {
-//     let app = spawn_app().await;
-//     create_confirmed_subscriber(&app).await;
-
-//     Mock::given(path("/email"))
-//         .and(method("POST"))
-//         .respond_with(ResponseTemplate::new(200))
-//         .expect(1)
-//         .mount(&app.email_server)
-//         .await;
-
-//     let newsletter_request_body = serde_json::json!({"title": "Newsletter title",
-//         "content": {
-//         "text": "Newsletter body as plain text",
-//         "html": "<p>Newsletter body as HTML</p>",
-//     }});
-
-//     let response = app.post_newsletters(newsletter_request_body).await;
-//     // Assert
-//     assert_eq!(response.status().as_u16(), 200);
-// }
-
-async fn create_unconfirmed_suscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-
-    let _mock_guard = Mock::given(path("/email"))
-        .and(method("POST"))
+#[tokio::test]
+async fn newsletter_are_not_delivered_to_unconfirmed_suscribers() {
+    let app = spawn_app().await;
+    create_unconfirmed_suscriber(&app).await;
+
+    Mock::given(any())
         .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({"title": "Newsletter title",
+        "content": {
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+    }});
+
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_to_confirmed_subscribers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchAccepted)
         .expect(1)
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({"title": "Newsletter title",
+        "content": {
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+    }});
+
+    let response = app.post_newsletters(newsletter_request_body).await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn publishing_returns_before_any_email_is_sent() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchAccepted)
+        .expect(0)
+        .mount(&app.email_server)
         .await;
 
-    app.post_subscriptions(body.into())
+    let newsletter_request_body = serde_json::json!({"title": "Newsletter title",
+        "content": {
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+    }});
+
+    let response = app.post_newsletters(newsletter_request_body).await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 202);
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
         .await
-        .error_for_status()
         .unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].subscriber_email, "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn a_transient_failure_is_retried_later() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let queued = sqlx::query!(
+        "SELECT n_retries, execute_after > now() AS \"is_delayed!\" FROM issue_delivery_queue"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.n_retries, 1);
+    assert!(queued.is_delayed);
+}
 
-    let email_request = &app
-        .email_server
-        .received_requests()
+#[tokio::test]
+async fn a_permanent_failure_is_dead_lettered() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
         .await
-        .unwrap()
-        .pop()
         .unwrap();
-    app.get_confirmation_links(&email_request)
+    assert!(queued.is_empty());
+    let dead_letter =
+        sqlx::query!("SELECT subscriber_email, n_retries FROM issue_delivery_dead_letters")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(dead_letter.subscriber_email, "ursula_le_guin@gmail.com");
+    assert_eq!(dead_letter.n_retries, 0);
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    // We can then reuse the same helper and just add
-    // an extra step to actually call the confirmation link!
-    let confirmation_link = create_unconfirmed_suscriber(app).await;
+#[tokio::test]
+async fn a_delivery_is_dead_lettered_once_it_runs_out_of_retries() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
 
-    reqwest::get(confirmation_link.html)
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    // Pretend we already went through all the retries
+    sqlx::query!(
+        "UPDATE issue_delivery_queue SET n_retries = $1",
+        app.retry_policy.max_retries
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let dead_letter = sqlx::query!("SELECT n_retries FROM issue_delivery_dead_letters")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(dead_letter.n_retries, app.retry_policy.max_retries);
+}
+
+#[tokio::test]
+async fn failed_deliveries_can_be_requeued_by_an_admin() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+    drop(_mock_guard);
+
+    // Act - Part 1 - Login and inspect the failed deliveries
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+    let html_page = app.get_failed_deliveries_html().await;
+    assert!(html_page.contains("ursula_le_guin@gmail.com"));
+    let newsletter_issue_id = sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
         .await
         .unwrap()
-        .error_for_status()
+        .newsletter_issue_id;
+
+    // Act - Part 2 - Requeue
+    let response = app
+        .post_requeue_failed_delivery(&serde_json::json!({
+            "newsletter_issue_id": newsletter_issue_id,
+            "subscriber_email": "ursula_le_guin@gmail.com",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/deliveries");
+    let html_page = app.get_failed_deliveries_html().await;
+    assert!(
+        html_page
+            .contains("<p><i>The delivery to ursula_le_guin@gmail.com has been requeued.</i></p>")
+    );
+
+    // Act - Part 3 - Deliver
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchAccepted)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_recipient_rejected_within_a_batch_is_dead_lettered_alone() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "first@example.com").await;
+    create_confirmed_subscriber_with_email(&app, "second@example.com").await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(|request: &wiremock::Request| {
+            let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            let results: Vec<_> = messages
+                .iter()
+                .map(|message| {
+                    if message["To"] == "second@example.com" {
+                        serde_json::json!({
+                            "ErrorCode": 406,
+                            "Message": "You tried to send to a recipient that has been marked as inactive."
+                        })
+                    } else {
+                        serde_json::json!({ "ErrorCode": 0, "Message": "OK", "To": message["To"] })
+                    }
+                })
+                .collect();
+            ResponseTemplate::new(200).set_body_json(results)
+        })
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
         .unwrap();
+    assert!(queued.is_empty());
+    let dead_letters = sqlx::query!("SELECT subscriber_email FROM issue_delivery_dead_letters")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].subscriber_email, "second@example.com");
+}
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_invalid_data() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({
+            "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+            }
+            }),
+            "missing title",
+        ),
+        (
+            serde_json::json!({"title": "Newsletter!"}),
+            "missing content",
+        ),
+    ];
+
+    for (invalid_body, error_message) in test_cases {
+        let response = app.post_newsletters(invalid_body).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .json(&serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+        }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn non_existing_user_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Random credentials
+    let username = Uuid::new_v4().to_string();
+    let password = Uuid::new_v4().to_string();
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
 }
 
-// #[tokio::test]
-// async fn newsletters_returns_400_for_invalid_data() {
-//     // Arrange
-//     let app = spawn_app().await;
-//     let test_cases = vec![
-//         (
-//             serde_json::json!({
-//             "content": {
-//             "text": "Newsletter body as plain text",
-//             "html": "<p>Newsletter body as HTML</p>",
-//             }
-//             }),
-//             "missing title",
-//         ),
-//         (
-//             serde_json::json!({"title": "Newsletter!"}),
-//             "missing content",
-//         ),
-//     ];
-
-//     for (invalid_body, error_message) in test_cases {
-//         let response = app.post_newsletters(invalid_body).await;
-
-//         assert_eq!(
-//             400,
-//             response.status().as_u16(),
-//             "The API did not fail with 400 Bad Request when the payload was {}.",
-//             error_message
-//         );
-//     }
-// }
-
-// #[tokio::test]
-// async fn requests_missing_authorization_are_rejected() {
-//     // Arrange
-//     let app = spawn_app().await;
-
-//     let response = reqwest::Client::new()
-//         .post(&format!("{}/newsletters", &app.address))
-//         .json(&serde_json::json!({
-//         "title": "Newsletter title",
-//         "content": {
-//             "text": "Newsletter body as plain text",
-//         "html": "<p>Newsletter body as HTML</p>",
-//         }
-//         }))
-//         .send()
-//         .await
-//         .expect("Failed to execute request.");
-
-//     // Assert
-//     assert_eq!(401, response.status().as_u16());
-//     assert_eq!(
-//         r#"Basic realm="publish""#,
-//         response.headers()["WWW-Authenticate"]
-//     );
-// }
-
-// #[tokio::test]
-// async fn non_existing_user_is_rejected() {
-//     // Arrange
-//     let app = spawn_app().await;
-
-//     // Random credentials
-//     let username = Uuid::new_v4().to_string();
-//     let password = Uuid::new_v4().to_string();
-
-//     let response = reqwest::Client::new()
-//         .post(&format!("{}/newsletters", &app.address))
-//         .basic_auth(username, Some(password))
-//         .json(&serde_json::json!({
-//             "title": "Newsletter title",
-//             "content": {
-//                 "text": "Newsletter body as plain text",
-//                 "html": "<p>Newsletter body as HTML</p>",
-//             }
-//         }))
-//         .send()
-//         .await
-//         .expect("Failed to execute request.");
-
-//     // Assert
-//     assert_eq!(401, response.status().as_u16());
-//     assert_eq!(
-//         r#"Basic realm="publish""#,
-//         response.headers()["WWW-Authenticate"]
-//     );
-// }
-
-// #[tokio::test]
-// async fn invalid_password_is_rejected() {
-//     // Arrange
-//     let app = spawn_app().await;
-//     let username = &app.test_user.username;
-//     // Random password
-//     let password = Uuid::new_v4().to_string();
-
-//     assert_ne!(app.test_user.password, password);
-//     let response = reqwest::Client::new()
-//         .post(&format!("{}/newsletters", &app.address))
-//         .basic_auth(username, Some(password))
-//         .json(&serde_json::json!({
-//         "title": "Newsletter title",
-//         "content": {
-//         "text": "Newsletter body as plain text",
-//         "html": "<p>Newsletter body as HTML</p>",
-//         }
-//         }))
-//         .send()
-//         .await
-//         .expect("Failed to execute request.");
-//     // Assert
-//     assert_eq!(401, response.status().as_u16());
-//     assert_eq!(
-//         r#"Basic realm="publish""#,
-//         response.headers()["WWW-Authenticate"]
-//     );
-// }
+#[tokio::test]
+async fn invalid_password_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let username = &app.test_user.username;
+    // Random password
+    let password = Uuid::new_v4().to_string();
+
+    assert_ne!(app.test_user.password, password);
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(username, Some(password))
+        .json(&serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+        "text": "Newsletter body as plain text",
+        "html": "<p>Newsletter body as HTML</p>",
+        }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="publish""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
+#[tokio::test]
+async fn newsletter_creation_is_idempotent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchAccepted)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Submit newsletter
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "idempotency_key": Uuid::new_v4().to_string()
+    });
+    let response = app.post_newsletters(newsletter_request_body.clone()).await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    // Act - Part 2 - Submit newsletter **again**
+    let response = app.post_newsletters(newsletter_request_body).await;
+    assert_eq!(response.status().as_u16(), 202);
+
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn concurrent_submissions_are_handled_gracefully() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchAccepted)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Submit two newsletter forms concurrently
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "idempotency_key": Uuid::new_v4().to_string()
+    });
+    let response1 = app.post_newsletters(newsletter_request_body.clone());
+    let response2 = app.post_newsletters(newsletter_request_body);
+    let (response1, response2) = tokio::join!(response1, response2);
+
+    assert_eq!(response1.status(), response2.status());
+    assert_eq!(
+        response1.text().await.unwrap(),
+        response2.text().await.unwrap()
+    );
+
+    app.dispatch_all_pending_emails().await;
+    // Mock verifies on Drop that we have sent the newsletter email **once**
+}
+
+#[tokio::test]
+async fn an_invalid_idempotency_key_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let test_cases = vec![("".to_string(), "empty"), ("a".repeat(50), "too long")];
+
+    for (idempotency_key, description) in test_cases {
+        // Act
+        let response = app
+            .post_newsletters(serde_json::json!({
+                "title": "Newsletter title",
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                },
+                "idempotency_key": idempotency_key
+            }))
+            .await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the idempotency key was {}.",
+            description
+        );
+    }
+}
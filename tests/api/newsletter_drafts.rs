@@ -0,0 +1,301 @@
+//! tests/api/newsletter_drafts.rs
+use crate::helpers::{
+    BatchAccepted, TestApp, assert_is_redirect_to, create_confirmed_subscriber, spawn_app,
+};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn login(app: &TestApp) {
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+fn issue_form(action: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "<p>Newsletter body as HTML</p>",
+        "text_content": "Newsletter body as plain text",
+        "action": action,
+    })
+}
+
+/// The id of the issue a draft form redirected to.
+fn issue_id_in(response: &reqwest::Response) -> String {
+    let location = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    location
+        .split_once("newsletter_issue_id=")
+        .expect("No issue id in the redirect.")
+        .1
+        .to_owned()
+}
+
+async fn n_queued_deliveries(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_write_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_newsletter_issue(&issue_form("save")).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn a_draft_can_be_saved_and_edited_without_being_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+
+    // Act - Part 1 - Save a draft
+    let response = app.post_newsletter_issue(&issue_form("save")).await;
+    let issue_id = issue_id_in(&response);
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/edit?newsletter_issue_id={issue_id}"),
+    );
+    let html_page = app
+        .get_newsletter_issue_html(&format!("edit?newsletter_issue_id={issue_id}"))
+        .await;
+    assert!(html_page.contains("<p><i>Your draft has been saved.</i></p>"));
+    assert!(html_page.contains(r#"value="Newsletter title""#));
+    assert!(html_page.contains("&#60;p&#62;Newsletter body as HTML&#60;/p&#62;</textarea>"));
+
+    // Act - Part 2 - Edit it
+    let mut form = issue_form("save");
+    form["newsletter_issue_id"] = issue_id.clone().into();
+    form["title"] = "A better title".into();
+    let response = app.post_newsletter_issue(&form).await;
+    assert_eq!(issue_id_in(&response), issue_id);
+
+    // Assert
+    let html_page = app.get_newsletter_issues_html().await;
+    assert!(html_page.contains("<td>A better title</td>"));
+    assert!(html_page.contains(&format!("<td>{}</td>", app.test_user.username)));
+    assert!(html_page.contains("<td>draft</td>"));
+    assert!(!html_page.contains("Newsletter title"));
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+}
+
+#[tokio::test]
+async fn issues_larger_than_the_default_form_limit_can_be_saved() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    // Well over the 16 KiB actix-web allows forms by default
+    let html_content = "<p>A long newsletter paragraph.</p>".repeat(2_000);
+    let mut form = issue_form("save");
+    form["html_content"] = html_content.clone().into();
+
+    // Act
+    let response = app.post_newsletter_issue(&form).await;
+
+    // Assert
+    let issue_id = issue_id_in(&response);
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/edit?newsletter_issue_id={issue_id}"),
+    );
+    let stored = sqlx::query_scalar!(
+        "SELECT html_content FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        uuid::Uuid::parse_str(&issue_id).unwrap()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(stored, html_content);
+}
+
+#[tokio::test]
+async fn a_draft_can_be_previewed() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let response = app.post_newsletter_issue(&issue_form("preview")).await;
+
+    // Assert
+    let issue_id = issue_id_in(&response);
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/preview?newsletter_issue_id={issue_id}"),
+    );
+    let html_page = app
+        .get_newsletter_issue_html(&format!("preview?newsletter_issue_id={issue_id}"))
+        .await;
+    assert!(
+        html_page
+            .contains(r#"<iframe sandbox srcdoc="&#60;p&#62;Newsletter body as HTML&#60;/p&#62;""#)
+    );
+    assert!(html_page.contains("<pre>Newsletter body as plain text</pre>"));
+    assert!(html_page.contains(r#"action="/admin/newsletters/publish""#));
+}
+
+#[tokio::test]
+async fn publishing_from_the_form_delivers_the_issue_to_confirmed_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchAccepted)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_newsletter_issue(&issue_form("publish")).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let html_page = app.get_newsletter_issues_html().await;
+    assert!(html_page.contains("<p><i>The newsletter issue has been published!</i></p>"));
+    assert!(html_page.contains("<td>published at "));
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_draft_is_published_only_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+    let response = app.post_newsletter_issue(&issue_form("save")).await;
+    let issue_id = issue_id_in(&response);
+
+    // Act - Submit the publish form twice
+    let response = app.post_publish_newsletter_issue(&issue_id).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let response = app.post_publish_newsletter_issue(&issue_id).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+
+    // Assert
+    let html_page = app.get_newsletter_issues_html().await;
+    assert!(html_page.contains("<p><i>This issue has already been published.</i></p>"));
+    assert_eq!(n_queued_deliveries(&app).await, 1);
+}
+
+#[tokio::test]
+async fn published_issues_cannot_be_edited() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let response = app.post_newsletter_issue(&issue_form("save")).await;
+    let issue_id = issue_id_in(&response);
+    app.post_publish_newsletter_issue(&issue_id).await;
+
+    // Act
+    let mut form = issue_form("save");
+    form["newsletter_issue_id"] = issue_id.into();
+    form["title"] = "A better title".into();
+    let response = app.post_newsletter_issue(&form).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    let html_page = app.get_newsletter_issues_html().await;
+    assert!(
+        html_page
+            .contains("<p><i>This issue has already been published: it cannot be edited.</i></p>")
+    );
+    assert!(!html_page.contains("A better title"));
+}
+
+#[tokio::test]
+async fn incomplete_drafts_cannot_be_published() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+    let mut form = issue_form("publish");
+    form["text_content"] = "".into();
+
+    // Act
+    let response = app.post_newsletter_issue(&form).await;
+
+    // Assert
+    let issue_id = issue_id_in(&response);
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/edit?newsletter_issue_id={issue_id}"),
+    );
+    let html_page = app
+        .get_newsletter_issue_html(&format!("edit?newsletter_issue_id={issue_id}"))
+        .await;
+    assert!(html_page.contains(
+        "<p><i>A newsletter issue needs a title, an HTML and a text content to be published.</i></p>"
+    ));
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+}
+
+#[tokio::test]
+async fn issues_published_through_the_api_are_listed() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Sent from a script",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 202);
+    login(&app).await;
+
+    // Act
+    let html_page = app.get_newsletter_issues_html().await;
+
+    // Assert
+    assert!(html_page.contains("<td>Sent from a script</td>"));
+    assert!(html_page.contains("<td>published at "));
+}
+
+#[tokio::test]
+async fn analysts_cannot_write_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    let analyst = app.add_user_with_role("analyst").await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &analyst.username,
+            "password": &analyst.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act
+    let response = app.post_newsletter_issue(&issue_form("save")).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(
+        !app.get_admin_dashboard_html()
+            .await
+            .contains("/admin/newsletters")
+    );
+}
@@ -0,0 +1,398 @@
+//! tests/api/newsletter_reports.rs
+use crate::helpers::{
+    BatchAccepted, TestApp, TestUser, assert_is_redirect_to, create_confirmed_subscriber,
+    create_confirmed_subscriber_with_email, spawn_app, unsubscribe_token,
+};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const HARD_BOUNCE: &str = include_str!("../fixtures/postmark/hard_bounce.json");
+/// The `MessageID` of the `HARD_BOUNCE` fixture.
+const BOUNCED_MESSAGE_ID: &str = "883953f4-6105-42a2-a16a-77a8eac79483";
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+async fn login_as(app: &TestApp, user: &TestUser) {
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": user.username,
+            "password": user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+/// Publish an issue through the API, returning its id.
+async fn publish_issue(app: &TestApp) -> String {
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    sqlx::query_scalar!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .to_string()
+}
+
+/// Answers `/email/batch` accepting every message under `message_id`.
+fn accepted_as(message_id: &'static str) -> impl wiremock::Respond {
+    move |request: &wiremock::Request| {
+        let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = messages
+            .iter()
+            .map(|message| {
+                serde_json::json!({
+                    "ErrorCode": 0,
+                    "Message": "OK",
+                    "MessageID": message_id,
+                    "To": message["To"]
+                })
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
+}
+
+async fn report(app: &TestApp, issue_id: &str) -> serde_json::Value {
+    let response = app.get_newsletter_report(issue_id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    response.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn the_report_counts_the_deliveries_by_outcome() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "first@example.com").await;
+    create_confirmed_subscriber_with_email(&app, "second@example.com").await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(|request: &wiremock::Request| {
+            let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            let results: Vec<_> = messages
+                .iter()
+                .map(|message| {
+                    if message["To"] == "second@example.com" {
+                        serde_json::json!({
+                            "ErrorCode": 406,
+                            "Message": "You tried to send to a recipient that has been marked as inactive."
+                        })
+                    } else {
+                        serde_json::json!({ "ErrorCode": 0, "Message": "OK", "To": message["To"] })
+                    }
+                })
+                .collect();
+            ResponseTemplate::new(200).set_body_json(results)
+        })
+        .mount(&app.email_server)
+        .await;
+    let issue_id = publish_issue(&app).await;
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let report = report(&app, &issue_id).await;
+    assert_eq!(report["title"], "Newsletter title");
+    assert_eq!(report["published_by"], app.test_user.username.as_str());
+    assert_eq!(report["n_queued"], 0);
+    assert_eq!(report["n_sent"], 1);
+    assert_eq!(report["n_failed"], 1);
+    assert_eq!(report["n_bounced"], 0);
+    assert!(report["finished_at"].is_string());
+    let failures = report["failures"].as_array().unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0]["subscriber_email"], "second@example.com");
+    assert_eq!(failures[0]["status"], "failed");
+    let error = failures[0]["error"].as_str().unwrap();
+    assert!(error.starts_with("Failed to send an email, the request was rejected."));
+    assert!(error.contains("Caused by:"));
+}
+
+#[tokio::test]
+async fn the_report_page_shows_the_outcome_of_the_delivery() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(422))
+        .mount(&app.email_server)
+        .await;
+    let issue_id = publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+    login_as(&app, &app.test_user).await;
+
+    // Act
+    let html_page = app.get_newsletter_issue_html(&issue_id).await;
+
+    // Assert
+    assert!(html_page.contains("<h1>Newsletter title</h1>"));
+    assert!(html_page.contains(&format!("<p>Published by {}.</p>", app.test_user.username)));
+    assert!(html_page.contains(", finished at "));
+    assert!(html_page.contains("<td>ursula_le_guin@gmail.com</td>"));
+    assert!(html_page.contains("<pre>Failed to send an email, the request was rejected."));
+}
+
+#[tokio::test]
+async fn the_delivery_is_in_progress_while_emails_are_queued() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // Act
+    let issue_id = publish_issue(&app).await;
+
+    // Assert
+    let report = report(&app, &issue_id).await;
+    assert_eq!(report["n_queued"], 1);
+    assert!(report["finished_at"].is_null());
+    login_as(&app, &app.test_user).await;
+    let html_page = app.get_newsletter_issue_html(&issue_id).await;
+    assert!(html_page.contains("still in progress"));
+}
+
+#[tokio::test]
+async fn the_delivery_finishes_when_a_subscriber_unsubscribes_before_it_is_sent() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let issue_id = publish_issue(&app).await;
+
+    // Act
+    let response = app
+        .post_unsubscribe(&unsubscribe_token("ursula_le_guin@gmail.com"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report = report(&app, &issue_id).await;
+    assert_eq!(report["n_queued"], 0);
+    assert_eq!(report["n_failed"], 1);
+    assert!(!report["finished_at"].is_null());
+    assert_eq!(
+        report["failures"][0]["error"],
+        "The subscriber unsubscribed before it was sent."
+    );
+}
+
+#[tokio::test]
+async fn a_hard_bounce_is_reported_on_the_issue_it_bounced_from() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    Mock::given(path("/email/batch"))
+        .respond_with(accepted_as(BOUNCED_MESSAGE_ID))
+        .mount(&app.email_server)
+        .await;
+    let issue_id = publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Act
+    let response = app.post_email_event(HARD_BOUNCE).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let report = report(&app, &issue_id).await;
+    assert_eq!(report["n_sent"], 0);
+    assert_eq!(report["n_bounced"], 1);
+    let failure = &report["failures"][0];
+    assert_eq!(failure["status"], "bounced");
+    assert!(
+        failure["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("HardBounce: ")
+    );
+}
+
+#[tokio::test]
+async fn a_late_bounce_is_not_reported_on_a_newer_issue() {
+    // Arrange - the bounced message belongs to the first issue
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let mock_guard = Mock::given(path("/email/batch"))
+        .respond_with(accepted_as(BOUNCED_MESSAGE_ID))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let first_issue_id = publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+    drop(mock_guard);
+    Mock::given(path("/email/batch"))
+        .respond_with(BatchAccepted)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_all_pending_emails().await;
+    let second_issue_id = sqlx::query_scalar!(
+        "SELECT newsletter_issue_id FROM newsletter_issues WHERE newsletter_issue_id != $1",
+        uuid::Uuid::parse_str(&first_issue_id).unwrap()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .to_string();
+
+    // Act
+    let response = app.post_email_event(HARD_BOUNCE).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let first_report = report(&app, &first_issue_id).await;
+    assert_eq!(first_report["n_bounced"], 1);
+    let second_report = report(&app, &second_issue_id).await;
+    assert_eq!(second_report["n_sent"], 1);
+    assert_eq!(second_report["n_bounced"], 0);
+}
+
+#[tokio::test]
+async fn a_requeued_delivery_is_queued_again() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(422))
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let issue_id = publish_issue(&app).await;
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(report(&app, &issue_id).await["n_failed"], 1);
+    login_as(&app, &app.test_user).await;
+
+    // Act
+    app.post_requeue_failed_delivery(&serde_json::json!({
+        "newsletter_issue_id": &issue_id,
+        "subscriber_email": "ursula_le_guin@gmail.com"
+    }))
+    .await;
+
+    // Assert
+    let report = report(&app, &issue_id).await;
+    assert_eq!(report["n_queued"], 1);
+    assert_eq!(report["n_failed"], 0);
+    assert!(report["failures"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn the_report_names_who_published_a_draft() {
+    // Arrange
+    let app = spawn_app().await;
+    login_as(&app, &app.test_user).await;
+    let response = app
+        .post_newsletter_issue(&serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "text_content": "Newsletter body as plain text",
+            "action": "save",
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 303);
+    let issue_id: uuid::Uuid =
+        sqlx::query_scalar!("SELECT newsletter_issue_id FROM newsletter_issues")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    let editor = app.add_user_with_role("editor").await;
+    app.post_logout().await;
+    login_as(&app, &editor).await;
+
+    // Act
+    app.post_publish_newsletter_issue(&issue_id.to_string())
+        .await;
+
+    // Assert
+    let report = report(&app, &issue_id.to_string()).await;
+    assert_eq!(report["published_by"], editor.username.as_str());
+}
+
+#[tokio::test]
+async fn drafts_and_unknown_issues_have_no_report() {
+    // Arrange
+    let app = spawn_app().await;
+    login_as(&app, &app.test_user).await;
+    app.post_newsletter_issue(&serde_json::json!({
+        "title": "Newsletter title",
+        "html_content": "",
+        "text_content": "",
+        "action": "save",
+    }))
+    .await;
+    let draft_id: uuid::Uuid =
+        sqlx::query_scalar!("SELECT newsletter_issue_id FROM newsletter_issues")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+
+    for issue_id in [draft_id, uuid::Uuid::new_v4()] {
+        // Act
+        let response = app.get_newsletter_report(&issue_id.to_string()).await;
+        // Assert
+        assert_eq!(response.status().as_u16(), 404);
+    }
+}
+
+#[tokio::test]
+async fn the_json_report_requires_the_reports_scope() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = publish_issue(&app).await;
+    login_as(&app, &app.test_user).await;
+    let html_page = app
+        .post_create_api_token(&[("name", "ci"), ("scope", "newsletters:publish")])
+        .await
+        .text()
+        .await
+        .unwrap();
+    let start = html_page.find(r#"<pre id="token">"#).unwrap() + r#"<pre id="token">"#.len();
+    let end = start + html_page[start..].find("</pre>").unwrap();
+    let token = &html_page[start..end];
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/newsletters/{}/report", &app.address, issue_id))
+        .bearer_auth(token)
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn analysts_can_read_reports_but_read_only_users_cannot() {
+    // Arrange
+    let app = spawn_app().await;
+    let issue_id = publish_issue(&app).await;
+    let analyst = app.add_user_with_role("analyst").await;
+    let read_only = app.add_user_with_role("read_only").await;
+
+    // Act - Part 1 - Analyst
+    login_as(&app, &analyst).await;
+    let response = app
+        .api_client
+        .get(format!("{}/admin/newsletters/{}", &app.address, issue_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 2 - Read-only user
+    app.post_logout().await;
+    login_as(&app, &read_only).await;
+    let response = app
+        .api_client
+        .get(format!("{}/admin/newsletters/{}", &app.address, issue_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 403);
+}
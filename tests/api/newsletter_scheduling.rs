@@ -0,0 +1,314 @@
+//! tests/api/newsletter_scheduling.rs
+use crate::helpers::{
+    BatchAccepted, TestApp, assert_is_redirect_to, create_confirmed_subscriber, spawn_app,
+    spawn_app_with,
+};
+use chrono::{Duration, Utc};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use z2p::issue_scheduling_worker::publish_due_issues;
+
+async fn login(app: &TestApp) {
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+/// The value of a `datetime-local` input, `days` from now.
+fn in_days(days: i64) -> String {
+    (Utc::now() + Duration::days(days))
+        .format("%Y-%m-%dT%H:%M")
+        .to_string()
+}
+
+/// Schedule a new issue, returning its id.
+async fn schedule_issue(app: &TestApp, scheduled_for: &str) -> String {
+    let response = app
+        .post_newsletter_issue(&serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "text_content": "Newsletter body as plain text",
+            "scheduled_for": scheduled_for,
+            "action": "schedule",
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/newsletters/scheduled");
+    sqlx::query_scalar!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .to_string()
+}
+
+/// Travel in time: make the scheduled issues due.
+async fn make_scheduled_issues_due(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE newsletter_issues SET scheduled_for = now() - interval '1 minute'
+        WHERE scheduled_for IS NOT NULL"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn n_queued_deliveries(app: &TestApp) -> i64 {
+    sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM issue_delivery_queue"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn a_scheduled_issue_is_listed_as_upcoming_and_not_delivered_yet() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+    let scheduled_for = in_days(3);
+
+    // Act
+    schedule_issue(&app, &scheduled_for).await;
+
+    // Assert
+    let html_page = app.get_newsletter_issue_html("scheduled").await;
+    let expected_time = scheduled_for.replace('T', " ");
+    assert!(html_page.contains(&format!(
+        "<p><i>The issue will be published at {expected_time} UTC.</i></p>"
+    )));
+    assert!(html_page.contains(&format!("<td>{expected_time} UTC</td>")));
+    assert!(html_page.contains("<td>Newsletter title</td>"));
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 0);
+    assert_eq!(n_queued_deliveries(&app).await, 0);
+}
+
+#[tokio::test]
+async fn due_issues_are_published_by_the_scheduler() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+    schedule_issue(&app, &in_days(1)).await;
+    make_scheduled_issues_due(&app).await;
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchAccepted)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let n_published = publish_due_issues(&app.db_pool).await.unwrap();
+
+    // Assert
+    assert_eq!(n_published, 1);
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 0);
+    let html_page = app.get_newsletter_issues_html().await;
+    assert!(html_page.contains("<td>published at "));
+    assert!(
+        app.get_newsletter_issue_html("scheduled")
+            .await
+            .contains("No issue is scheduled.")
+    );
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn concurrent_schedulers_publish_a_due_issue_once() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+    schedule_issue(&app, &in_days(1)).await;
+    make_scheduled_issues_due(&app).await;
+
+    // Act - Two instances of the application poll at the same time
+    let (first, second) = tokio::join!(
+        publish_due_issues(&app.db_pool),
+        publish_due_issues(&app.db_pool)
+    );
+
+    // Assert
+    assert_eq!(first.unwrap() + second.unwrap(), 1);
+    assert_eq!(n_queued_deliveries(&app).await, 1);
+}
+
+#[tokio::test]
+async fn a_scheduled_issue_can_be_rescheduled() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let issue_id = schedule_issue(&app, &in_days(1)).await;
+    let later = in_days(7);
+
+    // Act
+    let response = app
+        .post_newsletter_issue(&serde_json::json!({
+            "newsletter_issue_id": &issue_id,
+            "title": "Newsletter title",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "text_content": "Newsletter body as plain text",
+            "scheduled_for": &later,
+            "action": "schedule",
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters/scheduled");
+    let html_page = app.get_newsletter_issue_html("scheduled").await;
+    assert!(html_page.contains(&format!("<td>{} UTC</td>", later.replace('T', " "))));
+    let html_page = app
+        .get_newsletter_issue_html(&format!("edit?newsletter_issue_id={issue_id}"))
+        .await;
+    assert!(html_page.contains(&format!(r#"name="scheduled_for" value="{later}""#)));
+}
+
+#[tokio::test]
+async fn publishing_times_are_read_and_shown_in_the_configured_timezone() {
+    // Arrange
+    let app = spawn_app_with(|c| c.issue_scheduling.timezone = chrono_tz::Europe::Paris).await;
+    login(&app).await;
+
+    // Act
+    let issue_id = schedule_issue(&app, "2099-01-15T10:00").await;
+
+    // Assert
+    let scheduled_for: chrono::DateTime<Utc> =
+        sqlx::query_scalar("SELECT scheduled_for FROM newsletter_issues")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(scheduled_for.to_rfc3339(), "2099-01-15T09:00:00+00:00");
+    let html_page = app.get_newsletter_issue_html("scheduled").await;
+    assert!(html_page.contains("<td>2099-01-15 10:00 CET</td>"));
+    let html_page = app
+        .get_newsletter_issue_html(&format!("edit?newsletter_issue_id={issue_id}"))
+        .await;
+    assert!(html_page.contains("Publish at (Europe/Paris)"));
+    assert!(html_page.contains(r#"name="scheduled_for" value="2099-01-15T10:00""#));
+}
+
+#[tokio::test]
+async fn a_cancelled_issue_is_not_published() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    login(&app).await;
+    let issue_id = schedule_issue(&app, &in_days(1)).await;
+
+    // Act
+    let response = app.post_unschedule_newsletter_issue(&issue_id).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters/scheduled");
+    let html_page = app.get_newsletter_issue_html("scheduled").await;
+    assert!(
+        html_page
+            .contains("<p><i>The issue is not scheduled anymore: it is a draft again.</i></p>")
+    );
+    assert!(html_page.contains("No issue is scheduled."));
+    make_scheduled_issues_due(&app).await;
+    assert_eq!(publish_due_issues(&app.db_pool).await.unwrap(), 0);
+    assert!(
+        app.get_newsletter_issues_html()
+            .await
+            .contains("<td>draft</td>")
+    );
+}
+
+#[tokio::test]
+async fn published_issues_cannot_be_cancelled() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    login(&app).await;
+    let issue_id = schedule_issue(&app, &in_days(1)).await;
+    make_scheduled_issues_due(&app).await;
+    publish_due_issues(&app.db_pool).await.unwrap();
+
+    // Act
+    app.post_unschedule_newsletter_issue(&issue_id).await;
+
+    // Assert
+    let html_page = app.get_newsletter_issue_html("scheduled").await;
+    assert!(html_page.contains(
+        "<p><i>This issue is not scheduled: it may have been published already.</i></p>"
+    ));
+}
+
+#[tokio::test]
+async fn issues_cannot_be_scheduled_in_the_past() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+
+    // Act
+    let response = app
+        .post_newsletter_issue(&serde_json::json!({
+            "title": "Newsletter title",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "text_content": "Newsletter body as plain text",
+            "scheduled_for": in_days(-1),
+            "action": "schedule",
+        }))
+        .await;
+
+    // Assert - The draft is saved, but not scheduled
+    let location = response
+        .headers()
+        .get("Location")
+        .unwrap()
+        .to_str()
+        .unwrap();
+    assert!(location.starts_with("/admin/newsletters/edit?newsletter_issue_id="));
+    let html_page = app
+        .get_newsletter_issue_html(&location["/admin/newsletters/".len()..])
+        .await;
+    assert!(html_page.contains("<p><i>Pick a date and time in the future.</i></p>"));
+    assert!(
+        app.get_newsletter_issue_html("scheduled")
+            .await
+            .contains("No issue is scheduled.")
+    );
+}
+
+#[tokio::test]
+async fn emptying_a_scheduled_issue_cancels_its_schedule() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let issue_id = schedule_issue(&app, &in_days(1)).await;
+
+    // Act
+    let response = app
+        .post_newsletter_issue(&serde_json::json!({
+            "newsletter_issue_id": &issue_id,
+            "title": "Newsletter title",
+            "html_content": "<p>Newsletter body as HTML</p>",
+            "text_content": "",
+            "action": "save",
+        }))
+        .await;
+
+    // Assert
+    let edit_page = format!("/admin/newsletters/edit?newsletter_issue_id={issue_id}");
+    assert_is_redirect_to(&response, &edit_page);
+    let html_page = app
+        .get_newsletter_issue_html(&format!("edit?newsletter_issue_id={issue_id}"))
+        .await;
+    assert!(html_page.contains(
+        "<p><i>A newsletter issue needs a title, an HTML and a text content to be published. \
+        The issue is not scheduled anymore.</i></p>"
+    ));
+    assert!(
+        app.get_newsletter_issue_html("scheduled")
+            .await
+            .contains("No issue is scheduled.")
+    );
+}
@@ -0,0 +1,298 @@
+//! tests/api/password_reset.rs
+use crate::helpers::{TestApp, assert_is_redirect_to, extract_csrf_token, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const EMAIL: &str = "test-user@example.com";
+
+async fn give_test_user_an_email(app: &TestApp) {
+    sqlx::query!(
+        "UPDATE users SET email = $1 WHERE user_id = $2",
+        EMAIL,
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn post_forgot(app: &TestApp, username: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/login/forgot", &app.address))
+        .form(
+            &app.with_csrf_token(&serde_json::json!({ "username": username }))
+                .await,
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn get_forgot_html(app: &TestApp) -> String {
+    app.api_client
+        .get(format!("{}/login/forgot", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+async fn post_reset(app: &TestApp, link: &reqwest::Url, new_password: &str) -> reqwest::Response {
+    app.api_client
+        .post(link.clone())
+        .form(
+            &app.with_csrf_token(&serde_json::json!({
+                "new_password": new_password,
+                "new_password_check": new_password,
+            }))
+            .await,
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+/// Ask for a reset link for the test user and return it.
+async fn request_reset_link(app: &TestApp) -> reqwest::Url {
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    post_forgot(app, &app.test_user.username).await;
+    // The email is sent in the background
+    mock_guard.wait_until_satisfied().await;
+    let email_request = &mock_guard.received_requests().await[0];
+    app.get_confirmation_links(email_request).html
+}
+
+const SAME_FOR_EVERYONE: &str = "<p><i>If this user exists, a link to reset their password has been sent to their email address.</i></p>";
+
+#[tokio::test]
+async fn a_reset_link_is_emailed_to_existing_users() {
+    // Arrange
+    let app = spawn_app().await;
+    give_test_user_an_email(&app).await;
+
+    // Act
+    let link = request_reset_link(&app).await;
+
+    // Assert
+    assert_eq!(link.path(), "/login/reset");
+    assert!(get_forgot_html(&app).await.contains(SAME_FOR_EVERYONE));
+}
+
+#[tokio::test]
+async fn unknown_users_get_the_same_response_and_no_email() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = post_forgot(&app, "not-a-user").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/forgot");
+    assert!(get_forgot_html(&app).await.contains(SAME_FOR_EVERYONE));
+}
+
+#[tokio::test]
+async fn a_user_can_log_in_with_the_password_they_reset() {
+    // Arrange
+    let app = spawn_app().await;
+    give_test_user_an_email(&app).await;
+    let link = request_reset_link(&app).await;
+    let new_password = "a-brand-new-password";
+
+    // Act - Part 1 - Follow the link
+    let response = app.api_client.get(link.clone()).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+
+    // Act - Part 2 - Reset the password
+    let response = post_reset(&app, &link, new_password).await;
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Your password has been reset: you can now log in.</i></p>"));
+
+    // Act - Part 3 - Log in
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": new_password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // Act - Part 4 - The link cannot be used twice
+    let response = post_reset(&app, &link, "yet-another-password").await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn passwords_must_match_to_be_reset() {
+    // Arrange
+    let app = spawn_app().await;
+    give_test_user_an_email(&app).await;
+    let link = request_reset_link(&app).await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(link.clone())
+        .form(
+            &app.with_csrf_token(&serde_json::json!({
+                "new_password": "a-brand-new-password",
+                "new_password_check": "another-password",
+            }))
+            .await,
+        )
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_is_redirect_to(
+        &response,
+        &format!("/login/reset?{}", link.query().unwrap()),
+    );
+    // The link can still be used
+    let response = post_reset(&app, &link, "a-brand-new-password").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn resetting_a_password_ends_all_existing_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    give_test_user_an_email(&app).await;
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await;
+    let response = app.get_admin_dashboard().await;
+    assert_eq!(response.status().as_u16(), 200);
+    let link = request_reset_link(&app).await;
+
+    // Act - from another browser
+    let other_client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .build()
+        .unwrap();
+    let reset_page = other_client
+        .get(link.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let response = other_client
+        .post(link)
+        .form(&serde_json::json!({
+            "new_password": "a-brand-new-password",
+            "new_password_check": "a-brand-new-password",
+            "csrf_token": extract_csrf_token(&reset_page),
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+
+    // Assert
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn expired_reset_links_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    give_test_user_an_email(&app).await;
+    let link = request_reset_link(&app).await;
+    sqlx::query!("UPDATE password_reset_tokens SET expires_at = now() - interval '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let get_response = app.api_client.get(link.clone()).send().await.unwrap();
+    let post_response = post_reset(&app, &link, "a-brand-new-password").await;
+
+    // Assert
+    assert_eq!(get_response.status().as_u16(), 400);
+    assert_eq!(post_response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn reset_requests_are_rate_limited() {
+    // Arrange
+    let app = spawn_app().await;
+    give_test_user_an_email(&app).await;
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    // Act
+    for _ in 0..5 {
+        let response = post_forgot(&app, &app.test_user.username).await;
+        assert_is_redirect_to(&response, "/login/forgot");
+    }
+
+    // Assert
+    mock_guard.wait_until_satisfied().await;
+    let n_tokens = sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM password_reset_tokens"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_tokens, 3);
+}
+
+#[tokio::test]
+async fn a_reset_password_must_follow_the_password_policy() {
+    // Arrange
+    let app = spawn_app().await;
+    give_test_user_an_email(&app).await;
+    let link = request_reset_link(&app).await;
+
+    // Act
+    let response = post_reset(&app, &link, "correct horse battery staple").await;
+
+    // Assert
+    assert_is_redirect_to(
+        &response,
+        &format!("/login/reset?{}", link.query().unwrap()),
+    );
+    let html_page = app
+        .api_client
+        .get(link.clone())
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(
+        html_page
+            .contains("<p><i>This password appeared in a data breach: choose another one.</i></p>")
+    );
+}
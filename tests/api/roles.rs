@@ -0,0 +1,236 @@
+//! tests/api/roles.rs
+use crate::helpers::{TestApp, TestUser, assert_is_redirect_to, spawn_app};
+
+async fn login_as(app: &TestApp, user: &TestUser) {
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": user.username,
+            "password": user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+async fn get(app: &TestApp, path: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}{}", &app.address, path))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn requeue_body() -> serde_json::Value {
+    serde_json::json!({
+        "newsletter_issue_id": uuid::Uuid::new_v4().to_string(),
+        "subscriber_email": "ursula@example.com"
+    })
+}
+
+async fn publish_as(app: &TestApp, user: &TestUser) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/newsletters", &app.address))
+        .basic_auth(&user.username, Some(&user.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"text": "text", "html": "<p>html</p>"}
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+fn assert_is_forbidden_page(status: u16, html_page: &str) {
+    assert_eq!(status, 403);
+    assert!(html_page.contains("You are not allowed to do this"));
+}
+
+#[tokio::test]
+async fn editors_cannot_manage_users() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = app.add_user_with_role("editor").await;
+    login_as(&app, &editor).await;
+
+    // Act
+    let response = get(&app, "/admin/users").await;
+
+    // Assert
+    let status = response.status().as_u16();
+    assert_is_forbidden_page(status, &response.text().await.unwrap());
+    let response = app
+        .post_admin_users(
+            "delete",
+            &serde_json::json!({"user_id": app.test_user.user_id}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn editors_and_analysts_manage_their_own_api_tokens() {
+    for role in ["editor", "analyst"] {
+        // Arrange
+        let app = spawn_app().await;
+        let user = app.add_user_with_role(role).await;
+        login_as(&app, &user).await;
+
+        // Act
+        let response = app
+            .post_create_api_token(&[("name", "nightly-report"), ("scope", "reports:read")])
+            .await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(app.get_api_tokens_html().await.contains("nightly-report"));
+    }
+}
+
+#[tokio::test]
+async fn read_only_users_cannot_create_api_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    let reader = app.add_user_with_role("read_only").await;
+    login_as(&app, &reader).await;
+
+    // Act
+    let response = app
+        .post_create_api_token(&[("name", "nightly-report"), ("scope", "reports:read")])
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn editors_manage_subscribers_and_publish() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = app.add_user_with_role("editor").await;
+    login_as(&app, &editor).await;
+
+    // Act
+    let response = app.post_requeue_failed_delivery(&requeue_body()).await;
+    let publish_response = publish_as(&app, &editor).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/deliveries");
+    assert_eq!(publish_response.status().as_u16(), 202);
+}
+
+#[tokio::test]
+async fn analysts_see_reports_but_cannot_act_on_them() {
+    // Arrange
+    let app = spawn_app().await;
+    let analyst = app.add_user_with_role("analyst").await;
+    login_as(&app, &analyst).await;
+
+    // Act
+    let reports = get(&app, "/admin/deliveries").await;
+    let requeue = app.post_requeue_failed_delivery(&requeue_body()).await;
+    let publish = publish_as(&app, &analyst).await;
+
+    // Assert
+    assert_eq!(reports.status().as_u16(), 200);
+    let status = requeue.status().as_u16();
+    assert_is_forbidden_page(status, &requeue.text().await.unwrap());
+    assert_eq!(publish.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn read_only_users_only_see_their_dashboard() {
+    // Arrange
+    let app = spawn_app().await;
+    let reader = app.add_user_with_role("read_only").await;
+    login_as(&app, &reader).await;
+
+    // Act
+    let html_page = app.get_admin_dashboard_html().await;
+    let reports = get(&app, "/admin/deliveries").await;
+    let password = get(&app, "/admin/password").await;
+
+    // Assert
+    assert!(html_page.contains("You are signed in as read_only."));
+    assert!(!html_page.contains(r#"href="/admin/deliveries""#));
+    assert!(!html_page.contains(r#"href="/admin/users""#));
+    assert_eq!(reports.status().as_u16(), 403);
+    assert_eq!(password.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_new_role_applies_from_the_next_request() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = app.add_user_with_role("editor").await;
+    login_as(&app, &app.test_user).await;
+
+    // Act
+    let response = app
+        .post_admin_users(
+            "role",
+            &serde_json::json!({"user_id": editor.user_id, "role": "read_only"}),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>The role of the user has been changed.</i></p>"));
+    app.post_logout().await;
+    login_as(&app, &editor).await;
+
+    // Assert
+    let response = get(&app, "/admin/deliveries").await;
+    assert_eq!(response.status().as_u16(), 403);
+}
+
+#[tokio::test]
+async fn the_last_active_owner_cannot_be_demoted() {
+    // Arrange
+    let app = spawn_app().await;
+    login_as(&app, &app.test_user).await;
+    let admin_id = sqlx::query!("SELECT user_id FROM users WHERE username = 'admin'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .user_id;
+    app.post_admin_users(
+        "role",
+        &serde_json::json!({"user_id": admin_id, "role": "editor"}),
+    )
+    .await;
+
+    // Act
+    let response = app
+        .post_admin_users(
+            "role",
+            &serde_json::json!({"user_id": app.test_user.user_id, "role": "editor"}),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>There must be at least one active owner left.</i></p>"));
+}
+
+#[tokio::test]
+async fn unknown_roles_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login_as(&app, &app.test_user).await;
+
+    // Act
+    let response = app
+        .post_admin_users(
+            "invite",
+            &serde_json::json!({
+                "username": "ursula",
+                "email": "ursula@example.com",
+                "role": "admin"
+            }),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>admin is not a valid role.</i></p>"));
+}
@@ -0,0 +1,192 @@
+//! tests/api/sessions.rs
+use crate::helpers::{TestApp, assert_is_redirect_to, extract_csrf_token, spawn_app};
+use uuid::Uuid;
+
+async fn login(app: &TestApp) {
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": &app.test_user.username,
+            "password": &app.test_user.password
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+/// Whether `client` still gets into the admin panel.
+async fn is_logged_in(app: &TestApp, client: &reqwest::Client) -> bool {
+    let response = client
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap();
+    response.status().as_u16() == 200
+}
+
+/// The ids of the sessions that can be revoked from `html_page`.
+fn revocable_session_ids(html_page: &str) -> Vec<String> {
+    html_page
+        .split(r#"name="session_id" value=""#)
+        .skip(1)
+        .map(|s| s[..s.find('"').unwrap()].to_owned())
+        .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_your_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(format!("{}/admin/sessions", &app.address))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn the_sessions_of_the_user_are_listed() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let other_browser = app
+        .login_from_another_browser(&app.test_user, "Other browser/1.0")
+        .await;
+    let editor = app.add_user_with_role("editor").await;
+    app.login_from_another_browser(&editor, "Editor browser/1.0")
+        .await;
+
+    // Act - Part 1 - List the sessions
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("<td>This session</td>"));
+    assert!(html_page.contains("<td>Other browser/1.0</td>"));
+    assert!(!html_page.contains("Editor browser/1.0"));
+    assert_eq!(revocable_session_ids(&html_page).len(), 1);
+
+    // Act - Part 2 - Log the other browser out
+    let dashboard = other_browser
+        .get(format!("{}/admin/dashboard", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    other_browser
+        .post(format!("{}/admin/logout", &app.address))
+        .form(&serde_json::json!({ "csrf_token": extract_csrf_token(&dashboard) }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let html_page = app.get_sessions_html().await;
+    assert!(!html_page.contains("Other browser/1.0"));
+    assert!(revocable_session_ids(&html_page).is_empty());
+}
+
+#[tokio::test]
+async fn a_revoked_session_is_logged_out() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let other_browser = app
+        .login_from_another_browser(&app.test_user, "Other browser/1.0")
+        .await;
+    assert!(is_logged_in(&app, &other_browser).await);
+    let session_ids = revocable_session_ids(&app.get_sessions_html().await);
+
+    // Act
+    let response = app.post_revoke_session(&session_ids[0]).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("<p><i>The session has been logged out.</i></p>"));
+    assert!(!html_page.contains("Other browser/1.0"));
+    assert!(!is_logged_in(&app, &other_browser).await);
+    assert!(is_logged_in(&app, &app.api_client).await);
+}
+
+#[tokio::test]
+async fn users_cannot_revoke_the_sessions_of_others() {
+    // Arrange
+    let app = spawn_app().await;
+    let editor = app.add_user_with_role("editor").await;
+    let editor_browser = app
+        .login_from_another_browser(&editor, "Editor browser/1.0")
+        .await;
+    let editor_sessions = editor_browser
+        .get(format!("{}/admin/sessions", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(revocable_session_ids(&editor_sessions).is_empty());
+    login(&app).await;
+
+    // Act - A session id that is not ours
+    let response = app.post_revoke_session(&Uuid::new_v4().to_string()).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("<p><i>The session was not found among your active ones.</i></p>"));
+    assert!(is_logged_in(&app, &editor_browser).await);
+}
+
+#[tokio::test]
+async fn logging_out_everywhere_else_keeps_the_current_session() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let laptop = app
+        .login_from_another_browser(&app.test_user, "Laptop/1.0")
+        .await;
+    let phone = app
+        .login_from_another_browser(&app.test_user, "Phone/1.0")
+        .await;
+
+    // Act
+    let response = app.post_revoke_other_sessions().await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/sessions");
+    let html_page = app.get_sessions_html().await;
+    assert!(html_page.contains("<p><i>2 other session(s) logged out.</i></p>"));
+    assert!(revocable_session_ids(&html_page).is_empty());
+    assert!(!is_logged_in(&app, &laptop).await);
+    assert!(!is_logged_in(&app, &phone).await);
+    assert!(is_logged_in(&app, &app.api_client).await);
+}
+
+#[tokio::test]
+async fn changing_the_password_logs_out_the_other_sessions() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let other_browser = app
+        .login_from_another_browser(&app.test_user, "Other browser/1.0")
+        .await;
+    let new_password = Uuid::new_v4().to_string();
+
+    // Act
+    let response = app
+        .post_change_password(&serde_json::json!({
+            "current_password": &app.test_user.password,
+            "new_password": &new_password,
+            "new_password_check": &new_password,
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/password");
+    assert!(!is_logged_in(&app, &other_browser).await);
+    assert!(is_logged_in(&app, &app.api_client).await);
+}
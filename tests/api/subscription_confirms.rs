@@ -1,10 +1,10 @@
 //! tests/api/subscriptions_confirm.rs
-use crate::helpers::spawn_app;
-use actix_web::body;
-use reqwest::Url;
+use crate::helpers::{create_unconfirmed_suscriber, spawn_app};
+use std::time::Duration;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
-use z2p::email_client;
+use z2p::domain::SubscriptionStatus;
+use z2p::subscription_purge_worker::{PurgeOutcome, purge_stale_subscriptions};
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_a_400() {
@@ -33,7 +33,7 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
         .await;
     app.post_subscriptions(body.into()).await;
     let email_req = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_req);
+    let confirmation_links = app.get_confirmation_links(email_req);
     // Act
     let response = reqwest::get(confirmation_links.html).await.unwrap();
     // Assert
@@ -52,7 +52,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
         .await;
     app.post_subscriptions(body.into()).await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     // Act
     reqwest::get(confirmation_links.html)
@@ -62,12 +62,223 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
         .unwrap();
 
     // Assert
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions",)
-        .fetch_one(&app.db_pool)
-        .await
-        .expect("Failed to fetch saved subscription.");
+    let saved = sqlx::query!(
+        r#"SELECT email, name, status AS "status: SubscriptionStatus" FROM subscriptions"#,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch saved subscription.");
 
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "le guin");
-    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.status, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn confirming_a_subscriber_records_the_transition() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    // Act
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    // Assert
+    let history = sqlx::query!(
+        r#"
+        SELECT
+            from_status AS "from_status: SubscriptionStatus",
+            to_status AS "to_status: SubscriptionStatus"
+        FROM subscription_status_history
+        ORDER BY id
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].from_status, None);
+    assert_eq!(history[0].to_status, SubscriptionStatus::Pending);
+    assert_eq!(history[1].from_status, Some(SubscriptionStatus::Pending));
+    assert_eq!(history[1].to_status, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn a_confirmation_link_can_only_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_suscriber(&app).await;
+    reqwest::get(confirmation_links.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link has already been used."));
+}
+
+#[tokio::test]
+async fn an_expired_confirmation_link_is_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_suscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+    // Assert
+    assert_eq!(response.status().as_u16(), 410);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("This confirmation link has expired."));
+    assert!(html_page.contains(r#"href="/subscriptions/resend""#));
+    let saved = sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Pending);
+}
+
+#[tokio::test]
+async fn a_pending_subscriber_can_get_a_new_confirmation_link() {
+    // Arrange
+    let app = spawn_app().await;
+    let first_links = create_unconfirmed_suscriber(&app).await;
+    sqlx::query!("UPDATE subscription_tokens SET expires_at = now() - interval '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    // Act - Part 1 - Ask for a new link
+    let response = app
+        .post_resend_confirmation(&serde_json::json!({"email": "ursula_le_guin@gmail.com"}))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    // Act - Part 2 - Follow it
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let new_links = app.get_confirmation_links(&email_request);
+    assert_ne!(first_links.html, new_links.html);
+    reqwest::get(new_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    // Assert
+    let saved = sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn asking_for_a_new_link_for_an_unknown_email_sends_nothing() {
+    // Arrange
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let response = app
+        .post_resend_confirmation(&serde_json::json!({"email": "nobody@gmail.com"}))
+        .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn never_confirmed_subscribers_are_purged_after_the_retention_period() {
+    // Arrange
+    let app = spawn_app().await;
+    create_unconfirmed_suscriber(&app).await;
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = now() - interval '2 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!(
+        "UPDATE subscription_tokens
+        SET created_at = now() - interval '2 hours', expires_at = now() - interval '1 hour'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    // Act
+    let outcome = purge_stale_subscriptions(&app.db_pool, Duration::from_secs(3600))
+        .await
+        .unwrap();
+    // Assert
+    assert_eq!(
+        outcome,
+        PurgeOutcome {
+            n_subscribers: 1,
+            n_tokens: 0
+        }
+    );
+    let n_subscribers = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_subscribers, 0);
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_not_purged() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_suscriber(&app).await;
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = now() - interval '2 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query!("UPDATE subscription_tokens SET created_at = now() - interval '2 hours'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    // Act
+    let outcome = purge_stale_subscriptions(&app.db_pool, Duration::from_secs(3600))
+        .await
+        .unwrap();
+    // Assert - only the used token goes away
+    assert_eq!(
+        outcome,
+        PurgeOutcome {
+            n_subscribers: 0,
+            n_tokens: 1
+        }
+    );
 }
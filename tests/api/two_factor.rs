@@ -0,0 +1,365 @@
+//! tests/api/two_factor.rs
+use crate::helpers::{TestApp, assert_is_redirect_to, spawn_app, spawn_app_with};
+use chrono::{TimeDelta, Utc};
+use z2p::authentication::TotpSecret;
+
+fn text_between<'a>(html_page: &'a str, start: &str, end: &str) -> &'a str {
+    let from = html_page.find(start).unwrap() + start.len();
+    let to = from + html_page[from..].find(end).unwrap();
+    &html_page[from..to]
+}
+
+async fn login(app: &TestApp) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": &app.test_user.username,
+        "password": &app.test_user.password
+    }))
+    .await
+}
+
+/// Enable two-factor authentication for the test user, who stays logged in.
+/// Returns the secret and the recovery codes.
+async fn enable_two_factor(app: &TestApp) -> (TotpSecret, Vec<String>) {
+    login(app).await;
+    let html_page = app.get_two_factor_html().await;
+    let secret =
+        TotpSecret::parse_base32(text_between(&html_page, r#"<code id="secret">"#, "</code>"))
+            .unwrap();
+    let response = app
+        .post_two_factor("/admin/two-factor", &secret.code_at(Utc::now()))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    let recovery_codes = text_between(&html_page, r#"<ul id="recovery-codes">"#, "</ul>")
+        .split("<li><code>")
+        .skip(1)
+        .map(|li| li.split("</code>").next().unwrap().to_owned())
+        .collect();
+    (secret, recovery_codes)
+}
+
+/// A code the server accepts without clashing with the one used to enable
+/// two-factor authentication.
+fn next_code(secret: &TotpSecret) -> String {
+    secret.code_at(Utc::now() + TimeDelta::seconds(30))
+}
+
+#[tokio::test]
+async fn users_with_two_factor_authentication_need_a_code_to_log_in() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, recovery_codes) = enable_two_factor(&app).await;
+    assert_eq!(recovery_codes.len(), 10);
+    app.post_logout().await;
+
+    // Act - Part 1 - Password only
+    let response = login(&app).await;
+    assert_is_redirect_to(&response, "/login/two-factor");
+    // Partially authenticated users cannot reach the admin panel
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+
+    // Act - Part 2 - Wrong code
+    let response = app.post_two_factor("/login/two-factor", "000000").await;
+    assert_is_redirect_to(&response, "/login/two-factor");
+    let html_page = app
+        .api_client
+        .get(format!("{}/login/two-factor", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("<p><i>Invalid authentication code.</i></p>"));
+
+    // Act - Part 3 - Right code
+    let response = app
+        .post_two_factor("/login/two-factor", &next_code(&secret))
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    let html_page = app.get_admin_dashboard_html().await;
+    assert!(html_page.contains(&format!("Welcome {}", app.test_user.username)));
+}
+
+#[tokio::test]
+async fn guessing_the_code_locks_the_user_out() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttling.max_failures_per_username = 3;
+        c.login_throttling.base_delay_milliseconds = 0;
+        c.login_throttling.max_delay_milliseconds = 0;
+    })
+    .await;
+    let (secret, _) = enable_two_factor(&app).await;
+    app.post_logout().await;
+    login(&app).await;
+
+    // Act - Part 1 - Wrong codes
+    for _ in 0..2 {
+        let response = app.post_two_factor("/login/two-factor", "000000").await;
+        assert_is_redirect_to(&response, "/login/two-factor");
+    }
+    let response = app.post_two_factor("/login/two-factor", "000000").await;
+    assert_is_redirect_to(&response, "/login");
+    assert!(
+        app.get_login_html()
+            .await
+            .contains("<p><i>Too many failed login attempts: try again in 15 minute(s).</i></p>")
+    );
+
+    // Act - Part 2 - The right code is not enough anymore
+    let response = app
+        .post_two_factor("/login/two-factor", &next_code(&secret))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn entering_the_password_again_does_not_reset_the_count_of_wrong_codes() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttling.max_failures_per_username = 3;
+        c.login_throttling.base_delay_milliseconds = 0;
+        c.login_throttling.max_delay_milliseconds = 0;
+    })
+    .await;
+    enable_two_factor(&app).await;
+    app.post_logout().await;
+    login(&app).await;
+    for _ in 0..2 {
+        let response = app.post_two_factor("/login/two-factor", "000000").await;
+        assert_is_redirect_to(&response, "/login/two-factor");
+    }
+
+    // Act
+    let response = login(&app).await;
+    assert_is_redirect_to(&response, "/login/two-factor");
+    let response = app.post_two_factor("/login/two-factor", "000000").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert!(
+        app.get_login_html()
+            .await
+            .contains("<p><i>Too many failed login attempts: try again in 15 minute(s).</i></p>")
+    );
+}
+
+#[tokio::test]
+async fn totp_codes_cannot_be_replayed() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = enable_two_factor(&app).await;
+    app.post_logout().await;
+    login(&app).await;
+    let code = next_code(&secret);
+    let response = app.post_two_factor("/login/two-factor", &code).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    // Act
+    login(&app).await;
+    let response = app.post_two_factor("/login/two-factor", &code).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login/two-factor");
+}
+
+#[tokio::test]
+async fn recovery_codes_can_be_used_once() {
+    // Arrange
+    let app = spawn_app().await;
+    let (_, recovery_codes) = enable_two_factor(&app).await;
+    app.post_logout().await;
+
+    // Act - Part 1 - First use
+    login(&app).await;
+    let response = app
+        .post_two_factor("/login/two-factor", &recovery_codes[0].to_uppercase())
+        .await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+    app.post_logout().await;
+
+    // Act - Part 2 - Second use
+    login(&app).await;
+    let response = app
+        .post_two_factor("/login/two-factor", &recovery_codes[0])
+        .await;
+    assert_is_redirect_to(&response, "/login/two-factor");
+}
+
+#[tokio::test]
+async fn a_wrong_code_does_not_enable_two_factor_authentication() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app).await;
+    let html_page = app.get_two_factor_html().await;
+    let secret = text_between(&html_page, r#"<code id="secret">"#, "</code>").to_owned();
+
+    // Act
+    let response = app.post_two_factor("/admin/two-factor", "000000").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("<p><i>Invalid authentication code.</i></p>"));
+    // The same secret is offered until it is confirmed
+    assert!(html_page.contains(&secret));
+    app.post_logout().await;
+    let response = login(&app).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn totp_secrets_are_encrypted_at_rest() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = enable_two_factor(&app).await;
+
+    // Act
+    let stored = sqlx::query!(
+        "SELECT totp_secret FROM users WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .totp_secret
+    .unwrap();
+
+    // Assert
+    let secret = data_encoding::BASE32_NOPAD
+        .decode(secret.to_base32().as_bytes())
+        .unwrap();
+    assert!(!stored.windows(secret.len()).any(|w| w == secret.as_slice()));
+}
+
+#[tokio::test]
+async fn two_factor_authentication_can_be_disabled_with_a_code() {
+    // Arrange
+    let app = spawn_app().await;
+    let (secret, _) = enable_two_factor(&app).await;
+
+    // Act
+    let response = app
+        .post_two_factor("/admin/two-factor/disable", &next_code(&secret))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/two-factor");
+    let html_page = app.get_two_factor_html().await;
+    assert!(html_page.contains("<p><i>Two-factor authentication has been disabled.</i></p>"));
+    app.post_logout().await;
+    let response = login(&app).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn an_owner_can_reset_the_two_factor_authentication_of_another_user() {
+    // Arrange
+    let app = spawn_app().await;
+    enable_two_factor(&app).await;
+    app.post_logout().await;
+    let owner = app.add_user_with_role("owner").await;
+    app.post_login(&serde_json::json!({
+        "username": &owner.username,
+        "password": &owner.password
+    }))
+    .await;
+
+    // Act
+    let response = app
+        .post_admin_users(
+            "reset-two-factor",
+            &serde_json::json!({"user_id": app.test_user.user_id}),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(
+        html_page
+            .contains("<p><i>The two-factor authentication of the user has been reset.</i></p>")
+    );
+    app.post_logout().await;
+    let response = login(&app).await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+}
+
+#[tokio::test]
+async fn the_second_step_requires_a_password_first() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_two_factor("/login/two-factor", "000000").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn users_with_two_factor_authentication_cannot_publish_with_basic_auth() {
+    // Arrange
+    let app = spawn_app().await;
+    enable_two_factor(&app).await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+
+    // Act
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let issues = sqlx::query_scalar!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(issues.is_empty());
+}
+
+#[tokio::test]
+async fn refused_basic_auth_attempts_count_towards_the_lockout() {
+    // Arrange
+    let app = spawn_app_with(|c| {
+        c.login_throttling.max_failures_per_username = 3;
+        c.login_throttling.base_delay_milliseconds = 0;
+        c.login_throttling.max_delay_milliseconds = 0;
+    })
+    .await;
+    enable_two_factor(&app).await;
+    app.post_logout().await;
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    for _ in 0..3 {
+        let response = app.post_newsletters(newsletter_request_body.clone()).await;
+        assert_eq!(response.status().as_u16(), 401);
+    }
+
+    // Act
+    let response = login(&app).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+    assert!(
+        app.get_login_html()
+            .await
+            .contains("<p><i>Too many failed login attempts: try again in 15 minute(s).</i></p>")
+    );
+}
@@ -0,0 +1,186 @@
+//! tests/api/unsubscribe.rs
+use crate::helpers::{
+    BatchAccepted, create_confirmed_subscriber, create_unconfirmed_suscriber, spawn_app,
+    unsubscribe_token,
+};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use z2p::domain::SubscriptionStatus;
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+#[tokio::test]
+async fn newsletter_issues_carry_an_unsubscribe_link_and_headers() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(BatchAccepted)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+    let message = &messages[0];
+    let link = format!(
+        "/subscriptions/unsubscribe?token={}",
+        unsubscribe_token("ursula_le_guin@gmail.com")
+    );
+    assert!(message["HtmlBody"].as_str().unwrap().contains(&link));
+    assert!(message["TextBody"].as_str().unwrap().contains(&link));
+    let headers = message["Headers"].as_array().unwrap();
+    assert!(
+        headers.iter().any(
+            |h| h["Name"] == "List-Unsubscribe" && h["Value"].as_str().unwrap().contains(&link)
+        )
+    );
+    assert!(headers.iter().any(
+        |h| h["Name"] == "List-Unsubscribe-Post" && h["Value"] == "List-Unsubscribe=One-Click"
+    ));
+}
+
+#[tokio::test]
+async fn following_the_unsubscribe_link_asks_for_confirmation() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = unsubscribe_token("ursula_le_guin@gmail.com");
+
+    let response = app.get_unsubscribe(&token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains(r#"method="post""#));
+    let saved = sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn one_click_unsubscribe_unsubscribes_the_subscriber() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = unsubscribe_token("ursula_le_guin@gmail.com");
+
+    let response = app.post_unsubscribe(&token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Unsubscribed);
+
+    // Unsubscribing twice is fine
+    let response = app.post_unsubscribe(&token).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_get_newsletters() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_unsubscribe(&unsubscribe_token("ursula_le_guin@gmail.com"))
+        .await;
+
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn unsubscribing_drops_the_deliveries_still_queued() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_newsletters(newsletter_request_body()).await;
+
+    app.post_unsubscribe(&unsubscribe_token("ursula_le_guin@gmail.com"))
+        .await;
+
+    // Assert
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(queued.is_empty());
+}
+
+#[tokio::test]
+async fn an_invalid_token_is_rejected_with_a_400() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let token = unsubscribe_token("ursula_le_guin@gmail.com");
+    let (email, _) = token.split_once('.').unwrap();
+    let test_cases = vec![
+        ("not-a-token".to_string(), "malformed token"),
+        (format!("{}.{}", email, "00".repeat(32)), "forged signature"),
+    ];
+
+    for (token, description) in test_cases {
+        let response = app.get_unsubscribe(&token).await;
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "GET did not fail with a 400 for a {}.",
+            description
+        );
+        let response = app.post_unsubscribe(&token).await;
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "POST did not fail with a 400 for a {}.",
+            description
+        );
+    }
+    let saved = sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn an_old_confirmation_link_does_not_resubscribe_an_unsubscribed_subscriber() {
+    let app = spawn_app().await;
+    let confirmation_links = create_unconfirmed_suscriber(&app).await;
+    app.post_unsubscribe(&unsubscribe_token("ursula_le_guin@gmail.com"))
+        .await;
+
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    let saved = sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, SubscriptionStatus::Unsubscribed);
+}
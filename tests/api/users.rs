@@ -0,0 +1,342 @@
+//! tests/api/users.rs
+use crate::helpers::{TestApp, assert_is_redirect_to, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use z2p::authentication::UserStatus;
+
+async fn login(app: &TestApp, username: &str, password: &str) -> reqwest::Response {
+    app.post_login(&serde_json::json!({
+        "username": username,
+        "password": password
+    }))
+    .await
+}
+
+async fn seeded_admin_id(app: &TestApp) -> uuid::Uuid {
+    sqlx::query!("SELECT user_id FROM users WHERE username = 'admin'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .user_id
+}
+
+async fn user_status(app: &TestApp, user_id: uuid::Uuid) -> UserStatus {
+    sqlx::query!(
+        r#"SELECT status AS "status: UserStatus" FROM users WHERE user_id = $1"#,
+        user_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+/// Invite `ursula` and return the link found in the invitation email.
+async fn invite_ursula(app: &TestApp) -> reqwest::Url {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let response = app
+        .post_admin_users(
+            "invite",
+            &serde_json::json!({"username": "ursula", "email": "ursula@example.com", "role": "editor"}),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    app.get_confirmation_links(&email_request).html
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_users() {
+    // Arrange
+    let app = spawn_app().await;
+    // Act
+    let response = app
+        .post_admin_users(
+            "invite",
+            &serde_json::json!({"username": "ursula", "email": "ursula@example.com", "role": "editor"}),
+        )
+        .await;
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn an_invited_user_chooses_a_password_and_logs_in() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user.username, &app.test_user.password).await;
+
+    // Act - Part 1 - Invite
+    let invitation_link = invite_ursula(&app).await;
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>An invitation has been sent to ursula@example.com.</i></p>"));
+    assert!(html_page.contains("<td>invited</td>"));
+    app.post_logout().await;
+
+    // Act - Part 2 - Follow the link
+    let response = app
+        .api_client
+        .get(invitation_link.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Welcome ursula!"));
+
+    // Act - Part 3 - Choose a password
+    let response = app
+        .api_client
+        .post(invitation_link.clone())
+        .form(
+            &app.with_csrf_token(&serde_json::json!({
+                "password": "a-brand-new-password",
+                "password_check": "a-brand-new-password",
+            }))
+            .await,
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/login");
+    let html_page = app.get_login_html().await;
+    assert!(html_page.contains("<p><i>Your account is ready: you can now log in.</i></p>"));
+
+    // Act - Part 4 - Log in
+    let response = login(&app, "ursula", "a-brand-new-password").await;
+    assert_is_redirect_to(&response, "/admin/dashboard");
+
+    // The link cannot be used twice
+    let response = app
+        .api_client
+        .post(invitation_link)
+        .form(
+            &app.with_csrf_token(&serde_json::json!({
+                "password": "another-password",
+                "password_check": "another-password",
+            }))
+            .await,
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn an_invitation_cannot_be_accepted_without_the_csrf_token() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user.username, &app.test_user.password).await;
+    let invitation_link = invite_ursula(&app).await;
+    app.post_logout().await;
+    // Act
+    let response = app
+        .api_client
+        .post(invitation_link)
+        .form(&serde_json::json!({
+            "password": "a-brand-new-password",
+            "password_check": "a-brand-new-password",
+        }))
+        .send()
+        .await
+        .unwrap();
+    // Assert
+    assert_eq!(response.status().as_u16(), 403);
+    let response = login(&app, "ursula", "a-brand-new-password").await;
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn an_invited_user_cannot_log_in_before_choosing_a_password() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user.username, &app.test_user.password).await;
+    invite_ursula(&app).await;
+    app.post_logout().await;
+    // Act
+    let response = login(&app, "ursula", "").await;
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn passwords_must_match_when_accepting_an_invitation() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user.username, &app.test_user.password).await;
+    let invitation_link = invite_ursula(&app).await;
+    // Act
+    let response = app
+        .api_client
+        .post(invitation_link.clone())
+        .form(
+            &app.with_csrf_token(&serde_json::json!({
+                "password": "a-brand-new-password",
+                "password_check": "another-password",
+            }))
+            .await,
+        )
+        .send()
+        .await
+        .unwrap();
+    // Assert
+    assert_is_redirect_to(
+        &response,
+        &format!("/invitations/accept?{}", invitation_link.query().unwrap()),
+    );
+    let html_page = app
+        .api_client
+        .get(invitation_link)
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains(
+        "<p><i>You entered two different passwords - the field values must match.</i></p>"
+    ));
+}
+
+#[tokio::test]
+async fn invalid_invitations_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user.username, &app.test_user.password).await;
+    let test_cases = vec![
+        (
+            serde_json::json!({"username": app.test_user.username, "email": "ursula@example.com", "role": "editor"}),
+            "The username or the email is already taken.",
+        ),
+        (
+            serde_json::json!({"username": "", "email": "ursula@example.com", "role": "editor"}),
+            "The username must be between 1 and 64 characters long.",
+        ),
+        (
+            serde_json::json!({"username": "ursula", "email": "not-an-email", "role": "editor"}),
+            "not-an-email is not a valid subscriber email.",
+        ),
+    ];
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    for (body, error_message) in test_cases {
+        // Act
+        let response = app.post_admin_users("invite", &body).await;
+        // Assert
+        assert_is_redirect_to(&response, "/admin/users");
+        let html_page = app.get_users_html().await;
+        assert!(
+            html_page.contains(&format!("<p><i>{}</i></p>", error_message)),
+            "The invitation did not fail with `{}`.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_disabled_user_is_logged_out_and_cannot_use_their_api_tokens() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user.username, &app.test_user.password).await;
+    let response = app
+        .post_create_api_token(&[("name", "ci"), ("scope", "newsletters:publish")])
+        .await;
+    let html_page = response.text().await.unwrap();
+    let start = html_page.find(r#"<pre id="token">"#).unwrap() + r#"<pre id="token">"#.len();
+    let end = start + html_page[start..].find("</pre>").unwrap();
+    let token = html_page[start..end].to_owned();
+
+    // Act - Part 1 - Disable themselves, the seeded admin is still active
+    let response = app
+        .post_admin_users(
+            "disable",
+            &serde_json::json!({"user_id": app.test_user.user_id}),
+        )
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    assert_eq!(
+        user_status(&app, app.test_user.user_id).await,
+        UserStatus::Disabled
+    );
+
+    // Act - Part 2 - Try to carry on
+    let response = app.get_admin_dashboard().await;
+    assert_is_redirect_to(&response, "/login");
+    let response = login(&app, &app.test_user.username, &app.test_user.password).await;
+    assert_is_redirect_to(&response, "/login");
+    let response = app
+        .post_newsletters_with_token(
+            &token,
+            serde_json::json!({
+                "title": "Newsletter title",
+                "content": {"text": "text", "html": "<p>html</p>"}
+            }),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_last_active_admin_cannot_be_disabled_nor_deleted() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user.username, &app.test_user.password).await;
+    let admin_id = seeded_admin_id(&app).await;
+    let response = app
+        .post_admin_users("delete", &serde_json::json!({"user_id": admin_id}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/users");
+    let html_page = app.get_users_html().await;
+    assert!(html_page.contains("<p><i>The user has been deleted.</i></p>"));
+
+    for action in ["disable", "delete"] {
+        // Act
+        let response = app
+            .post_admin_users(
+                action,
+                &serde_json::json!({"user_id": app.test_user.user_id}),
+            )
+            .await;
+        // Assert
+        assert_is_redirect_to(&response, "/admin/users");
+        let html_page = app.get_users_html().await;
+        assert!(html_page.contains("<p><i>There must be at least one active owner left.</i></p>"));
+    }
+    assert_eq!(
+        user_status(&app, app.test_user.user_id).await,
+        UserStatus::Active
+    );
+}
+
+#[tokio::test]
+async fn a_disabled_user_can_be_enabled_again() {
+    // Arrange
+    let app = spawn_app().await;
+    login(&app, &app.test_user.username, &app.test_user.password).await;
+    let admin_id = seeded_admin_id(&app).await;
+    app.post_admin_users("disable", &serde_json::json!({"user_id": admin_id}))
+        .await;
+    assert_eq!(user_status(&app, admin_id).await, UserStatus::Disabled);
+    // Act
+    let response = app
+        .post_admin_users("enable", &serde_json::json!({"user_id": admin_id}))
+        .await;
+    // Assert
+    assert_is_redirect_to(&response, "/admin/users");
+    assert_eq!(user_status(&app, admin_id).await, UserStatus::Active);
+}
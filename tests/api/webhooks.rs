@@ -0,0 +1,205 @@
+//! tests/api/webhooks.rs
+use crate::helpers::{create_confirmed_subscriber, spawn_app};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use z2p::domain::{SubscriptionStatus, SuppressionReason};
+
+const HARD_BOUNCE: &str = include_str!("../fixtures/postmark/hard_bounce.json");
+const SOFT_BOUNCE: &str = include_str!("../fixtures/postmark/soft_bounce.json");
+const SPAM_COMPLAINT: &str = include_str!("../fixtures/postmark/spam_complaint.json");
+const DELIVERY: &str = include_str!("../fixtures/postmark/delivery.json");
+
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+async fn saved_status(app: &crate::helpers::TestApp) -> SubscriptionStatus {
+    sqlx::query!(r#"SELECT status AS "status: SubscriptionStatus" FROM subscriptions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+#[tokio::test]
+async fn requests_without_valid_credentials_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let test_cases = vec![
+        (None, "no credentials"),
+        (Some(("postmark", "wrong-password")), "a wrong password"),
+        (Some(("someone", "my-webhook-secret")), "a wrong username"),
+    ];
+
+    for (credentials, description) in test_cases {
+        // Act
+        let mut request = app
+            .api_client
+            .post(format!("{}/webhooks/email-events", &app.address))
+            .header("Content-Type", "application/json")
+            .body(HARD_BOUNCE);
+        if let Some((username, password)) = credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+        let response = request.send().await.unwrap();
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            401,
+            "The webhook did not fail with 401 Unauthorized with {}.",
+            description
+        );
+        assert_eq!(
+            r#"Basic realm="webhooks""#,
+            response.headers()["WWW-Authenticate"]
+        );
+    }
+    assert_eq!(saved_status(&app).await, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn a_malformed_payload_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    // Act
+    let response = app.post_email_event(r#"{"RecordType": "Bounce"}"#).await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn a_hard_bounce_marks_the_subscriber_as_bounced_and_suppresses_the_address() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    // Act
+    let response = app.post_email_event(HARD_BOUNCE).await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(saved_status(&app).await, SubscriptionStatus::Bounced);
+    let suppression = sqlx::query!(
+        r#"SELECT email, reason AS "reason: SuppressionReason" FROM email_suppressions"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(suppression.email, "ursula_le_guin@gmail.com");
+    assert_eq!(suppression.reason, SuppressionReason::HardBounce);
+
+    // Receiving the same event twice is fine
+    let response = app.post_email_event(HARD_BOUNCE).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_bounced_subscriber_who_signs_up_again_stays_bounced() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_email_event(HARD_BOUNCE).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(saved_status(&app).await, SubscriptionStatus::Bounced);
+}
+
+#[tokio::test]
+async fn a_spam_complaint_marks_the_subscriber_as_complained_and_drops_queued_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_newsletters(newsletter_request_body()).await;
+    // Act
+    let response = app.post_email_event(SPAM_COMPLAINT).await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(saved_status(&app).await, SubscriptionStatus::Complained);
+    let queued = sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(queued.is_empty());
+}
+
+#[tokio::test]
+async fn soft_bounces_and_deliveries_are_acknowledged_without_side_effects() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    for payload in [SOFT_BOUNCE, DELIVERY] {
+        // Act
+        let response = app.post_email_event(payload).await;
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+    }
+    assert_eq!(saved_status(&app).await, SubscriptionStatus::Confirmed);
+    let n_suppressions = sqlx::query!(r#"SELECT count(*) AS "count!" FROM email_suppressions"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    assert_eq!(n_suppressions, 0);
+}
+
+#[tokio::test]
+async fn suppressed_addresses_do_not_get_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    // A bounce for an email that is not on the list, e.g. an admin notification
+    sqlx::query!("UPDATE subscriptions SET email = 'ursula@example.com'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.post_email_event(HARD_BOUNCE).await;
+    sqlx::query!("UPDATE subscriptions SET email = 'ursula_le_guin@gmail.com'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved_status(&app).await, SubscriptionStatus::Confirmed);
+
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    app.post_newsletters(newsletter_request_body()).await;
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn suppressed_addresses_do_not_get_confirmation_emails() {
+    // Arrange
+    let app = spawn_app().await;
+    app.post_email_event(HARD_BOUNCE).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}